@@ -0,0 +1,466 @@
+//! The password handshake, clients prove that they know the password without sending it
+//!
+//! The handshake works like SCRAM. The client key is an HMAC keyed with the Argon2 hash of the password, and the server only stores the SHA-256 digest of it, the stored key.
+//! The server hands out the salt and parameters of the hash along with a random nonce, the client derives the client key from the password
+//! and answers with it XORed with an HMAC of the nonce keyed with the stored key. The server takes the HMAC back off and checks the digest of what is left,
+//! so what it stores is not enough to log in with.
+//! The session token it gets in return is sent in the authorization metadata of every other request, and refreshed before it expires.
+//!
+//! Scripts can send an API key minted on the server instead, which skips the handshake.
+//! The token of a share link also skips it, but it can only download the file the link has been made for.
+
+use anyhow::Context;
+use argon2::{
+    password_hash::{
+        rand_core::{OsRng, RngCore},
+        Output, PasswordHash, PasswordHasher, SaltString,
+    },
+    Algorithm, Argon2, Params, Version,
+};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+use std::{
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tonic::{
+    codegen::InterceptedService, metadata::MetadataMap, service::Interceptor, transport::Channel,
+    Request, Status,
+};
+
+use crate::{
+    error::ServiceError,
+    messages::{
+        auth_client::AuthClient, serving_client::ServingClient, ChallengeRequest, LoginReply,
+        LoginRequest, LogoutRequest, RefreshRequest,
+    },
+};
+
+///The amount of random bytes in a nonce or a session token
+pub const NONCE_LENGTH: usize = 32;
+
+///The metadata key the session token is sent in
+pub const AUTHORIZATION: &str = "authorization";
+
+///The metadata key API keys are sent in, requests with one do not need to log in
+pub const API_KEY: &str = "x-api-key";
+
+///The metadata key the tokens of share links are sent in
+pub const SHARE_LINK: &str = "x-share-link";
+
+type HmacSha256 = Hmac<Sha256>;
+
+///The label the client key is derived from the hash of the password with
+const CLIENT_KEY_LABEL: &[u8] = b"Client Key";
+
+///Hashes the password with a random salt, the returned PHC string is what the server stores
+///
+///Its output is the stored key rather than the Argon2 hash, so it cannot be used in place of the password
+pub fn hash_password(password: &str) -> anyhow::Result<String> {
+    let salt = SaltString::generate(&mut OsRng);
+
+    let mut hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+
+    let salted = hash.hash.context("Argon2 has not produced an output")?;
+
+    hash.hash = Some(Output::new(&stored_key_of(&client_key_of(
+        salted.as_bytes(),
+    )))?);
+
+    Ok(hash.to_string())
+}
+
+///Checks if the stored hash is the one of an empty password, so the server can warn about it
+pub fn is_empty_password(hash: &str) -> anyhow::Result<bool> {
+    is_password(hash, "")
+}
+
+///Checks if the stored hash is the one of the password, so the server can refuse well known passwords
+pub fn is_password(hash: &str, password: &str) -> anyhow::Result<bool> {
+    let stored = stored_key(hash)?;
+
+    Ok(constant_time_eq(
+        &stored_key_of(&client_key(password, hash)?),
+        &stored,
+    ))
+}
+
+///The algorithm, parameters and salt of the stored hash without the hash itself, this is what clients get in the challenge
+pub fn hash_settings(hash: &str) -> anyhow::Result<String> {
+    let mut hash = PasswordHash::new(hash)?;
+
+    hash.hash = None;
+
+    Ok(hash.to_string())
+}
+
+///Settings which look like the ones of a real user, they are sent for users which do not exist so the challenges do not reveal which ones do
+///
+///The salt is taken from the seed, so the same user always gets the same settings
+pub fn decoy_settings(seed: &[u8]) -> anyhow::Result<String> {
+    let salt = SaltString::encode_b64(&seed[..seed.len().min(16)])?;
+    let params = Params::default();
+
+    Ok(format!(
+        "${}$v={}$m={},t={},p={}${}",
+        Algorithm::default().ident(),
+        Version::default() as u32,
+        params.m_cost(),
+        params.t_cost(),
+        params.p_cost(),
+        salt.as_str()
+    ))
+}
+
+///The stored key, which is the output of the stored hash
+pub fn stored_key(hash: &str) -> anyhow::Result<Vec<u8>> {
+    let hash = PasswordHash::new(hash)?;
+
+    hash.hash
+        .map(|output| output.as_bytes().to_vec())
+        .context("The password hash has no output")
+}
+
+///Hashes the password like the server has, with the settings from the challenge, and derives the client key from it
+pub fn client_key(password: &str, settings: &str) -> anyhow::Result<Vec<u8>> {
+    let settings = PasswordHash::new(settings)?;
+    let salt = settings.salt.context("The password hash has no salt")?;
+
+    let hash = Argon2::default().hash_password_customized(
+        password.as_bytes(),
+        Some(settings.algorithm),
+        settings.version,
+        Params::try_from(&settings)?,
+        salt,
+    )?;
+
+    hash.hash
+        .map(|output| client_key_of(output.as_bytes()))
+        .context("Argon2 has not produced an output")
+}
+
+fn client_key_of(salted: &[u8]) -> Vec<u8> {
+    proof(salted, CLIENT_KEY_LABEL)
+}
+
+fn stored_key_of(client_key: &[u8]) -> Vec<u8> {
+    Sha256::digest(client_key).to_vec()
+}
+
+///Answers the nonce of a challenge with the client key, hidden by an HMAC of the nonce which only the stored key can make
+pub fn client_proof(client_key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let signature = proof(&stored_key_of(client_key), nonce);
+
+    xor(client_key, &signature)
+}
+
+///Checks the answer to a challenge against the stored key, the digest is compared in constant time
+pub fn verify_client_proof(stored_key: &[u8], nonce: &[u8], client_proof: &[u8]) -> bool {
+    let signature = proof(stored_key, nonce);
+
+    client_proof.len() == signature.len()
+        && constant_time_eq(&stored_key_of(&xor(client_proof, &signature)), stored_key)
+}
+
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    a.iter().zip(b).map(|(a, b)| a ^ b).collect()
+}
+
+///Compares without returning early, so the time it takes does not tell where the values differ
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+///An HMAC-SHA256 of the message, it is used to sign share links as well as in the handshake
+pub fn proof(key: &[u8], nonce: &[u8]) -> Vec<u8> {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+
+    mac.update(nonce);
+
+    mac.finalize().into_bytes().to_vec()
+}
+
+///Checks the answer to a nonce, the comparison takes the same time no matter where the proof differs
+pub fn verify_proof(key: &[u8], nonce: &[u8], proof: &[u8]) -> bool {
+    let mut mac = HmacSha256::new_from_slice(key).expect("HMAC accepts keys of any length");
+
+    mac.update(nonce);
+
+    mac.verify_slice(proof).is_ok()
+}
+
+///Random bytes for nonces and session tokens
+pub fn random_bytes() -> [u8; NONCE_LENGTH] {
+    let mut bytes = [0; NONCE_LENGTH];
+
+    OsRng.fill_bytes(&mut bytes);
+
+    bytes
+}
+
+///Reads the session token from the authorization metadata of a request
+pub fn bearer_token(metadata: &MetadataMap) -> Option<&str> {
+    metadata
+        .get(AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+}
+
+///Reads the API key from the metadata of a request
+pub fn api_key(metadata: &MetadataMap) -> Option<&str> {
+    metadata.get(API_KEY)?.to_str().ok()
+}
+
+///Reads the token of a share link from the metadata of a request
+pub fn share_link(metadata: &MetadataMap) -> Option<&str> {
+    metadata.get(SHARE_LINK)?.to_str().ok()
+}
+
+///The client of the file requests, each of them carries the token of the session
+pub type SessionClient = ServingClient<InterceptedService<Channel, Session>>;
+
+///A logged in connection, its token is replaced whenever it is refreshed
+#[derive(Clone)]
+pub struct Session {
+    channel: Channel,
+    state: Arc<RwLock<SessionState>>,
+}
+
+enum SessionState {
+    Token {
+        token: String,
+        expires: Instant,
+    },
+    ///API keys do not expire while they are used, they are sent as they are
+    ApiKey(String),
+    ///Share links are sent as they are too, the server decides when they stop working
+    ShareLink(String),
+}
+
+impl Session {
+    ///Runs the handshake over the channel
+    pub async fn login(channel: Channel, user: &str, password: &str) -> anyhow::Result<Self> {
+        let mut auth = AuthClient::new(channel.clone());
+
+        let challenge = auth
+            .challenge(ChallengeRequest {
+                user: user.to_string(),
+            })
+            .await
+            .map_err(ServiceError::from)?
+            .into_inner();
+
+        //Argon2 is slow on purpose, so it should not block the runtime
+        let password = password.to_string();
+        let settings = challenge.hash_settings;
+
+        let key = tokio::task::spawn_blocking(move || client_key(&password, &settings)).await??;
+
+        let reply = auth
+            .login(LoginRequest {
+                proof: client_proof(&key, &challenge.nonce),
+                nonce: challenge.nonce,
+            })
+            .await
+            .map_err(ServiceError::from)?
+            .into_inner();
+
+        Ok(Self {
+            channel,
+            state: Arc::new(RwLock::new(SessionState::from(reply))),
+        })
+    }
+
+    ///Sends the API key with every request instead of logging in, the key can only do what it has been scoped to
+    pub fn with_api_key(channel: Channel, key: &str) -> Self {
+        Self {
+            channel,
+            state: Arc::new(RwLock::new(SessionState::ApiKey(key.to_string()))),
+        }
+    }
+
+    ///Sends the token of a share link with every request, which can only download the file of the link
+    pub fn with_share_link(channel: Channel, token: &str) -> Self {
+        Self {
+            channel,
+            state: Arc::new(RwLock::new(SessionState::ShareLink(token.to_string()))),
+        }
+    }
+
+    ///A client whose requests are authenticated with this session, clones share the connection
+    pub fn client(&self) -> SessionClient {
+        ServingClient::with_interceptor(self.channel.clone(), self.clone())
+    }
+
+    ///Swaps the token for a new one which expires later
+    pub async fn refresh(&self) -> anyhow::Result<()> {
+        if !self.has_token() {
+            return Ok(());
+        }
+
+        let reply = AuthClient::with_interceptor(self.channel.clone(), self.clone())
+            .refresh(RefreshRequest {})
+            .await
+            .map_err(ServiceError::from)?
+            .into_inner();
+
+        *self.state.write().unwrap() = SessionState::from(reply);
+
+        Ok(())
+    }
+
+    ///Refreshes the token before it expires, this only returns once the server has refused to refresh it
+    pub async fn keep_alive(self) -> anyhow::Error {
+        loop {
+            let expires = match &*self.state.read().unwrap() {
+                SessionState::Token { expires, .. } => Some(*expires),
+                SessionState::ApiKey(_) | SessionState::ShareLink(_) => None,
+            };
+
+            //There is nothing to refresh
+            let Some(expires) = expires else {
+                return std::future::pending().await;
+            };
+
+            let now = Instant::now();
+
+            //Refresh halfway through the remaining time, so a slow reply still arrives in time
+            tokio::time::sleep(expires.saturating_duration_since(now) / 2).await;
+
+            if let Err(err) = self.refresh().await {
+                return err;
+            }
+        }
+    }
+
+    ///Only logins have a session token, which has to be refreshed and ended
+    fn has_token(&self) -> bool {
+        matches!(*self.state.read().unwrap(), SessionState::Token { .. })
+    }
+
+    ///Ends the session on the server, the token cannot be used anymore
+    pub async fn logout(&self) -> anyhow::Result<()> {
+        if !self.has_token() {
+            return Ok(());
+        }
+
+        AuthClient::with_interceptor(self.channel.clone(), self.clone())
+            .logout(LogoutRequest {})
+            .await
+            .map_err(ServiceError::from)?;
+
+        Ok(())
+    }
+}
+
+impl From<LoginReply> for SessionState {
+    fn from(reply: LoginReply) -> Self {
+        Self::Token {
+            token: reply.token,
+            expires: Instant::now() + Duration::from_secs(reply.expires_in),
+        }
+    }
+}
+
+///Adds the token to the authorization metadata of every request, or the API key or share link to their own metadata
+impl Interceptor for Session {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let (key, value) = match &*self.state.read().unwrap() {
+            SessionState::Token { token, .. } => (AUTHORIZATION, format!("Bearer {}", token)),
+            SessionState::ApiKey(key) => (API_KEY, key.clone()),
+            SessionState::ShareLink(token) => (SHARE_LINK, token.clone()),
+        };
+
+        let value = value
+            .parse()
+            .map_err(|_| Status::internal("The session token is not valid metadata"))?;
+
+        request.metadata_mut().insert(key, value);
+
+        Ok(request)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_the_proof_of_the_right_password() {
+        let hash = hash_password("correct horse").unwrap();
+        let nonce = random_bytes();
+
+        let key = client_key("correct horse", &hash_settings(&hash).unwrap()).unwrap();
+        let answer = client_proof(&key, &nonce);
+
+        assert!(verify_client_proof(
+            &stored_key(&hash).unwrap(),
+            &nonce,
+            &answer
+        ));
+        assert!(is_password(&hash, "correct horse").unwrap());
+        assert!(!is_empty_password(&hash).unwrap());
+    }
+
+    #[test]
+    fn rejects_the_proof_of_a_wrong_password_or_another_nonce() {
+        let hash = hash_password("correct horse").unwrap();
+        let settings = hash_settings(&hash).unwrap();
+        let stored = stored_key(&hash).unwrap();
+        let nonce = random_bytes();
+
+        let wrong = client_proof(&client_key("battery staple", &settings).unwrap(), &nonce);
+        assert!(!verify_client_proof(&stored, &nonce, &wrong));
+        assert!(!is_password(&hash, "battery staple").unwrap());
+
+        //An answer cannot be replayed for another challenge
+        let right = client_proof(&client_key("correct horse", &settings).unwrap(), &nonce);
+        assert!(!verify_client_proof(&stored, &random_bytes(), &right));
+
+        //Nor be cut short or sent as the stored key itself
+        assert!(!verify_client_proof(&stored, &nonce, &right[..16]));
+        assert!(!verify_client_proof(&stored, &nonce, &stored));
+    }
+
+    #[test]
+    fn the_stored_hash_does_not_contain_the_settings_output() {
+        let hash = hash_password("").unwrap();
+        let settings = hash_settings(&hash).unwrap();
+
+        assert!(hash.starts_with(&settings));
+        assert!(PasswordHash::new(&settings).unwrap().hash.is_none());
+        assert!(is_empty_password(&hash).unwrap());
+    }
+
+    #[test]
+    fn decoy_settings_look_like_real_ones() {
+        let decoy = decoy_settings(b"someone who does not exist").unwrap();
+        let real = hash_settings(&hash_password("password").unwrap()).unwrap();
+
+        assert_eq!(
+            decoy,
+            decoy_settings(b"someone who does not exist").unwrap()
+        );
+        assert_eq!(
+            decoy.rsplit_once('$').unwrap().0,
+            real.rsplit_once('$').unwrap().0
+        );
+        assert!(client_key("password", &decoy).is_ok());
+    }
+
+    #[test]
+    fn verifies_proofs_of_a_nonce() {
+        let key = random_bytes();
+        let nonce = random_bytes();
+        let answer = proof(&key, &nonce);
+
+        assert!(verify_proof(&key, &nonce, &answer));
+        assert!(!verify_proof(&random_bytes(), &nonce, &answer));
+        assert!(!verify_proof(&key, &random_bytes(), &answer));
+
+        let mut tampered = answer.clone();
+        tampered[0] ^= 1;
+        assert!(!verify_proof(&key, &nonce, &tampered));
+    }
+}
@@ -0,0 +1,517 @@
+use std::path::{Component, Path, PathBuf};
+
+use common_definitions::{
+    find_folder_mut,
+    sync::{SyncJob, SyncReport},
+    PathItem,
+};
+
+use crate::{
+    connection::{Connection, Failure},
+    Cli, Command,
+};
+
+///What gets printed about a remote file or folder
+#[derive(serde::Serialize, Debug)]
+struct Entry {
+    name: String,
+    ///Full path on the server
+    path: PathBuf,
+    kind: &'static str,
+    ///Size in bytes, only set for files
+    size: Option<u64>,
+    ///RFC 3339 timestamp, only set for files
+    modified: Option<String>,
+    ///Hex SHA-256 digest, only set for files the server has already hashed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    sha256: Option<String>,
+    ///Only set for folders when printing a tree
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entries: Option<Vec<Entry>>,
+}
+
+impl Entry {
+    fn new(item: &PathItem, recursive: bool) -> Self {
+        let path = item.get_path();
+
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_string_lossy().into_owned());
+
+        match item {
+            PathItem::Folder(folder) => Self {
+                name,
+                path,
+                kind: "folder",
+                size: None,
+                modified: None,
+                sha256: None,
+                entries: recursive.then(|| {
+                    folder
+                        .entries
+                        .iter()
+                        .map(|entry| Entry::new(entry, true))
+                        .collect()
+                }),
+            },
+            PathItem::File(file) => Self {
+                name,
+                path,
+                kind: "file",
+                size: file.metadata.as_ref().map(|metadata| metadata.file_size),
+                modified: file.metadata.as_ref().map(|metadata| {
+                    humantime::format_rfc3339_seconds(metadata.file_modified).to_string()
+                }),
+                sha256: file
+                    .metadata
+                    .as_ref()
+                    .and_then(|metadata| metadata.sha256.clone()),
+                entries: None,
+            },
+        }
+    }
+
+    ///A single line like `file   1024  2024-01-01T00:00:00Z  name`
+    fn line(&self) -> String {
+        format!(
+            "{:<6} {:>12}  {:<20}  {}",
+            self.kind,
+            self.size.map(|size| size.to_string()).unwrap_or_default(),
+            self.modified.as_deref().unwrap_or(""),
+            self.name
+        )
+    }
+
+    fn print_tree(&self, depth: usize) {
+        println!("{}{}", "  ".repeat(depth), self.name);
+
+        for entry in self.entries.iter().flatten() {
+            entry.print_tree(depth + 1);
+        }
+    }
+}
+
+///A file which has been downloaded by `get` or `redeem`
+#[derive(serde::Serialize, Debug)]
+struct Downloaded {
+    remote: PathBuf,
+    local: PathBuf,
+    bytes: u64,
+}
+
+///An archive which has been downloaded by `archive`
+#[derive(serde::Serialize, Debug)]
+struct Archived {
+    remote: Vec<PathBuf>,
+    local: PathBuf,
+    bytes: u64,
+}
+
+pub async fn run(cli: Cli) -> anyhow::Result<()> {
+    let mut connection = Connection::open(&cli).await?;
+
+    //A share link cannot list anything, it only knows its own file
+    let roots = if matches!(cli.command, Command::Redeem { .. }) {
+        Vec::new()
+    } else {
+        connection.list().await?
+    };
+
+    match &cli.command {
+        Command::Ls { remote } => {
+            let entries: Vec<Entry> = match remote {
+                Some(remote) => match find_item(&mut connection, &roots, remote).await? {
+                    PathItem::Folder(folder) => connection
+                        .list_directory(&folder.path)
+                        .await?
+                        .iter()
+                        .map(|entry| Entry::new(entry, false))
+                        .collect(),
+                    file => vec![Entry::new(&file, false)],
+                },
+                None => roots.iter().map(|root| Entry::new(root, false)).collect(),
+            };
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for entry in entries {
+                    println!("{}", entry.line());
+                }
+            }
+        }
+        Command::Tree { remote } => {
+            let mut items = match remote {
+                Some(remote) => vec![find_item(&mut connection, &roots, remote).await?],
+                None => roots,
+            };
+
+            for item in &mut items {
+                load_tree(&mut connection, item).await?;
+            }
+
+            let entries: Vec<Entry> = items.iter().map(|item| Entry::new(item, true)).collect();
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&entries)?);
+            } else {
+                for entry in entries {
+                    entry.print_tree(0);
+                }
+            }
+        }
+        Command::Stat { remote } => {
+            let entry = Entry::new(&find_item(&mut connection, &roots, remote).await?, false);
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&entry)?);
+            } else {
+                println!("Name: {}", entry.name);
+                println!("Path: {}", entry.path.display());
+                println!("Kind: {}", entry.kind);
+
+                if let Some(size) = entry.size {
+                    println!("Size: {} bytes", size);
+                }
+
+                if let Some(modified) = entry.modified {
+                    println!("Modified: {}", modified);
+                }
+
+                if let Some(sha256) = entry.sha256 {
+                    println!("SHA-256: {}", sha256);
+                }
+            }
+        }
+        Command::Get {
+            recursive,
+            remote,
+            local,
+        } => {
+            let item = find_item(&mut connection, &roots, remote).await?;
+
+            let mut downloaded = Vec::new();
+
+            match &item {
+                PathItem::File(file) => {
+                    //Save into the folder if we were given one
+                    let local = if local.is_dir() {
+                        local.join(file.path.file_name().unwrap_or_default())
+                    } else {
+                        local.clone()
+                    };
+
+                    let bytes = connection.download(&file.path, &local).await?;
+
+                    downloaded.push(Downloaded {
+                        remote: file.path.clone(),
+                        local,
+                        bytes,
+                    });
+                }
+                PathItem::Folder(_) if !recursive => {
+                    anyhow::bail!("{} is a folder, use -r to download it", remote)
+                }
+                PathItem::Folder(_) => {
+                    download_folder(&mut connection, &item, local, &mut downloaded, cli.json)
+                        .await?;
+                }
+            }
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&downloaded)?);
+            } else if !recursive {
+                for file in downloaded {
+                    println!(
+                        "{} -> {} ({} bytes)",
+                        file.remote.display(),
+                        file.local.display(),
+                        file.bytes
+                    );
+                }
+            }
+        }
+        Command::Redeem { local } => {
+            let redeemed = connection.redeem(local).await?;
+
+            let downloaded = Downloaded {
+                remote: redeemed.remote,
+                local: redeemed.destination,
+                bytes: redeemed.size,
+            };
+
+            if cli.json {
+                println!("{}", serde_json::to_string_pretty(&downloaded)?);
+            } else {
+                println!(
+                    "{} -> {} ({} bytes)",
+                    downloaded.remote.display(),
+                    downloaded.local.display(),
+                    downloaded.bytes
+                );
+            }
+        }
+        Command::Archive {
+            format,
+            local,
+            remotes,
+        } => {
+            let mut remote = Vec::new();
+
+            //Every path is resolved first, so nothing is downloaded if one of them does not exist
+            for path in remotes {
+                remote.push(find_item(&mut connection, &roots, path).await?.get_path());
+            }
+
+            let bytes = connection.download_archive(&remote, *format, local).await?;
+
+            if cli.json {
+                println!(
+                    "{}",
+                    serde_json::to_string_pretty(&Archived {
+                        remote,
+                        local: local.clone(),
+                        bytes,
+                    })?
+                );
+            } else {
+                println!("{} ({} bytes)", local.display(), bytes);
+            }
+        }
+        Command::Sync {
+            remote,
+            local,
+            include,
+            exclude,
+            delete,
+            dry_run,
+            interval,
+        } => {
+            let PathItem::Folder(folder) = find_item(&mut connection, &roots, remote).await? else {
+                anyhow::bail!("{} is not a folder", remote);
+            };
+
+            let job = SyncJob {
+                remote: folder.path,
+                local: local.clone(),
+                include: include.clone(),
+                exclude: exclude.clone(),
+                delete: *delete,
+                dry_run: *dry_run,
+            };
+
+            let Some(interval) = interval else {
+                let report = connection.sync(&job).await?;
+
+                print_sync_report(&report, cli.json)?;
+
+                anyhow::ensure!(
+                    report.failed.is_empty(),
+                    "{} files could not be synced",
+                    report.failed.len()
+                );
+
+                return connection.close().await;
+            };
+
+            //Runs until interrupted, a run which fails is tried again on the next tick
+            loop {
+                match connection.sync(&job).await {
+                    Ok(report) => print_sync_report(&report, cli.json)?,
+                    Err(err) => {
+                        eprintln!("error: {:#}", err);
+
+                        //The server might have restarted or ended the session, the next run gets a new one
+                        if is_session_lost(&err) {
+                            match Connection::open(&cli).await {
+                                Ok(reconnected) => connection = reconnected,
+                                Err(err) => eprintln!("error: {:#}", err),
+                            }
+                        }
+                    }
+                }
+
+                tokio::time::sleep(std::time::Duration::from_secs(*interval)).await;
+            }
+        }
+    }
+
+    connection.close().await
+}
+
+///The connection has broken or the session is not valid anymore, so the requests can only succeed on a new connection
+fn is_session_lost(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        matches!(
+            cause.downcast_ref::<Failure>(),
+            Some(Failure::Connection(_) | Failure::Authentication(_))
+        )
+    })
+}
+
+fn print_sync_report(report: &SyncReport, json: bool) -> anyhow::Result<()> {
+    if json {
+        println!("{}", serde_json::to_string(report)?);
+
+        return Ok(());
+    }
+
+    for action in &report.actions {
+        if report.dry_run {
+            println!("would {}", action);
+        } else {
+            println!("{}", action);
+        }
+    }
+
+    for (path, err) in &report.failed {
+        eprintln!("failed {}: {}", path.display(), err);
+    }
+
+    println!(
+        "{} changes, {} files up to date, {} bytes downloaded",
+        report.actions.len(),
+        report.unchanged,
+        report.bytes
+    );
+
+    Ok(())
+}
+
+///Recreates the remote folder inside the local path and downloads every file in it
+async fn download_folder(
+    connection: &mut Connection,
+    folder: &PathItem,
+    local: &Path,
+    downloaded: &mut Vec<Downloaded>,
+    quiet: bool,
+) -> anyhow::Result<()> {
+    //Walk the tree without recursion, as async fns cannot call themselves directly
+    let mut stack = vec![(folder.clone(), local.to_path_buf())];
+
+    while let Some((item, local)) = stack.pop() {
+        match item {
+            PathItem::Folder(folder) => {
+                tokio::fs::create_dir_all(&local).await?;
+
+                for entry in connection.list_directory(&folder.path).await? {
+                    let name = entry.get_path();
+                    let name = name.file_name().unwrap_or_default();
+
+                    let local = local.join(name);
+
+                    stack.push((entry, local));
+                }
+            }
+            PathItem::File(file) => {
+                let bytes = connection.download(&file.path, &local).await?;
+
+                if !quiet {
+                    println!(
+                        "{} -> {} ({} bytes)",
+                        file.path.display(),
+                        local.display(),
+                        bytes
+                    );
+                }
+
+                downloaded.push(Downloaded {
+                    remote: file.path,
+                    local,
+                    bytes,
+                });
+            }
+        }
+    }
+
+    Ok(())
+}
+
+///Lists every folder below the item, so that it can be printed as a tree
+async fn load_tree(connection: &mut Connection, item: &mut PathItem) -> anyhow::Result<()> {
+    let PathItem::Folder(root) = item else {
+        return Ok(());
+    };
+
+    //Walk the tree without recursion, as async fns cannot call themselves directly
+    let mut pending = vec![root.path.clone()];
+
+    while let Some(path) = pending.pop() {
+        let entries = connection.list_directory(&path).await?;
+
+        pending.extend(entries.iter().filter_map(|entry| match entry {
+            PathItem::Folder(folder) => Some(folder.path.clone()),
+            PathItem::File(_) => None,
+        }));
+
+        if let Some(folder) = find_folder_mut(std::slice::from_mut(item), &path) {
+            folder.entries = entries;
+            folder.loaded = true;
+        }
+    }
+
+    Ok(())
+}
+
+///Finds a remote item either by its full path on the server, or by a path starting with the name of a shared folder
+async fn find_item(
+    connection: &mut Connection,
+    roots: &[PathItem],
+    remote: &str,
+) -> anyhow::Result<PathItem> {
+    let remote_path = Path::new(remote);
+
+    for root in roots {
+        let root_path = root.get_path();
+
+        let relative = if let Ok(relative) = remote_path.strip_prefix(&root_path) {
+            relative.to_path_buf()
+        } else if let Ok(relative) =
+            remote_path.strip_prefix(root_path.file_name().unwrap_or_default())
+        {
+            relative.to_path_buf()
+        } else {
+            continue;
+        };
+
+        if let Some(item) = walk(connection, root, &relative).await? {
+            return Ok(item);
+        }
+    }
+
+    Err(Failure::NotFound(remote.to_string()).into())
+}
+
+///Follows the relative path down from the item, listing each folder on the way
+async fn walk(
+    connection: &mut Connection,
+    item: &PathItem,
+    relative: &Path,
+) -> anyhow::Result<Option<PathItem>> {
+    let mut item = item.clone();
+
+    for component in relative.components() {
+        let name = match component {
+            Component::Normal(name) => name,
+            Component::CurDir => continue,
+            _ => return Ok(None),
+        };
+
+        let PathItem::Folder(folder) = &item else {
+            return Ok(None);
+        };
+
+        let entries = connection.list_directory(&folder.path).await?;
+
+        match entries
+            .into_iter()
+            .find(|entry| entry.get_path().file_name() == Some(name))
+        {
+            Some(entry) => item = entry,
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(item))
+}
@@ -0,0 +1,193 @@
+use std::{
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use common_definitions::{
+    auth::{Session, SessionClient},
+    download::{download_archive, download_file, redeem_share_link, RedeemedLink},
+    error::ServiceError,
+    messages::HostRequest,
+    sync::{run_sync, SyncJob, SyncReport},
+    tls::{connect_channel, ServerVerification},
+    ArchiveFormat, ClientRequest, PathItem, ServerReply, DIRECTORY_PAGE_SIZE,
+};
+
+use crate::Cli;
+
+///The failures which have their own exit code
+#[derive(Debug)]
+pub enum Failure {
+    Connection(String),
+    Authentication(String),
+    NotFound(String),
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Connection(message) => write!(f, "Connection failed: {}", message),
+            Failure::Authentication(message) => write!(f, "Authentication failed: {}", message),
+            Failure::NotFound(path) => write!(f, "Remote path not found: {}", path),
+        }
+    }
+}
+
+impl std::error::Error for Failure {}
+
+///Turns the error the server replied with into a failure with its own exit code, if it has one
+fn service_failure(err: ServiceError) -> anyhow::Error {
+    match err {
+        ServiceError::Unauthenticated(message) => Failure::Authentication(message).into(),
+        ServiceError::NotFound(message) => Failure::NotFound(message).into(),
+        ServiceError::Unavailable(message) => Failure::Connection(message).into(),
+        err => err.into(),
+    }
+}
+
+///Gives the errors the server has answered with their exit code, the rest are kept as they are
+fn library_failure(err: anyhow::Error) -> anyhow::Error {
+    match ServiceError::find(&err) {
+        Some(service) => service_failure(service),
+        None => err,
+    }
+}
+
+pub struct Connection {
+    client: SessionClient,
+    session: Session,
+}
+
+impl Connection {
+    pub async fn open(cli: &Cli) -> anyhow::Result<Self> {
+        let verification = match &cli.ca_cert {
+            Some(path) => ServerVerification::CertificateAuthority(std::fs::read(path)?),
+            None => ServerVerification::Fingerprint(cli.fingerprint.clone()),
+        };
+
+        //Wrap IPv6 addresses in brackets
+        let address = if cli.address.contains(':') && !cli.address.starts_with('[') {
+            format!("[{}]:{}", cli.address, cli.port)
+        } else {
+            format!("{}:{}", cli.address, cli.port)
+        };
+
+        let (channel, fingerprint) = connect_channel(&address, verification)
+            .await
+            .map_err(|err| Failure::Connection(format!("{:#}", err)))?;
+
+        if let (Some(fingerprint), None) = (fingerprint, &cli.fingerprint) {
+            eprintln!(
+                "warning: the server's certificate was not verified, pass --fingerprint {} to pin it",
+                fingerprint
+            );
+        }
+
+        let session = match (&cli.api_key, &cli.share_link, &cli.user) {
+            (Some(key), _, _) => Session::with_api_key(channel, key),
+            (None, Some(token), _) => Session::with_share_link(channel, token),
+            //The password is only used to log in, the requests carry the session token
+            (None, None, Some(user)) => Session::login(channel, user, &cli.password)
+                .await
+                .map_err(library_failure)?,
+            //Clap requires one of them
+            (None, None, None) => {
+                anyhow::bail!("Either a user, an API key or a share link is needed")
+            }
+        };
+
+        //Long transfers outlive the token, the task stops with the process
+        tokio::spawn(session.clone().keep_alive());
+
+        Ok(Self {
+            client: session.client(),
+            session,
+        })
+    }
+
+    ///Sends a request over `server_provide` and unwraps the reply
+    async fn provide(&mut self, request: ClientRequest) -> anyhow::Result<ServerReply> {
+        let reply = self
+            .client
+            .server_provide(HostRequest::new(request))
+            .await
+            .map_err(|status| service_failure(status.into()))?
+            .into_inner();
+
+        reply.into_reply()
+    }
+
+    ///Asks for the shared folders, only their immediate children are listed
+    pub async fn list(&mut self) -> anyhow::Result<Vec<PathItem>> {
+        match self.provide(ClientRequest::ListRequest).await? {
+            ServerReply::List(list) => Ok(list.list),
+            reply => anyhow::bail!("Unexpected reply: {:?}", reply),
+        }
+    }
+
+    ///Asks for every entry of a remote folder, one page at a time
+    pub async fn list_directory(&mut self, path: &Path) -> anyhow::Result<Vec<PathItem>> {
+        let mut entries = Vec::new();
+
+        loop {
+            let request = ClientRequest::ListDirectory {
+                path: path.to_path_buf(),
+                offset: entries.len() as u64,
+                limit: DIRECTORY_PAGE_SIZE,
+            };
+
+            let listing = match self.provide(request).await? {
+                ServerReply::Directory(listing) => listing,
+                reply => anyhow::bail!("Unexpected reply: {:?}", reply),
+            };
+
+            let page_was_empty = listing.entries.is_empty();
+
+            entries.extend(listing.entries);
+
+            if page_was_empty || entries.len() as u64 >= listing.total {
+                return Ok(entries);
+            }
+        }
+    }
+
+    ///Streams the remote file to the local path, returns the amount of bytes written
+    ///
+    ///An interrupted download of the same file is resumed
+    pub async fn download(&mut self, remote: &Path, local: &Path) -> anyhow::Result<u64> {
+        download_file(&mut self.client, remote, local)
+            .await
+            .map_err(library_failure)
+    }
+
+    ///Downloads the file of the share link to the local path, an interrupted download is resumed
+    pub async fn redeem(&mut self, local: &Path) -> anyhow::Result<RedeemedLink> {
+        redeem_share_link(&mut self.client, local)
+            .await
+            .map_err(library_failure)
+    }
+
+    ///Streams an archive of the remote files and folders to the local path, returns the size of the archive
+    pub async fn download_archive(
+        &mut self,
+        remote: &[PathBuf],
+        format: ArchiveFormat,
+        local: &Path,
+    ) -> anyhow::Result<u64> {
+        download_archive(&mut self.client, remote, format, local)
+            .await
+            .map_err(library_failure)
+    }
+
+    ///Brings the local folder of the job up to date with the remote one
+    pub async fn sync(&mut self, job: &SyncJob) -> anyhow::Result<SyncReport> {
+        run_sync(&mut self.client, job)
+            .await
+            .map_err(library_failure)
+    }
+
+    ///Ends the session, so its token cannot be used anymore
+    pub async fn close(self) -> anyhow::Result<()> {
+        self.session.logout().await
+    }
+}
@@ -0,0 +1,164 @@
+#![warn(clippy::all, rust_2018_idioms)]
+
+use std::{path::PathBuf, process::ExitCode};
+
+use clap::{Parser, Subcommand};
+use common_definitions::ArchiveFormat;
+
+mod commands;
+mod connection;
+
+use connection::Failure;
+
+/// Command-line client for a File Hosting server
+///
+/// Remote paths either start with the name of a shared folder (`share/sub/file.txt`),
+/// or are the full path of the item on the server.
+///
+/// A share link only needs `--share-link` and the `redeem` command, no user.
+///
+/// Exit codes: 0 success, 1 error, 2 invalid usage, 3 connection or authentication failure, 4 remote path not found
+#[derive(Parser, Debug)]
+#[command(name = "cli", version)]
+pub struct Cli {
+    /// Address of the server (IPv6 address or hostname)
+    #[arg(short, long, env = "FILE_HOSTING_ADDRESS")]
+    address: String,
+
+    /// Port of the server
+    #[arg(short, long, env = "FILE_HOSTING_PORT")]
+    port: u16,
+
+    /// User to log in as
+    #[arg(
+        short,
+        long,
+        env = "FILE_HOSTING_USER",
+        required_unless_present_any = ["api_key", "share_link"]
+    )]
+    user: Option<String>,
+
+    /// Password of the user
+    #[arg(
+        long,
+        env = "FILE_HOSTING_PASSWORD",
+        default_value = "",
+        hide_env_values = true
+    )]
+    password: String,
+
+    /// API key minted on the server, it is used instead of logging in as a user
+    #[arg(
+        long,
+        env = "FILE_HOSTING_API_KEY",
+        conflicts_with = "user",
+        hide_env_values = true
+    )]
+    api_key: Option<String>,
+
+    /// Share link made on the server, it can only download its file with the redeem command
+    #[arg(
+        long,
+        env = "FILE_HOSTING_SHARE_LINK",
+        conflicts_with_all = ["user", "api_key"],
+        hide_env_values = true
+    )]
+    share_link: Option<String>,
+
+    /// SHA-256 fingerprint of the server's certificate, as shown by the server. If neither this nor --ca-cert is set, any certificate is accepted
+    #[arg(long, env = "FILE_HOSTING_FINGERPRINT")]
+    fingerprint: Option<String>,
+
+    /// PEM certificate authority the server's certificate has to be signed by
+    #[arg(long, env = "FILE_HOSTING_CA_CERT", conflicts_with = "fingerprint")]
+    ca_cert: Option<PathBuf>,
+
+    /// Print machine-readable JSON instead of text
+    #[arg(long, global = true)]
+    json: bool,
+
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// List the entries of a remote folder, or the shared folders if no path is given
+    Ls { remote: Option<String> },
+    /// Print a remote folder recursively, or every shared folder if no path is given
+    Tree { remote: Option<String> },
+    /// Download a remote file, or a whole folder with -r
+    Get {
+        /// Download a folder with all of its contents
+        #[arg(short, long)]
+        recursive: bool,
+        remote: String,
+        /// Where to save the download, files are saved into it if it is an existing folder
+        local: PathBuf,
+    },
+    /// Show the metadata of a remote file or folder
+    Stat { remote: String },
+    /// Download remote files and folders as a single archive, which the server generates while sending it
+    Archive {
+        /// Format of the archive: tar, tar.zst or zip
+        #[arg(short, long, default_value_t = ArchiveFormat::Tar)]
+        format: ArchiveFormat,
+        /// Where to save the archive
+        local: PathBuf,
+        #[arg(required = true)]
+        remotes: Vec<String>,
+    },
+    /// Download the file of the share link passed with --share-link
+    Redeem {
+        /// Where to save the file, it keeps its name if this is an existing folder
+        local: PathBuf,
+    },
+    /// Bring a local folder up to date with a remote folder, only new and changed files are downloaded
+    Sync {
+        remote: String,
+        local: PathBuf,
+        /// Only sync the files matching this glob, relative to the remote folder. Can be repeated
+        #[arg(long)]
+        include: Vec<String>,
+        /// Do not sync the files and folders matching this glob, relative to the remote folder. Can be repeated
+        #[arg(long)]
+        exclude: Vec<String>,
+        /// Delete the local files which have been deleted on the server
+        #[arg(long)]
+        delete: bool,
+        /// Only print the changes which would be made
+        #[arg(long)]
+        dry_run: bool,
+        /// Sync again after this many seconds, until interrupted
+        #[arg(long)]
+        interval: Option<u64>,
+    },
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let cli = Cli::parse();
+
+    match commands::run(cli).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(err) => {
+            eprintln!("error: {:#}", err);
+
+            ExitCode::from(exit_code(&err))
+        }
+    }
+}
+
+///Maps the error to the exit codes described in the help message
+fn exit_code(err: &anyhow::Error) -> u8 {
+    for cause in err.chain() {
+        if let Some(failure) = cause.downcast_ref::<Failure>() {
+            return match failure {
+                Failure::Connection(_) | Failure::Authentication(_) => 3,
+                Failure::NotFound(_) => 4,
+            };
+        }
+    }
+
+    1
+}
@@ -5,11 +5,6 @@ use eframe::NativeOptions;
 mod ui;
 use ui::Client;
 
-/// Include proto
-pub mod file_hosting {
-    tonic::include_proto!("file_hosting");
-}
-
 #[tokio::main]
 async fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
     eframe::run_native(
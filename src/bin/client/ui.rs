@@ -2,4 +2,7 @@
 
 mod app;
 mod backend;
+mod requests;
+mod sync;
+mod transfers;
 pub use app::Client;
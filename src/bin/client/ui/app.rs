@@ -1,8 +1,24 @@
+use common_definitions::{
+    apply_change, download::discard_download, dropped_files, find_folder_mut, render_path,
+    tls::ServerVerification, ArchiveFormat, ClientRequest, PathAction, PathItem, ServerReply,
+    TreeChange, DIRECTORY_PAGE_SIZE,
+};
 use egui::{vec2, Color32, RichText};
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 use tokio::sync::mpsc;
-use common_definitions::{render_path, ClientRequest, PathItem, ServerReply};
 
-use crate::ui::backend::client;
+use crate::ui::{
+    backend::client::{
+        self, ConnectionCommand, ConnectionEvent, ConnectionRequest, RequestError, Response,
+    },
+    requests::{send_command, Pending, Requests},
+    sync::SyncPanel,
+    transfers::{TransferCommand, TransfersPanel},
+};
 
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
@@ -11,26 +27,77 @@ pub struct Client {
     connecting_to: String,
     /// The port we are connecting to
     connecting_port: i64,
+    /// The user we log in as
+    user: String,
     /// The password
     password: String,
+    /// The certificate authority the server's certificate has to be signed by, if None the server's certificate is pinned on first use
+    ca_certificate: Option<PathBuf>,
+    /// The pinned certificate fingerprints of the servers we have connected to, keyed by address
+    known_servers: HashMap<String, String>,
+    /// The format folders and selections are downloaded in
+    archive_format: ArchiveFormat,
+    /// The job which keeps a local folder in sync with a remote one
+    sync: SyncPanel,
+    /// The queue of downloads
+    transfers: TransfersPanel,
     //this_sx gets moved to connection, and you can send instruction to the connection thread byy this channel
     #[serde(skip)]
-    connection: Option<mpsc::Sender<Option<ClientRequest>>>,
+    connection: Option<mpsc::Sender<Option<ConnectionCommand>>>,
     #[serde(skip)]
-    main_rx: mpsc::Receiver<String>,
+    main_rx: mpsc::Receiver<ConnectionEvent>,
     #[serde(skip)]
-    main_sx: mpsc::Sender<String>,
+    main_sx: mpsc::Sender<ConnectionEvent>,
     #[serde(skip)]
-    this_rx: mpsc::Receiver<Option<ClientRequest>>,
+    this_rx: mpsc::Receiver<Option<ConnectionCommand>>,
     #[serde(skip)]
-    this_sx: mpsc::Sender<Option<ClientRequest>>,
+    this_sx: mpsc::Sender<Option<ConnectionCommand>>,
+    /// The requests which are in flight on the connection
+    #[serde(skip)]
+    requests: Requests,
 
     #[serde(skip)]
     shared_folders: Vec<PathItem>,
     #[serde(skip)]
     invalid_password: bool,
+    /// The address of the server we have connected to last
+    #[serde(skip)]
+    connected_to: String,
+    /// The fingerprint of the server we are connected to
+    #[serde(skip)]
+    server_fingerprint: Option<String>,
+    /// Why we could not connect to the server
+    #[serde(skip)]
+    connection_error: Option<String>,
+    /// The last error the server has replied with
+    #[serde(skip)]
+    request_error: Option<String>,
+    /// The outcome of the last finished transfer
+    #[serde(skip)]
+    transfer_status: Option<String>,
+    /// The uploads which have been refused because the file is already on the server, by the local file and the folder on the server
+    #[serde(skip)]
+    upload_conflicts: Vec<(PathBuf, PathBuf)>,
+    /// The items which have appeared recently, and when they did
+    #[serde(skip)]
+    highlights: HashMap<PathBuf, Instant>,
+    /// The items which have been checked in the tree, they are downloaded together as an archive
+    #[serde(skip)]
+    selection: HashSet<PathBuf>,
+    /// The share link to download, it is not saved as anyone who has it can use it
+    #[serde(skip)]
+    share_link: String,
+    /// A share link is being downloaded
+    #[serde(skip)]
+    redeeming: bool,
+    /// Where the file of the last share link has been saved, or why it could not be downloaded
+    #[serde(skip)]
+    redeem_status: Option<Result<String, String>>,
 }
 
+/// How long new items stay highlighted
+const HIGHLIGHT_DURATION: Duration = Duration::from_secs(10);
+
 impl Default for Client {
     fn default() -> Self {
         //this sx is used to send info the the connection thread
@@ -39,23 +106,243 @@ impl Default for Client {
         let (main_sx, main_rx) = mpsc::channel(100);
         Self {
             connecting_to: String::new(),
+            user: String::new(),
             password: String::new(),
             connecting_port: 0,
+            ca_certificate: None,
+            known_servers: HashMap::new(),
+            archive_format: ArchiveFormat::default(),
+            sync: SyncPanel::default(),
+            transfers: TransfersPanel::default(),
             connection: None,
             main_rx,
             main_sx,
             this_rx,
             this_sx,
+            requests: Requests::default(),
             shared_folders: Vec::new(),
             invalid_password: false,
+            connected_to: String::new(),
+            server_fingerprint: None,
+            connection_error: None,
+            request_error: None,
+            transfer_status: None,
+            upload_conflicts: Vec::new(),
+            highlights: HashMap::new(),
+            selection: HashSet::new(),
+            share_link: String::new(),
+            redeeming: false,
+            redeem_status: None,
         }
     }
 }
 
 impl Client {
+    ///Handles the outcome of a request in the context it has been started in
+    fn finished(&mut self, pending: Pending, outcome: Result<Response, RequestError>) {
+        match (pending, outcome) {
+            //The session might have been ended on the server since we have connected
+            (_, Err(err)) if err.is_unauthenticated() => {
+                self.connection_error = Some(err.to_string());
+
+                self.disconnect();
+            }
+            (Pending::Download(transfer), Ok(Response::Downloaded(bytes))) => {
+                self.transfers.finished(transfer, Ok(bytes));
+            }
+            (Pending::Download(transfer), Err(err)) => {
+                self.transfers.finished(transfer, Err(err.to_string()));
+            }
+            (Pending::Sync(_), Ok(Response::Synced(report))) => {
+                self.sync.finished(Ok(report));
+            }
+            (pending @ Pending::Sync(_), Err(err)) => {
+                self.sync.finished(Err(format!("{}: {}", pending, err)));
+            }
+            (Pending::Archive(destination), Ok(Response::Downloaded(bytes))) => {
+                self.transfer_status = Some(format!(
+                    "Saved {} ({} KB)",
+                    destination
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                    bytes / 1024
+                ));
+            }
+            (Pending::Mirror(folder), Ok(Response::Mirrored(report))) => {
+                self.transfer_status = Some(format!(
+                    "Mirrored {}: {} downloaded ({} KB), {} already up to date",
+                    folder.file_name().unwrap_or_default().to_string_lossy(),
+                    report.downloaded,
+                    report.bytes / 1024,
+                    report.skipped
+                ));
+
+                if let Some((path, err)) = report.failed.first() {
+                    self.request_error = Some(format!(
+                        "Failed to mirror {} files, {}: {}",
+                        report.failed.len(),
+                        path.display(),
+                        err
+                    ));
+                }
+            }
+            (Pending::Listing(_), Ok(Response::Reply(ServerReply::Directory(listing)))) => {
+                if let Some(folder) = find_folder_mut(&mut self.shared_folders, &listing.path) {
+                    if listing.offset == 0 {
+                        folder.entries.clear();
+                    }
+
+                    let received = listing.offset + listing.entries.len() as u64;
+                    let page_was_empty = listing.entries.is_empty();
+
+                    folder.entries.extend(listing.entries);
+
+                    //Ask for the next page until we have every entry
+                    if received < listing.total && !page_was_empty {
+                        request_directory(
+                            &self.this_sx,
+                            &mut self.requests,
+                            listing.path,
+                            received,
+                        );
+                    } else {
+                        folder.loaded = true;
+                    }
+                }
+            }
+            (Pending::Upload { .. }, Ok(Response::Reply(ServerReply::Uploaded(file)))) => {
+                self.request_error = None;
+
+                let folder_path = file.path.parent().map(|path| path.to_path_buf());
+
+                //Show the file if its folder has already been listed
+                if let Some(folder) =
+                    folder_path.and_then(|path| find_folder_mut(&mut self.shared_folders, &path))
+                {
+                    folder.entries.retain(|entry| entry.get_path() != file.path);
+                    folder.entries.push(PathItem::File(file));
+                }
+            }
+            //The user decides if the file on the server is replaced
+            (Pending::Upload { source, folder }, Err(err)) if err.is_already_exists() => {
+                self.upload_conflicts.push((source, folder));
+            }
+            (pending, Err(err)) => {
+                self.request_error = Some(format!("{}: {}", pending, err));
+            }
+            (pending, Ok(response)) => {
+                self.request_error = Some(format!(
+                    "{}: the server has sent an unexpected reply {:?}",
+                    pending, response
+                ));
+            }
+        }
+    }
+
+    ///Stops the connection thread gracefully and forgets everything which belonged to the connection
+    fn disconnect(&mut self) {
+        let this_sx = self.this_sx.clone();
+
+        tokio::spawn(async move {
+            //The thread might have already stopped on its own
+            let _ = this_sx.send(None).await;
+        });
+
+        self.reset_connection();
+    }
+
+    fn reset_connection(&mut self) {
+        self.shared_folders.clear();
+        self.selection.clear();
+        self.upload_conflicts.clear();
+        self.sync.disconnected();
+        self.transfers.disconnected();
+        self.requests.clear();
+        self.connection = None;
+    }
+
+    ///How the certificate of the server at the address is checked, with the certificate authority if one has been selected
+    fn verification(&self, ip: &str) -> Result<ServerVerification, String> {
+        match &self.ca_certificate {
+            Some(path) => std::fs::read(path)
+                .map(ServerVerification::CertificateAuthority)
+                .map_err(|err| format!("Failed to read certificate authority: {}", err)),
+            None => Ok(ServerVerification::Fingerprint(
+                self.known_servers.get(ip).cloned(),
+            )),
+        }
+    }
+
+    ///Downloads the file of a share link, it does not need a password or the connection
+    fn show_share_link(&mut self, ui: &mut egui::Ui) {
+        ui.label("Download the file of a share link from the server set up in the Connect menu, no password is needed");
+
+        ui.label("Link");
+        ui.text_edit_singleline(&mut self.share_link);
+
+        let enabled = !self.redeeming && !self.share_link.trim().is_empty();
+
+        if ui
+            .add_enabled(enabled, egui::Button::new("Download"))
+            .clicked()
+        {
+            let ip = format!("[{}]:{}", self.connecting_to, self.connecting_port);
+
+            let verification = match self.verification(&ip) {
+                Ok(verification) => verification,
+                Err(err) => {
+                    self.redeem_status = Some(Err(err));
+
+                    return;
+                }
+            };
+
+            if let Some(folder) = rfd::FileDialog::new().pick_folder() {
+                let token = self.share_link.trim().to_string();
+                let main_sx = self.main_sx.clone();
+
+                self.redeeming = true;
+                self.redeem_status = None;
+
+                tokio::spawn(async move {
+                    let outcome = client::redeem_link(ip.clone(), token, verification, folder)
+                        .await
+                        .map_err(|err| format!("{:#}", err));
+
+                    let _ = main_sx
+                        .send(ConnectionEvent::Redeemed {
+                            address: ip,
+                            outcome,
+                        })
+                        .await;
+                });
+            }
+        }
+
+        if self.redeeming {
+            ui.spinner();
+        }
+
+        match &self.redeem_status {
+            Some(Ok(status)) => {
+                ui.label(status);
+            }
+            Some(Err(err)) => {
+                ui.label(RichText::from(err).color(Color32::RED));
+            }
+            None => {}
+        }
+    }
+
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
         if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+            let mut client: Self = eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+
+            //The downloads which were running when the client was closed are resumed once we connect
+            client.transfers.disconnected();
+
+            return client;
         }
 
         Default::default()
@@ -85,22 +372,94 @@ impl eframe::App for Client {
                                     .clamp_range(0..=65535),
                             );
 
+                            ui.label("User");
+                            ui.text_edit_singleline(&mut self.user);
+
                             ui.label("Password");
                             ui.text_edit_singleline(&mut self.password);
+
+                            ui.separator();
+
+                            //How the server's certificate is checked
+                            ui.label("Certificate authority");
+                            ui.horizontal(|ui| {
+                                match &self.ca_certificate {
+                                    Some(path) => ui
+                                        .label(
+                                            path.file_name().unwrap_or_default().to_string_lossy(),
+                                        )
+                                        .on_hover_text(format!("Full path: {:?}", path)),
+                                    None => ui.label("None (trust on first use)"),
+                                };
+
+                                if ui.button("Select").clicked() {
+                                    if let Some(path) = rfd::FileDialog::new()
+                                        .add_filter("Certificate", &["pem", "crt"])
+                                        .pick_file()
+                                    {
+                                        self.ca_certificate = Some(path);
+                                    }
+                                }
+
+                                if ui.button("Clear").clicked() {
+                                    self.ca_certificate = None;
+                                }
+                            });
+
+                            let address =
+                                format!("[{}]:{}", self.connecting_to, self.connecting_port);
+
+                            if let Some(fingerprint) = self.known_servers.get(&address).cloned() {
+                                ui.label("Pinned fingerprint");
+                                ui.horizontal(|ui| {
+                                    ui.label(RichText::from(fingerprint).monospace());
+
+                                    //The server might have generated a new certificate
+                                    if ui.button("Forget").clicked() {
+                                        self.known_servers.remove(&address);
+                                    }
+                                });
+                            }
                         });
 
                         ui.separator();
 
                         if self.invalid_password {
-                            ui.label(RichText::from("Invalid password!").color(Color32::RED));
+                            ui.label(
+                                RichText::from("Invalid user name or password!")
+                                    .color(Color32::RED),
+                            );
+                        }
+
+                        if let Some(err) = &self.connection_error {
+                            ui.label(RichText::from(err).color(Color32::RED));
+                        }
+
+                        if let (Some(fingerprint), Some(_)) =
+                            (&self.server_fingerprint, &self.connection)
+                        {
+                            ui.label("Server fingerprint");
+                            ui.label(RichText::from(fingerprint).monospace());
                         }
 
                         ui.add_enabled_ui(self.connection.is_none(), |ui| {
                             if ui.button("Connect").clicked() {
                                 let ip =
                                     format!("[{}]:{}", self.connecting_to, self.connecting_port);
+                                let user = self.user.clone();
                                 let password = self.password.clone();
 
+                                let verification = match self.verification(&ip) {
+                                    Ok(verification) => verification,
+                                    Err(err) => {
+                                        self.connection_error = Some(err);
+
+                                        return;
+                                    }
+                                };
+
+                                self.connected_to = ip.clone();
+
                                 //The info is passed TO the MAIN from the connection
                                 let main_sx = self.main_sx.clone();
 
@@ -111,48 +470,198 @@ impl eframe::App for Client {
 
                                 //Connect
                                 tokio::spawn(async move {
-                                    match client::connect(ip, password, main_sx, this_rx).await {
-                                        Ok(_) => {}
-                                        Err(err) => {
-                                            dbg!(err.downcast::<String>().unwrap());
-                                        }
+                                    if let Err(err) = client::connect(
+                                        ip,
+                                        user,
+                                        password,
+                                        verification,
+                                        main_sx.clone(),
+                                        this_rx,
+                                    )
+                                    .await
+                                    {
+                                        let _ = main_sx
+                                            .send(ConnectionEvent::Error(format!("{:#}", err)))
+                                            .await;
                                     };
                                 });
                             };
                         });
                         ui.add_enabled_ui(self.connection.is_some(), |ui| {
                             if ui.button("Disconnect").clicked() {
-                                let this_sx = self.this_sx.clone();
-
-                                //Stop the connection thread gracefully
-                                tokio::spawn(async move {
-                                    dbg!(this_sx.send(None).await.unwrap());
-                                });
-
-                                //reset state
-                                self.shared_folders.clear();
-                                self.connection = None;
+                                self.disconnect();
                             };
                         });
                     });
                 });
 
+                ui.menu_button("Share link", |ui| {
+                    ui.allocate_ui(vec2(300., 100.), |ui| {
+                        self.show_share_link(ui);
+                    });
+                });
+
+                ui.menu_button("Sync", |ui| {
+                    ui.allocate_ui(vec2(300., 100.), |ui| {
+                        ui.label("Keep a local folder in sync with a remote one");
+
+                        self.sync.show(ui, self.connection.is_some());
+                    });
+                });
+
+                if ui
+                    .selectable_label(
+                        self.transfers.open,
+                        format!("Transfers ({})", self.transfers.pending()),
+                    )
+                    .clicked()
+                {
+                    self.transfers.open = !self.transfers.open;
+                }
+
                 // Display status
                 if self.connection.is_none() {
                     ui.label(RichText::from("Offline").color(Color32::RED));
                 } else {
                     ui.label(RichText::from("Online").color(Color32::GREEN));
                 }
+
+                if let Some(err) = &self.request_error {
+                    ui.separator();
+
+                    ui.label(RichText::from(err).color(Color32::RED));
+
+                    if ui.small_button("Dismiss").clicked() {
+                        self.request_error = None;
+                    }
+                }
+
+                //The conflicts are asked about one at a time
+                if let Some((source, folder)) = self.upload_conflicts.first().cloned() {
+                    ui.separator();
+
+                    ui.label(format!(
+                        "{} already exists in {}",
+                        source.file_name().unwrap_or_default().to_string_lossy(),
+                        folder.display()
+                    ));
+
+                    if ui.small_button("Overwrite").clicked() {
+                        self.upload_conflicts.remove(0);
+
+                        upload_files(
+                            &self.this_sx,
+                            &mut self.requests,
+                            folder,
+                            vec![source],
+                            true,
+                        );
+                    } else if ui.small_button("Skip").clicked() {
+                        self.upload_conflicts.remove(0);
+                    }
+                }
+
+                if let Some(status) = self.sync.status() {
+                    ui.separator();
+
+                    ui.label(status);
+                }
+
+                if let Some(status) = &self.transfer_status {
+                    ui.separator();
+
+                    ui.label(status);
+
+                    if ui.small_button("Dismiss").clicked() {
+                        self.transfer_status = None;
+                    }
+                }
             });
         });
 
+        self.highlights
+            .retain(|_, appeared| appeared.elapsed() < HIGHLIGHT_DURATION);
+
+        let highlighted: HashSet<PathBuf> = self.highlights.keys().cloned().collect();
+
+        let mut transfer_commands = Vec::new();
+
+        egui::SidePanel::right("transfers").show_animated(ctx, self.transfers.open, |ui| {
+            ui.heading("Transfers");
+
+            transfer_commands = self.transfers.show(ui);
+        });
+
+        for command in transfer_commands {
+            match (command, &self.connection) {
+                (TransferCommand::Pause(transfer), Some(_)) => {
+                    if let Some(id) = self.requests.take_download(transfer) {
+                        send_command(&self.this_sx, ConnectionCommand::Abort(id));
+                    }
+                }
+                (TransferCommand::Cancel { id, destination }, Some(_)) => send_command(
+                    &self.this_sx,
+                    ConnectionCommand::CancelDownload {
+                        id: self.requests.take_download(id),
+                        destination,
+                    },
+                ),
+                //Nothing is running without a connection, only the part has to be deleted
+                (TransferCommand::Cancel { destination, .. }, None) => {
+                    tokio::spawn(async move { discard_download(&destination).await });
+                }
+                (TransferCommand::Pause(_), None) => {}
+            }
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
+            if self.connection.is_some() {
+                ui.horizontal(|ui| {
+                    egui::ComboBox::from_label("Archive format")
+                        .selected_text(self.archive_format.extension())
+                        .show_ui(ui, |ui| {
+                            for format in ArchiveFormat::ALL {
+                                ui.selectable_value(
+                                    &mut self.archive_format,
+                                    format,
+                                    format.extension(),
+                                );
+                            }
+                        });
+
+                    ui.add_enabled_ui(!self.selection.is_empty(), |ui| {
+                        if ui
+                            .button(format!("Download selected ({})", self.selection.len()))
+                            .clicked()
+                        {
+                            let mut remote: Vec<PathBuf> = self.selection.iter().cloned().collect();
+                            remote.sort();
+
+                            download_archive(
+                                &self.this_sx,
+                                &mut self.requests,
+                                remote,
+                                self.archive_format,
+                            );
+                        }
+
+                        if ui.button("Clear selection").clicked() {
+                            self.selection.clear();
+                        }
+                    });
+                });
+
+                ui.separator();
+            }
+
             egui::ScrollArea::both()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
                     //iter over all added folders
                     for group in self.shared_folders.iter_mut() {
-                        ui.group(|ui| {
+                        let mut action = None;
+
+                        let response = ui.group(|ui| {
                             //Folder name and delete button
                             ui.horizontal(|ui| {
                                 //Folder name
@@ -164,86 +673,327 @@ impl eframe::App for Client {
                                     .size(20.),
                                 )
                                 .on_hover_text(format!("Full path: {:?}", group.get_path()));
+
+                                if let PathItem::Folder(folder) = group {
+                                    if folder.writable && ui.button("Upload").clicked() {
+                                        action =
+                                            Some(PathAction::UploadClicked(folder.path.clone()));
+                                    }
+
+                                    if ui.button("Download folder").clicked() {
+                                        action =
+                                            Some(PathAction::DownloadFolder(folder.path.clone()));
+                                    }
+
+                                    if ui.button("Mirror").clicked() {
+                                        action =
+                                            Some(PathAction::MirrorFolder(folder.path.clone()));
+                                    }
+
+                                    //The rest of the job is set up in the sync menu
+                                    if ui.button("Sync").clicked() {
+                                        self.sync.set_remote(folder.path.clone());
+                                    }
+                                }
                             });
 
                             if let PathItem::Folder(folder) = group {
-                                //Get pathbuf which we have clicked on
-                                let file_clicked_on = render_path(&mut folder.entries, ui);
-
-                                if let Some(path) = file_clicked_on {
-                                    let this_sx = self.this_sx.clone();
-
-                                    //Send requested path
-                                    tokio::spawn(async move {
-                                        let _ = this_sx
-                                            .send(Some(ClientRequest::FileRequest(dbg!(path))))
-                                            .await
-                                            .map_err(|err| dbg!(err));
-                                    });
+                                //Get what we have clicked on
+                                if let Some(clicked) = render_path(
+                                    &mut folder.entries,
+                                    &highlighted,
+                                    Some(&mut self.selection),
+                                    ui,
+                                ) {
+                                    action = Some(clicked);
                                 }
                             }
                         });
+
+                        //Files dropped onto the shared folder itself
+                        if let PathItem::Folder(folder) = group {
+                            if folder.writable
+                                && !matches!(action, Some(PathAction::FilesDropped { .. }))
+                            {
+                                if let Some(files) = dropped_files(ui, response.response.rect) {
+                                    action = Some(PathAction::FilesDropped {
+                                        folder: folder.path.clone(),
+                                        files,
+                                    });
+                                }
+                            }
+                        }
+
+                        match action {
+                            Some(PathAction::FolderOpened(path)) => {
+                                request_directory(&self.this_sx, &mut self.requests, path, 0);
+                            }
+                            Some(PathAction::UploadClicked(folder)) => {
+                                if let Some(files) = rfd::FileDialog::new()
+                                    .set_title("Upload files")
+                                    .pick_files()
+                                {
+                                    upload_files(
+                                        &self.this_sx,
+                                        &mut self.requests,
+                                        folder,
+                                        files,
+                                        false,
+                                    );
+                                }
+                            }
+                            Some(PathAction::FilesDropped { folder, files }) => {
+                                upload_files(
+                                    &self.this_sx,
+                                    &mut self.requests,
+                                    folder,
+                                    files,
+                                    false,
+                                );
+                            }
+                            Some(PathAction::DownloadFolder(folder)) => {
+                                download_archive(
+                                    &self.this_sx,
+                                    &mut self.requests,
+                                    vec![folder],
+                                    self.archive_format,
+                                );
+                            }
+                            Some(PathAction::MirrorFolder(path)) => {
+                                //The folders which have already been listed do not have to be listed again
+                                let folder =
+                                    find_folder_mut(std::slice::from_mut(group), &path).cloned();
+
+                                let destination = rfd::FileDialog::new()
+                                    .set_title("Mirror into")
+                                    .pick_folder();
+
+                                if let (Some(folder), Some(destination)) = (folder, destination) {
+                                    self.requests.start(
+                                        &self.this_sx,
+                                        ConnectionRequest::Mirror {
+                                            folder,
+                                            destination,
+                                        },
+                                        Pending::Mirror(path),
+                                    );
+                                }
+                            }
+
+                            Some(PathAction::FileClicked(path)) => {
+                                //Ask where to save the file before the download starts
+                                let destination = rfd::FileDialog::new()
+                                    .set_title("Save to")
+                                    .set_directory("/")
+                                    .set_file_name(
+                                        path.file_name().unwrap_or_default().to_string_lossy(),
+                                    )
+                                    .add_filter(
+                                        "File extension",
+                                        &[path
+                                            .extension()
+                                            .unwrap_or(path.file_stem().unwrap())
+                                            .to_os_string()
+                                            .to_string_lossy()],
+                                    )
+                                    .save_file();
+
+                                if let Some(destination) = destination {
+                                    self.transfers.enqueue(
+                                        self.connected_to.clone(),
+                                        path,
+                                        destination,
+                                    );
+
+                                    self.transfers.open = true;
+                                }
+                            }
+                            None => {}
+                        }
                     }
                 });
         });
 
-        if let Ok(struct_str) = self.main_rx.try_recv() {
-            if struct_str == "Invalid password!" {
-                let sx = self.this_sx.clone();
+        if self.connection.is_some() {
+            for (transfer, remote, destination) in self.transfers.due(&self.connected_to) {
+                self.requests.start(
+                    &self.this_sx,
+                    ConnectionRequest::Download {
+                        remote,
+                        destination,
+                    },
+                    Pending::Download(transfer),
+                );
+            }
 
-                //Destroy local connection
-                tokio::spawn(async move {
-                    sx.send(None).await.unwrap();
-                });
+            if let Some(job) = self.sync.due() {
+                let remote = job.remote.clone();
 
-                self.invalid_password = true;
+                self.requests.start(
+                    &self.this_sx,
+                    ConnectionRequest::Sync(job),
+                    Pending::Sync(remote),
+                );
+            }
+        }
 
-                self.connection = None;
-            } else {
-                self.connection = Some(self.this_sx.clone());
+        //Everything which has arrived is handled, so frequent progress does not hold back the rest
+        while let Ok(event) = self.main_rx.try_recv() {
+            match event {
+                ConnectionEvent::Connected(fingerprint) => {
+                    self.connection_error = None;
+
+                    if let Some(fingerprint) = &fingerprint {
+                        //Trust on first use, a mismatch would have failed the connection
+                        self.known_servers
+                            .entry(self.connected_to.clone())
+                            .or_insert_with(|| fingerprint.clone());
+                    }
 
-                match serde_json::from_str::<ServerReply>(&struct_str) {
-                    Ok(ok) => {
-                        match ok {
-                            ServerReply::List(list) => {
-                                self.invalid_password = false;
-                                self.shared_folders = list.list;
-                            }
-                            ServerReply::File(file) => {
-                                self.invalid_password = false;
-                                if let Some(err) = file.error {
-                                    dbg!(err);
-                                } else if let Some(file_bytes) = file.bytes {
-                                    //Handle download
-                                    let files = rfd::FileDialog::new()
-                                        .set_title("Save to")
-                                        .set_directory("/")
-                                        .add_filter(
-                                            "File extension",
-                                            &[file
-                                                .path
-                                                .extension()
-                                                .unwrap_or(file.path.file_stem().unwrap())
-                                                .to_os_string()
-                                                .to_string_lossy()],
-                                        )
-                                        .save_file();
+                    self.server_fingerprint = fingerprint;
+                }
+                ConnectionEvent::SharedFolders(list) => {
+                    self.connection = Some(self.this_sx.clone());
+                    self.invalid_password = false;
+                    self.shared_folders = list;
+                }
+                ConnectionEvent::InvalidPassword => {
+                    self.invalid_password = true;
 
-                                    if let Some(file_path) = files {
-                                        let _ = std::fs::write(file_path, file_bytes)
-                                            .map_err(|err| dbg!(err));
-                                    }
-                                }
-                            }
+                    self.disconnect();
+                }
+                ConnectionEvent::Error(err) => {
+                    self.connection_error = Some(err);
+
+                    self.reset_connection();
+                }
+                ConnectionEvent::Failed(err) => {
+                    self.request_error = Some(err);
+                }
+                ConnectionEvent::Redeemed { address, outcome } => {
+                    self.redeeming = false;
+
+                    self.redeem_status = Some(outcome.map(|(redeemed, fingerprint)| {
+                        if let Some(fingerprint) = fingerprint {
+                            //Trust on first use, like when connecting
+                            self.known_servers.entry(address).or_insert(fingerprint);
                         }
+
+                        //The link might have been a one-time link
+                        self.share_link.clear();
+
+                        format!(
+                            "Saved {} ({} bytes)",
+                            redeemed.destination.display(),
+                            redeemed.size
+                        )
+                    }));
+                }
+                ConnectionEvent::Changed(change) => {
+                    if let TreeChange::Created(item) | TreeChange::Renamed { to: item, .. } =
+                        &change
+                    {
+                        self.highlights.insert(item.get_path(), Instant::now());
+                    }
+
+                    //Items which are gone cannot be downloaded anymore
+                    if let TreeChange::Removed(path) | TreeChange::Renamed { from: path, .. } =
+                        &change
+                    {
+                        self.selection
+                            .retain(|selected| !selected.starts_with(path));
+                    }
+
+                    apply_change(&mut self.shared_folders, change);
+                }
+                ConnectionEvent::Progress { id, progress } => {
+                    if let Some(Pending::Download(transfer)) = self.requests.get(id) {
+                        self.transfers.progress(*transfer, progress);
                     }
-                    Err(err) => {
-                        dbg!(err);
+                }
+                ConnectionEvent::Finished { id, outcome } => {
+                    //Requests which have been aborted are not waited for anymore
+                    if let Some(pending) = self.requests.finish(id) {
+                        self.finished(pending, outcome);
                     }
                 }
             }
-        };
+        }
 
         ctx.request_repaint();
     }
 }
+
+///Asks the server for a page of the entries of a folder
+fn request_directory(
+    this_sx: &mpsc::Sender<Option<ConnectionCommand>>,
+    requests: &mut Requests,
+    path: PathBuf,
+    offset: u64,
+) {
+    requests.start(
+        this_sx,
+        ConnectionRequest::Request(ClientRequest::ListDirectory {
+            path: path.clone(),
+            offset,
+            limit: DIRECTORY_PAGE_SIZE,
+        }),
+        Pending::Listing(path),
+    );
+}
+
+///Asks the connection thread to upload the files into the folder, they are uploaded at the same time
+fn upload_files(
+    this_sx: &mpsc::Sender<Option<ConnectionCommand>>,
+    requests: &mut Requests,
+    folder: PathBuf,
+    files: Vec<PathBuf>,
+    overwrite: bool,
+) {
+    for source in files {
+        requests.start(
+            this_sx,
+            ConnectionRequest::Upload {
+                source: source.clone(),
+                folder: folder.clone(),
+                overwrite,
+            },
+            Pending::Upload {
+                source,
+                folder: folder.clone(),
+            },
+        );
+    }
+}
+
+///Asks where to save the archive, then asks the connection thread to download it
+fn download_archive(
+    this_sx: &mpsc::Sender<Option<ConnectionCommand>>,
+    requests: &mut Requests,
+    remote: Vec<PathBuf>,
+    format: ArchiveFormat,
+) {
+    //A single folder keeps its name, a selection of several items does not have one
+    let name = match remote.as_slice() {
+        [path] => path.file_name().unwrap_or_default().to_string_lossy(),
+        _ => "download".into(),
+    };
+
+    let destination = rfd::FileDialog::new()
+        .set_title("Save archive to")
+        .set_file_name(format!("{}.{}", name, format.extension()))
+        .add_filter("Archive", &[format.extension()])
+        .save_file();
+
+    if let Some(destination) = destination {
+        requests.start(
+            this_sx,
+            ConnectionRequest::DownloadArchive {
+                remote,
+                format,
+                destination: destination.clone(),
+            },
+            Pending::Archive(destination),
+        );
+    }
+}
@@ -1,67 +1,514 @@
-use messages::HostRequest;
+use common_definitions::messages::{
+    upload_chunk::Chunk, HostReply, HostRequest, TreeEventKind, UploadChunk, UploadHeader,
+    WatchRequest,
+};
 
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
 
+use tokio::{
+    fs::File,
+    io::AsyncReadExt,
+    sync::mpsc::{self, Receiver, Sender},
+    task::JoinHandle,
+};
+use tokio_stream::wrappers::ReceiverStream;
 
-use tokio::sync::mpsc::{Receiver, Sender};
+use common_definitions::{
+    auth::{Session, SessionClient},
+    download::{
+        discard_download, download_archive, download_file_with_progress, redeem_share_link,
+        DownloadProgress, RedeemedLink,
+    },
+    error::ServiceError,
+    mirror::{mirror_folder, MirrorReport},
+    sync::{run_sync, SyncJob, SyncReport},
+    tls::{connect_channel, ServerVerification},
+    ArchiveFormat, ClientRequest, FolderItem, PathItem, ServerReply, TreeChange,
+};
 
+///The size of a single chunk sent by `UploadFile`
+const UPLOAD_CHUNK_SIZE: usize = 256 * 1024;
 
-pub mod messages {
-    tonic::include_proto!("file_hosting");
+///How often a running download reports its progress to the main thread
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+///Identifies a request, every event it causes is tagged with it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RequestId(pub u64);
+
+///Instructions the main thread can give to the connection thread
+#[derive(Debug)]
+pub enum ConnectionCommand {
+    ///Start a request next to the ones which are already in flight
+    Start {
+        id: RequestId,
+        request: ConnectionRequest,
+    },
+    ///Stop an in-flight request, nothing more is sent back for it
+    Abort(RequestId),
+    ///Stop the download if it is still in flight, and delete what has been downloaded of it
+    CancelDownload {
+        id: Option<RequestId>,
+        destination: PathBuf,
+    },
+}
+
+///The requests the connection thread can run, they all share the same connection
+#[derive(Debug)]
+pub enum ConnectionRequest {
+    ///Forward a request to the server
+    Request(ClientRequest),
+    ///Stream a file from the server straight to the disk, the progress is sent back while it runs
+    Download {
+        ///Path of the file on the server
+        remote: PathBuf,
+        ///Where the file should be written to
+        destination: PathBuf,
+    },
+    ///Stream an archive of files and folders, which the server generates while sending it
+    DownloadArchive {
+        ///Paths of the files and folders on the server
+        remote: Vec<PathBuf>,
+        format: ArchiveFormat,
+        ///Where the archive should be written to
+        destination: PathBuf,
+    },
+    ///Recreate a remote folder inside of a local one, files which are already identical are skipped
+    Mirror {
+        ///The folder as it is in the tree, folders which have not been listed yet are listed while mirroring
+        folder: FolderItem,
+        destination: PathBuf,
+    },
+    ///Bring a local folder up to date with a remote one
+    Sync(SyncJob),
+    ///Stream a file from the disk into a writable folder on the server
+    Upload {
+        ///Path of the file on the disk
+        source: PathBuf,
+        ///The folder on the server the file is saved into
+        folder: PathBuf,
+        ///Replace the file on the server if it already exists
+        overwrite: bool,
+    },
+}
+
+///What a request has produced
+#[derive(Debug)]
+pub enum Response {
+    ///The server's reply to a `Request` or an `Upload`
+    Reply(ServerReply),
+    ///A file or an archive has been downloaded, contains the amount of bytes written
+    Downloaded(u64),
+    ///A folder has been mirrored, some of its files might have failed
+    Mirrored(MirrorReport),
+    Synced(SyncReport),
+}
+
+///Why a request has failed
+#[derive(Debug)]
+pub enum RequestError {
+    ///The server has refused or failed the request
+    Service(ServiceError),
+    ///The request has failed on our side, like a file which could not be written
+    Failed(String),
+}
+
+impl RequestError {
+    ///The password is wrong, or the session has expired or been ended on the server
+    pub fn is_unauthenticated(&self) -> bool {
+        matches!(
+            self,
+            RequestError::Service(ServiceError::Unauthenticated(_))
+        )
+    }
+
+    ///The uploaded file is already on the server, it can be uploaded again to overwrite it
+    pub fn is_already_exists(&self) -> bool {
+        matches!(self, RequestError::Service(ServiceError::AlreadyExists(_)))
+    }
 }
 
-use common_definitions::ClientRequest;
+impl Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RequestError::Service(err) => err.fmt(f),
+            RequestError::Failed(message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl From<anyhow::Error> for RequestError {
+    fn from(value: anyhow::Error) -> Self {
+        match ServiceError::find(&value) {
+            Some(err) => RequestError::Service(err),
+            None => RequestError::Failed(format!("{:#}", value)),
+        }
+    }
+}
+
+impl From<tonic::Status> for RequestError {
+    fn from(value: tonic::Status) -> Self {
+        RequestError::Service(value.into())
+    }
+}
+
+///What the connection thread sends back to the main thread
+#[derive(Debug)]
+pub enum ConnectionEvent {
+    ///The encrypted channel has been established, contains the fingerprint of the server's certificate if it was checked by pinning
+    Connected(Option<String>),
+    ///The shared folders, sent once we are logged in and whenever the server asks us to list them again
+    SharedFolders(Vec<PathItem>),
+    ///The server has rejected the password, the connection has been closed
+    InvalidPassword,
+    ///The connection could not be established or it has broken
+    Error(String),
+    ///Something has failed without closing the connection, it is shown like a failed request
+    Failed(String),
+    ///Something has changed inside of the shared folders
+    Changed(TreeChange),
+    ///How far a download has got
+    Progress {
+        id: RequestId,
+        progress: DownloadProgress,
+    },
+    ///A request has completed, or why it has failed
+    Finished {
+        id: RequestId,
+        outcome: Result<Response, RequestError>,
+    },
+    ///The file of a share link has been downloaded from the server at the address, or why it has failed
+    Redeemed {
+        address: String,
+        outcome: Result<(RedeemedLink, Option<String>), String>,
+    },
+}
 
-//We use the reciver to get what the main thread wants to recive, we use the sender to send back the response from the server
+///Stops the task when it is dropped, so it does not outlive the connection
+struct AbortOnDrop<T = ()>(JoinHandle<T>);
+
+impl<T> AbortOnDrop<T> {
+    ///Aborts the task and waits until it is gone
+    async fn stop(&mut self) {
+        self.0.abort();
+
+        let _ = (&mut self.0).await;
+    }
+}
+
+impl<T> Drop for AbortOnDrop<T> {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+///Downloads the file of a share link over a connection of its own, no user is logged in
+///
+///Returns the fingerprint of the server's certificate along with the file, if it was checked by pinning
+pub async fn redeem_link(
+    ip: String,
+    token: String,
+    verification: ServerVerification,
+    destination: PathBuf,
+) -> anyhow::Result<(RedeemedLink, Option<String>)> {
+    let (channel, fingerprint) = connect_channel(&ip, verification).await?;
+
+    let mut client = Session::with_share_link(channel, &token).client();
+
+    let redeemed = redeem_share_link(&mut client, &destination).await?;
+
+    Ok((redeemed, fingerprint))
+}
+
+//We use the reciver to get what the main thread wants, every request runs in its own task and sends back what it has produced
 
 pub async fn connect(
     ip: String,
+    user: String,
     password: String,
-    main_sx: Sender<String>,
+    verification: ServerVerification,
+    main_sx: Sender<ConnectionEvent>,
 
-    //We add an option to client_request therefor we can shut down gracefully, when we ask for a None
-    mut this_rx: Receiver<Option<ClientRequest>>,
+    //We add an option to the command therefor we can shut down gracefully, when we ask for a None
+    mut this_rx: Receiver<Option<ConnectionCommand>>,
 ) -> anyhow::Result<()> {
-    let mut client =
-        messages::serving_client::ServingClient::connect(format!("http://{}", ip)).await?;
-
-    let list = client
-        .server_provide(HostRequest {
-            serialized_request: { ClientRequest::ListRequest.serialize() },
-            password: password.clone(),
-        })
-        .await?
-        .into_inner()
-        .serialized_reply;
+    let (channel, fingerprint) = connect_channel(&ip, verification).await?;
+
+    main_sx
+        .send(ConnectionEvent::Connected(fingerprint))
+        .await?;
+
+    //The password is only used to log in, the requests carry the session token
+    let session = match Session::login(channel, &user, &password)
+        .await
+        .map_err(RequestError::from)
+    {
+        Ok(session) => session,
+        Err(err) if err.is_unauthenticated() => {
+            main_sx.send(ConnectionEvent::InvalidPassword).await?;
+
+            return Ok(());
+        }
+        Err(err) => return Err(err.into()),
+    };
+
+    //Clones share the channel, so every request in flight is multiplexed over the same connection
+    let mut client = session.client();
+
+    //The list is kept up to date by the changes the server pushes
+    let list = shared_folders(&mut client).await?;
+
+    main_sx.send(ConnectionEvent::SharedFolders(list)).await?;
+
+    let watch_sx = main_sx.clone();
+    let watch_client = client.clone();
+
+    let _watch = AbortOnDrop(tokio::spawn(async move {
+        //Servers which cannot watch their folders are still usable, but the tree is not kept up to date anymore
+        if let Err(err) = watch_changes(watch_client, watch_sx.clone()).await {
+            let _ = watch_sx
+                .send(ConnectionEvent::Failed(format!(
+                    "Stopped watching for changes: {}",
+                    RequestError::from(err)
+                )))
+                .await;
+        }
+    }));
 
-    //Send back the list, this wont be mutable this is a constant
-    main_sx.send(list).await?;
+    let mut keep_alive = AbortOnDrop(tokio::spawn(session.clone().keep_alive()));
+
+    //The requests in flight by their id, they are stopped when the connection is closed
+    let mut in_flight: HashMap<RequestId, AbortOnDrop> = HashMap::new();
 
-    //download requests here
     loop {
-        //Block until main asks us for something, unwrap is called cuz of the lib
-        let main_need = this_rx.recv().await;
-
-        if let Some(main_need) = main_need {
-            //if the main thread asked us for a None we exit
-            if let Some(need) = main_need {
-                //Send whatever we get back to the main thread
-                main_sx
-                    .send(
-                        client
-                            .server_provide(HostRequest {
-                                serialized_request: need.serialize(),
-                                password: password.clone(),
-                            })
-                            .await?
-                            .into_inner()
-                            .serialized_reply,
-                    )
-                    .await?;
-            } else {
-                break;
+        //Block until main asks us for something, a closed channel is the same as asking for a None
+        let command = tokio::select! {
+            command = this_rx.recv() => command,
+            //Refreshing only stops once the server has ended the session
+            ended = &mut keep_alive.0 => return Err(ended?.context("The session has ended")),
+        };
+
+        let Some(Some(command)) = command else {
+            break;
+        };
+
+        in_flight.retain(|_, task| !task.0.is_finished());
+
+        match command {
+            ConnectionCommand::Start { id, request } => {
+                let task = tokio::spawn(run_request(client.clone(), main_sx.clone(), id, request));
+
+                in_flight.insert(id, AbortOnDrop(task));
+            }
+            ConnectionCommand::Abort(id) => {
+                if let Some(mut task) = in_flight.remove(&id) {
+                    task.stop().await;
+                }
+            }
+            ConnectionCommand::CancelDownload { id, destination } => {
+                //The task has to be gone before its part is deleted, or it would keep writing to it
+                if let Some(mut task) = id.and_then(|id| in_flight.remove(&id)) {
+                    task.stop().await;
+                }
+
+                discard_download(&destination).await;
+            }
+        }
+    }
+
+    //The token would stay valid until it expires
+    session.logout().await
+}
+
+///Runs a single request and sends back its outcome tagged with its id
+async fn run_request(
+    mut client: SessionClient,
+    main_sx: Sender<ConnectionEvent>,
+    id: RequestId,
+    request: ConnectionRequest,
+) {
+    let outcome = match request {
+        ConnectionRequest::Request(request) => server_provide(&mut client, request).await,
+        ConnectionRequest::Download {
+            remote,
+            destination,
+        } => {
+            let mut last_report: Option<Instant> = None;
+
+            download_file_with_progress(&mut client, &remote, &destination, |progress| {
+                if last_report.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL) {
+                    last_report = Some(Instant::now());
+
+                    //A full channel only delays the progress, the next report catches up
+                    let _ = main_sx.try_send(ConnectionEvent::Progress { id, progress });
+                }
+            })
+            .await
+            .map(Response::Downloaded)
+            .map_err(RequestError::from)
+        }
+        ConnectionRequest::DownloadArchive {
+            remote,
+            format,
+            destination,
+        } => download_archive(&mut client, &remote, format, &destination)
+            .await
+            .map(Response::Downloaded)
+            .map_err(RequestError::from),
+        ConnectionRequest::Mirror {
+            folder,
+            destination,
+        } => mirror_folder(&mut client, &folder, &destination)
+            .await
+            .map(Response::Mirrored)
+            .map_err(RequestError::from),
+        ConnectionRequest::Sync(job) => run_sync(&mut client, &job)
+            .await
+            .map(Response::Synced)
+            .map_err(RequestError::from),
+        ConnectionRequest::Upload {
+            source,
+            folder,
+            overwrite,
+        } => {
+            match upload_file(&mut client, source, folder, overwrite).await {
+                //The reply contains the uploaded file, or why it has been rejected
+                Ok(reply) => server_reply(reply).map(Response::Reply),
+                Err(err) => Err(err.into()),
             }
         }
+    };
+
+    let _ = main_sx
+        .send(ConnectionEvent::Finished { id, outcome })
+        .await;
+}
+
+async fn server_provide(
+    client: &mut SessionClient,
+    request: ClientRequest,
+) -> Result<Response, RequestError> {
+    let reply = client
+        .server_provide(HostRequest::new(request))
+        .await?
+        .into_inner();
+
+    server_reply(reply).map(Response::Reply)
+}
+
+///Asks for the shared folders
+async fn shared_folders(client: &mut SessionClient) -> Result<Vec<PathItem>, RequestError> {
+    match server_provide(client, ClientRequest::ListRequest).await? {
+        Response::Reply(ServerReply::List(list)) => Ok(list.list),
+        reply => Err(RequestError::Failed(format!(
+            "Unexpected reply: {:?}",
+            reply
+        ))),
+    }
+}
+
+///Turns the reply into the typed reply, the server sends its errors as statuses instead
+fn server_reply(reply: HostReply) -> Result<ServerReply, RequestError> {
+    Ok(reply.into_reply()?)
+}
+
+///Forwards the changes the server pushes to the main thread
+async fn watch_changes(
+    mut client: SessionClient,
+    main_sx: Sender<ConnectionEvent>,
+) -> anyhow::Result<()> {
+    let mut stream = client.watch(WatchRequest {}).await?.into_inner();
+
+    while let Some(event) = stream.message().await? {
+        //We have missed some changes, so the whole tree is asked for again
+        if event.kind() == TreeEventKind::Resync {
+            let list = shared_folders(&mut client).await?;
+
+            main_sx.send(ConnectionEvent::SharedFolders(list)).await?;
+
+            continue;
+        }
+
+        let event = match TreeChange::try_from(event) {
+            Ok(change) => ConnectionEvent::Changed(change),
+            Err(err) => {
+                ConnectionEvent::Failed(format!("The server has sent an invalid change: {:#}", err))
+            }
+        };
+
+        main_sx.send(event).await?;
     }
 
     Ok(())
 }
+
+///Sends the header and then the file chunk by chunk, the file is read while it is being sent
+async fn upload_file(
+    client: &mut SessionClient,
+    source: PathBuf,
+    folder: PathBuf,
+    overwrite: bool,
+) -> anyhow::Result<HostReply> {
+    let mut file = File::open(&source).await?;
+
+    let header = UploadHeader {
+        folder: folder.to_string_lossy().into_owned(),
+        file_name: source
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned(),
+        file_size: file.metadata().await?.len(),
+        overwrite,
+    };
+
+    //The channel is bounded so we only read ahead a few chunks of a slow connection
+    let (sx, rx) = mpsc::channel(4);
+
+    sx.send(UploadChunk {
+        chunk: Some(Chunk::Header(header)),
+    })
+    .await?;
+
+    let reader = tokio::spawn(async move {
+        loop {
+            let mut data = vec![0; UPLOAD_CHUNK_SIZE];
+
+            match file.read(&mut data).await {
+                Ok(0) => return Ok(()),
+                Ok(read) => {
+                    data.truncate(read);
+
+                    if sx
+                        .send(UploadChunk {
+                            chunk: Some(Chunk::Data(data)),
+                        })
+                        .await
+                        .is_err()
+                    {
+                        //The server has stopped receiving
+                        return Ok(());
+                    }
+                }
+                //Ending the stream early makes the server reject the upload
+                Err(err) => return Err(err),
+            }
+        }
+    });
+
+    let reply = client.upload_file(ReceiverStream::new(rx)).await;
+
+    //The server only knows that the upload has ended early, the reason is on our side
+    if let Ok(Err(err)) = reader.await {
+        return Err(anyhow::Error::new(err).context(format!("Failed to read {}", source.display())));
+    }
+
+    Ok(reply?.into_inner())
+}
@@ -0,0 +1,101 @@
+use std::{collections::HashMap, fmt::Display, path::PathBuf};
+
+use tokio::sync::mpsc;
+
+use crate::ui::backend::client::{ConnectionCommand, ConnectionRequest, RequestId};
+
+///What an in-flight request is for, so its outcome can be handled in context
+#[derive(Debug)]
+pub enum Pending {
+    ///A page of the entries of a folder
+    Listing(PathBuf),
+    ///A file of the transfers panel, by its id there
+    Download(u64),
+    ///An archive, by where it is saved to
+    Archive(PathBuf),
+    ///A local file which is uploaded, with the folder on the server it is uploaded into
+    Upload { source: PathBuf, folder: PathBuf },
+    ///A remote folder which is mirrored
+    Mirror(PathBuf),
+    ///The sync job of a remote folder
+    Sync(PathBuf),
+}
+
+impl Display for Pending {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Pending::Listing(path) => write!(f, "Failed to list {}", path.display()),
+            Pending::Download(_) => f.write_str("Failed to download"),
+            Pending::Archive(destination) => {
+                write!(f, "Failed to download {}", destination.display())
+            }
+            Pending::Upload { source, .. } => write!(f, "Failed to upload {}", source.display()),
+            Pending::Mirror(path) => write!(f, "Failed to mirror {}", path.display()),
+            Pending::Sync(path) => write!(f, "Failed to sync {}", path.display()),
+        }
+    }
+}
+
+///Hands out the ids of the requests, and remembers what each of them is for until it has finished
+#[derive(Default)]
+pub struct Requests {
+    ///Ids are never reused, so a late event of a previous connection cannot be mistaken for a new one
+    next_id: u64,
+    pending: HashMap<RequestId, Pending>,
+}
+
+impl Requests {
+    ///Sends the request to the connection thread, the events it causes are tagged with the returned id
+    pub fn start(
+        &mut self,
+        this_sx: &mpsc::Sender<Option<ConnectionCommand>>,
+        request: ConnectionRequest,
+        pending: Pending,
+    ) -> RequestId {
+        let id = RequestId(self.next_id);
+
+        self.next_id += 1;
+        self.pending.insert(id, pending);
+
+        send_command(this_sx, ConnectionCommand::Start { id, request });
+
+        id
+    }
+
+    pub fn get(&self, id: RequestId) -> Option<&Pending> {
+        self.pending.get(&id)
+    }
+
+    ///Forgets the request, the events which still arrive for it are ignored
+    pub fn finish(&mut self, id: RequestId) -> Option<Pending> {
+        self.pending.remove(&id)
+    }
+
+    ///Forgets the request which is running the download of the transfers panel, returns its id so it can be stopped
+    pub fn take_download(&mut self, transfer: u64) -> Option<RequestId> {
+        let id = self
+            .pending
+            .iter()
+            .find(|(_, pending)| matches!(pending, Pending::Download(id) if *id == transfer))
+            .map(|(id, _)| *id)?;
+
+        self.pending.remove(&id);
+
+        Some(id)
+    }
+
+    ///The requests of a closed connection will not finish
+    pub fn clear(&mut self) {
+        self.pending.clear();
+    }
+}
+
+///Sends the command to the connection thread without waiting
+pub fn send_command(this_sx: &mpsc::Sender<Option<ConnectionCommand>>, command: ConnectionCommand) {
+    let this_sx = this_sx.clone();
+
+    tokio::spawn(async move {
+        //The connection thread has stopped, its requests are forgotten once the main thread hears about it
+        let _ = this_sx.send(Some(command)).await;
+    });
+}
@@ -0,0 +1,199 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common_definitions::sync::{SyncJob, SyncReport};
+use egui::{Color32, RichText};
+
+///The settings of the sync job, and the outcome of its last run
+#[derive(serde::Deserialize, serde::Serialize, Default)]
+#[serde(default)]
+pub struct SyncPanel {
+    ///Full path of the folder on the server
+    remote: String,
+    local: Option<PathBuf>,
+    ///Comma separated globs, matched against paths relative to the remote folder
+    include: String,
+    exclude: String,
+    delete: bool,
+    dry_run: bool,
+    ///Minutes between the runs, the job only runs once if it is 0
+    interval: u32,
+    ///When the job runs next, None if it has been stopped
+    #[serde(skip)]
+    next_run: Option<Instant>,
+    ///The connection thread is running the job
+    #[serde(skip)]
+    running: bool,
+    #[serde(skip)]
+    last_run: Option<Result<SyncReport, String>>,
+}
+
+impl SyncPanel {
+    pub fn set_remote(&mut self, remote: PathBuf) {
+        self.remote = remote.to_string_lossy().into_owned();
+    }
+
+    ///Returns the job if it is time to run it again
+    pub fn due(&mut self) -> Option<SyncJob> {
+        if self.running || self.next_run.is_none_or(|next| next > Instant::now()) {
+            return None;
+        }
+
+        let local = self.local.clone()?;
+
+        self.running = true;
+
+        Some(SyncJob {
+            remote: PathBuf::from(&self.remote),
+            local,
+            include: split_globs(&self.include),
+            exclude: split_globs(&self.exclude),
+            delete: self.delete,
+            dry_run: self.dry_run,
+        })
+    }
+
+    ///The connection thread has finished the job, it is scheduled again if it runs on an interval
+    pub fn finished(&mut self, outcome: Result<SyncReport, String>) {
+        self.running = false;
+        self.last_run = Some(outcome);
+
+        let interval = Duration::from_secs(self.interval as u64 * 60);
+
+        self.next_run = self
+            .next_run
+            .filter(|_| self.interval > 0)
+            .map(|_| Instant::now() + interval);
+    }
+
+    ///A running job still finishes, but it is not scheduled again
+    pub fn stop(&mut self) {
+        self.next_run = None;
+    }
+
+    ///The job the connection was running is gone with it
+    pub fn disconnected(&mut self) {
+        self.running = false;
+        self.next_run = None;
+    }
+
+    ///A short description of what the job is doing, for the status bar
+    pub fn status(&self) -> Option<String> {
+        if self.running {
+            return Some("Syncing...".to_string());
+        }
+
+        self.next_run.map(|next| {
+            format!(
+                "Next sync in {}",
+                humantime::format_duration(Duration::from_secs(
+                    next.saturating_duration_since(Instant::now()).as_secs()
+                ))
+            )
+        })
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, connected: bool) {
+        ui.label("Remote folder (full path on the server)");
+        ui.text_edit_singleline(&mut self.remote);
+
+        ui.label("Local folder");
+        ui.horizontal(|ui| {
+            match &self.local {
+                Some(path) => ui.label(path.to_string_lossy()),
+                None => ui.label("None"),
+            };
+
+            if ui.button("Select").clicked() {
+                if let Some(path) = rfd::FileDialog::new().set_title("Sync into").pick_folder() {
+                    self.local = Some(path);
+                }
+            }
+        });
+
+        ui.label("Include (comma separated globs, everything if empty)");
+        ui.text_edit_singleline(&mut self.include);
+
+        ui.label("Exclude (comma separated globs)");
+        ui.text_edit_singleline(&mut self.exclude);
+
+        ui.checkbox(&mut self.delete, "Delete files removed on the server");
+        ui.checkbox(
+            &mut self.dry_run,
+            "Dry run, only report the planned changes",
+        );
+
+        ui.horizontal(|ui| {
+            ui.label("Repeat every (minutes, 0 runs once)");
+            ui.add(egui::widgets::DragValue::new(&mut self.interval).clamp_range(0..=1440));
+        });
+
+        ui.separator();
+
+        ui.horizontal(|ui| {
+            let ready = connected && self.local.is_some() && !self.remote.is_empty();
+
+            ui.add_enabled_ui(ready && self.next_run.is_none() && !self.running, |ui| {
+                if ui.button("Start").clicked() {
+                    self.next_run = Some(Instant::now());
+                }
+            });
+
+            ui.add_enabled_ui(self.next_run.is_some(), |ui| {
+                if ui.button("Stop").clicked() {
+                    self.stop();
+                }
+            });
+
+            if let Some(status) = self.status() {
+                ui.label(status);
+            }
+        });
+
+        match &self.last_run {
+            Some(Ok(report)) => {
+                ui.separator();
+
+                ui.label(format!(
+                    "{}{} changes, {} files up to date, {} KB downloaded",
+                    if report.dry_run { "Dry run: " } else { "" },
+                    report.actions.len(),
+                    report.unchanged,
+                    report.bytes / 1024
+                ));
+
+                egui::ScrollArea::vertical()
+                    .max_height(200.)
+                    .show(ui, |ui| {
+                        for action in &report.actions {
+                            ui.label(action.to_string());
+                        }
+
+                        for (path, err) in &report.failed {
+                            ui.label(
+                                RichText::from(format!("{}: {}", path.display(), err))
+                                    .color(Color32::RED),
+                            );
+                        }
+                    });
+            }
+            Some(Err(err)) => {
+                ui.separator();
+
+                ui.label(RichText::from(err).color(Color32::RED));
+            }
+            None => {}
+        }
+    }
+}
+
+fn split_globs(globs: &str) -> Vec<String> {
+    globs
+        .split(',')
+        .map(str::trim)
+        .filter(|glob| !glob.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,327 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use common_definitions::download::DownloadProgress;
+use egui::{Color32, RichText};
+
+///How much a new throughput sample counts, the rest is the previous estimate
+const RATE_SMOOTHING: f64 = 0.3;
+
+///The queue of downloads, it is saved with the rest of the settings so it survives a restart
+#[derive(serde::Deserialize, serde::Serialize)]
+#[serde(default)]
+pub struct TransfersPanel {
+    downloads: Vec<Download>,
+    ///The amount of downloads which run at the same time
+    parallel: usize,
+    next_id: u64,
+    ///The panel is shown next to the tree
+    pub open: bool,
+}
+
+impl Default for TransfersPanel {
+    fn default() -> Self {
+        Self {
+            downloads: Vec::new(),
+            parallel: 3,
+            next_id: 0,
+            open: false,
+        }
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Clone, PartialEq, Debug)]
+pub enum TransferState {
+    Queued,
+    Running,
+    Paused,
+    Done,
+    Failed(String),
+}
+
+#[derive(serde::Deserialize, serde::Serialize)]
+struct Download {
+    id: u64,
+    ///The address of the server the file is on, it is only downloaded while we are connected to it
+    server: String,
+    remote: PathBuf,
+    destination: PathBuf,
+    state: TransferState,
+    written: u64,
+    total: u64,
+    ///Bytes per second, smoothed over the last samples
+    #[serde(skip)]
+    rate: f64,
+    ///When the last progress arrived, and how much had been written then
+    #[serde(skip)]
+    last_sample: Option<(Instant, u64)>,
+}
+
+impl Download {
+    fn is_finished(&self) -> bool {
+        matches!(self.state, TransferState::Done | TransferState::Failed(_))
+    }
+
+    fn eta(&self) -> Option<Duration> {
+        (self.rate > 0. && self.total > self.written)
+            .then(|| Duration::from_secs(((self.total - self.written) as f64 / self.rate) as u64))
+    }
+}
+
+///What the user has asked the connection thread to do with a download
+pub enum TransferCommand {
+    ///Stop the running download, what has been downloaded is kept so it can be resumed
+    Pause(u64),
+    ///Stop the download and delete what has been downloaded
+    Cancel { id: u64, destination: PathBuf },
+}
+
+impl TransfersPanel {
+    pub fn enqueue(&mut self, server: String, remote: PathBuf, destination: PathBuf) {
+        self.downloads.push(Download {
+            id: self.next_id,
+            server,
+            remote,
+            destination,
+            state: TransferState::Queued,
+            written: 0,
+            total: 0,
+            rate: 0.,
+            last_sample: None,
+        });
+
+        self.next_id += 1;
+    }
+
+    ///Marks as many queued downloads of the server as running as the limit allows, returns the ones which have to be started
+    pub fn due(&mut self, server: &str) -> Vec<(u64, PathBuf, PathBuf)> {
+        let running = self
+            .downloads
+            .iter()
+            .filter(|download| download.state == TransferState::Running)
+            .count();
+
+        self.downloads
+            .iter_mut()
+            .filter(|download| download.state == TransferState::Queued && download.server == server)
+            .take(self.parallel.saturating_sub(running))
+            .map(|download| {
+                download.state = TransferState::Running;
+                download.rate = 0.;
+                download.last_sample = None;
+
+                (
+                    download.id,
+                    download.remote.clone(),
+                    download.destination.clone(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn progress(&mut self, id: u64, progress: DownloadProgress) {
+        let Some(download) = self.find(id) else {
+            return;
+        };
+
+        let now = Instant::now();
+
+        if let Some((sampled, written)) = download.last_sample {
+            let elapsed = now.duration_since(sampled).as_secs_f64();
+
+            if elapsed > 0. {
+                let rate = progress.written.saturating_sub(written) as f64 / elapsed;
+
+                download.rate = if download.rate == 0. {
+                    rate
+                } else {
+                    download.rate * (1. - RATE_SMOOTHING) + rate * RATE_SMOOTHING
+                };
+            }
+        }
+
+        download.last_sample = Some((now, progress.written));
+        download.written = progress.written;
+        download.total = progress.total;
+    }
+
+    ///The connection thread has stopped the download, a download which has been paused in the meantime stays paused
+    pub fn finished(&mut self, id: u64, outcome: Result<u64, String>) {
+        let Some(download) = self.find(id) else {
+            return;
+        };
+
+        match outcome {
+            Ok(written) => {
+                download.state = TransferState::Done;
+                download.written = written;
+                download.total = written;
+            }
+            Err(err) if download.state == TransferState::Running => {
+                download.state = TransferState::Failed(err);
+            }
+            Err(_) => {}
+        }
+
+        download.rate = 0.;
+    }
+
+    ///The downloads the connection was running are resumed once we are connected again
+    pub fn disconnected(&mut self) {
+        for download in &mut self.downloads {
+            if download.state == TransferState::Running {
+                download.state = TransferState::Queued;
+                download.rate = 0.;
+            }
+        }
+    }
+
+    ///The amount of downloads which have not finished yet
+    pub fn pending(&self) -> usize {
+        self.downloads
+            .iter()
+            .filter(|download| !download.is_finished())
+            .count()
+    }
+
+    fn find(&mut self, id: u64) -> Option<&mut Download> {
+        self.downloads.iter_mut().find(|download| download.id == id)
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) -> Vec<TransferCommand> {
+        let mut commands = Vec::new();
+
+        ui.horizontal(|ui| {
+            ui.label("Parallel downloads");
+            ui.add(egui::widgets::DragValue::new(&mut self.parallel).clamp_range(1..=16));
+
+            if ui.button("Clear finished").clicked() {
+                self.downloads.retain(|download| !download.is_finished());
+            }
+        });
+
+        ui.separator();
+
+        if self.downloads.is_empty() {
+            ui.label("Click a file to download it");
+        }
+
+        let mut removed = Vec::new();
+
+        egui::ScrollArea::vertical().show(ui, |ui| {
+            for download in &mut self.downloads {
+                ui.label(
+                    download
+                        .remote
+                        .file_name()
+                        .unwrap_or_default()
+                        .to_string_lossy(),
+                )
+                .on_hover_text(format!(
+                    "{} on {}\nSaved to {}",
+                    download.remote.display(),
+                    download.server,
+                    download.destination.display()
+                ));
+
+                let fraction = if download.total > 0 {
+                    download.written as f32 / download.total as f32
+                } else if download.state == TransferState::Done {
+                    1.
+                } else {
+                    0.
+                };
+
+                ui.add(egui::ProgressBar::new(fraction).text(format!(
+                    "{} / {}",
+                    format_bytes(download.written),
+                    format_bytes(download.total)
+                )));
+
+                ui.horizontal(|ui| {
+                    match &download.state {
+                        TransferState::Queued => {
+                            ui.label("Queued");
+                        }
+                        TransferState::Running => {
+                            ui.label(format!("{}/s", format_bytes(download.rate as u64)));
+
+                            if let Some(eta) = download.eta() {
+                                ui.label(format!("{} left", humantime::format_duration(eta)));
+                            }
+                        }
+                        TransferState::Paused => {
+                            ui.label("Paused");
+                        }
+                        TransferState::Done => {
+                            ui.label(RichText::from("Done").color(Color32::GREEN));
+                        }
+                        TransferState::Failed(err) => {
+                            ui.label(RichText::from("Failed").color(Color32::RED))
+                                .on_hover_text(err);
+                        }
+                    }
+
+                    match download.state {
+                        TransferState::Queued | TransferState::Running => {
+                            if ui.small_button("Pause").clicked() {
+                                if download.state == TransferState::Running {
+                                    commands.push(TransferCommand::Pause(download.id));
+                                }
+
+                                download.state = TransferState::Paused;
+                                download.rate = 0.;
+                            }
+                        }
+                        TransferState::Paused | TransferState::Failed(_) => {
+                            if ui.small_button("Resume").clicked() {
+                                download.state = TransferState::Queued;
+                            }
+                        }
+                        TransferState::Done => {}
+                    }
+
+                    if download.is_finished() {
+                        if ui.small_button("Remove").clicked() {
+                            removed.push(download.id);
+                        }
+                    } else if ui.small_button("Cancel").clicked() {
+                        commands.push(TransferCommand::Cancel {
+                            id: download.id,
+                            destination: download.destination.clone(),
+                        });
+
+                        removed.push(download.id);
+                    }
+                });
+
+                ui.separator();
+            }
+        });
+
+        self.downloads
+            .retain(|download| !removed.contains(&download.id));
+
+        commands
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut size = bytes as f64;
+    let mut unit = 0;
+
+    while size >= 1024. && unit < UNITS.len() - 1 {
+        size /= 1024.;
+        unit += 1;
+    }
+
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
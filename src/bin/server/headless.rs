@@ -0,0 +1,615 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Context;
+use common_definitions::{
+    auth::{hash_password, is_password, random_bytes},
+    tls::ServerIdentity,
+    to_hex, write_private_file, FolderItem, PathItem,
+};
+use tokio::sync::mpsc;
+
+use crate::ui::backend::{
+    accounts::{Access, Accounts, AccountsConfig, UserAccount},
+    api_keys::{self, ApiKey, ApiKeyId, ApiKeys, Operation},
+    audit::AuditLog,
+    guard::Guard,
+    server::{server_spawner, AccessControl},
+    sessions::Sessions,
+    share_links::{ShareLink, ShareLinkId, ShareLinks},
+};
+
+///The settings of a server running without a window, read from a TOML (or JSON) file
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct HeadlessConfig {
+    ///The port the server listens on
+    port: i64,
+    ///The accounts clients log in with
+    #[serde(default)]
+    users: Vec<UserAccount>,
+    ///What the users and groups can do in each shared folder, nobody can access a folder which is not listed
+    #[serde(default)]
+    access: BTreeMap<PathBuf, BTreeMap<String, Access>>,
+    ///The single password of older servers is not accepted anymore, this is only read to point to `users`
+    #[serde(default)]
+    password_hash: Option<String>,
+    ///Plaintext passwords are not accepted anymore, this is only read to point to `users`
+    #[serde(default)]
+    password: Option<String>,
+    ///The folders shared by the server, relative paths are resolved from the config file's folder like every other path in it
+    shared_folders: Vec<PathBuf>,
+    ///The shared folders clients can upload files into
+    #[serde(default)]
+    writable_folders: Vec<PathBuf>,
+    ///The largest file clients can upload, in megabytes
+    #[serde(default = "default_max_upload_size")]
+    max_upload_size: u64,
+    ///How many requests each client address can send per second, 0 turns the limit off
+    #[serde(default = "default_rate_limit")]
+    rate_limit: u32,
+    ///Every request is appended to this JSON Lines file, relative paths are resolved from the config file's folder
+    #[serde(default = "default_audit_log")]
+    audit_log: PathBuf,
+    ///Path to the PEM certificate, relative paths are resolved from the config file's folder, a self-signed one is generated if it does not exist
+    #[serde(default = "default_certificate")]
+    certificate: PathBuf,
+    ///Path to the PEM private key of the certificate
+    #[serde(default = "default_private_key")]
+    private_key: PathBuf,
+    ///Keys scripts can send instead of logging in, only their digests are stored
+    #[serde(default)]
+    api_keys: Vec<ConfiguredApiKey>,
+    ///The key the tokens of the share links are signed with, changing it invalidates all of them
+    #[serde(default)]
+    share_link_secret: Option<String>,
+    ///Links which let anyone with their token download a single file without logging in
+    #[serde(default)]
+    share_links: Vec<ConfiguredShareLink>,
+    ///How often each share link has been downloaded is kept in this JSON file, so the limits hold across restarts
+    #[serde(default = "default_share_link_state")]
+    share_link_state: PathBuf,
+}
+
+///An API key minted with `server --mint-api-key`
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfiguredApiKey {
+    ///What the key is used for, it is only shown to the server's admin
+    name: String,
+    ///The SHA-256 digest of the key in hex
+    sha256: String,
+    ///The shared folders the key can be used in
+    shares: BTreeSet<PathBuf>,
+    ///What the key can do in them: "list", "download" and "upload"
+    operations: BTreeSet<Operation>,
+    ///The key is refused after this RFC 3339 time, if it is set
+    #[serde(default)]
+    expires: Option<String>,
+}
+
+///A share link, its token is printed by `server --share-link-token`
+#[derive(serde::Deserialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct ConfiguredShareLink {
+    ///Is part of the token, so it must not be reused for another link
+    id: u64,
+    ///Who the link has been made for, it is only shown to the server's admin
+    name: String,
+    ///The file the link is for, inside one of the shared folders
+    path: PathBuf,
+    ///The link is refused after this RFC 3339 time
+    expires: String,
+    ///How many times the file can be downloaded, until the link expires if it is not set
+    #[serde(default)]
+    max_downloads: Option<u32>,
+}
+
+///The password of the account in server.example.toml, a server which still has it is open to anyone who has read the example
+const EXAMPLE_PASSWORD: &str = "change me";
+
+fn default_max_upload_size() -> u64 {
+    1024
+}
+
+fn default_rate_limit() -> u32 {
+    20
+}
+
+fn default_audit_log() -> PathBuf {
+    PathBuf::from("audit.jsonl")
+}
+
+fn default_certificate() -> PathBuf {
+    PathBuf::from("server_cert.pem")
+}
+
+fn default_private_key() -> PathBuf {
+    PathBuf::from("server_key.pem")
+}
+
+fn default_share_link_state() -> PathBuf {
+    PathBuf::from("share_links.json")
+}
+
+impl HeadlessConfig {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {:?}", path))?;
+
+        let mut config: Self = if path.extension().is_some_and(|ext| ext == "json") {
+            serde_json::from_str(&contents)?
+        } else {
+            toml::from_str(&contents)?
+        };
+
+        anyhow::ensure!(
+            config.password.is_none() && config.password_hash.is_none(),
+            "The config file has a single password, replace it with [[users]] entries, each with the password_hash printed by `server --hash-password`"
+        );
+
+        //Make every path independent of the working directory, the shares are matched by these paths so they are all resolved the same way
+        let config_dir = path.parent().unwrap_or(Path::new("."));
+        let resolve = |path: &mut PathBuf| *path = config_dir.join(&*path);
+
+        resolve(&mut config.audit_log);
+        resolve(&mut config.certificate);
+        resolve(&mut config.private_key);
+        resolve(&mut config.share_link_state);
+
+        config.shared_folders.iter_mut().for_each(resolve);
+        config.writable_folders.iter_mut().for_each(resolve);
+
+        config.access = std::mem::take(&mut config.access)
+            .into_iter()
+            .map(|(folder, grants)| (config_dir.join(folder), grants))
+            .collect();
+
+        for key in &mut config.api_keys {
+            key.shares = std::mem::take(&mut key.shares)
+                .into_iter()
+                .map(|share| config_dir.join(share))
+                .collect();
+        }
+
+        for link in &mut config.share_links {
+            resolve(&mut link.path);
+        }
+
+        Ok(config)
+    }
+}
+
+///Runs the server until SIGINT or SIGTERM is received
+pub async fn run(config_path: &Path) -> anyhow::Result<()> {
+    let config = HeadlessConfig::load(config_path)?;
+
+    let mut file_list = Vec::new();
+
+    for folder in &config.shared_folders {
+        anyhow::ensure!(
+            folder.is_dir(),
+            "Shared folder {:?} is not a directory",
+            folder
+        );
+
+        let mut item = FolderItem::new(folder.clone());
+        item.writable = config.writable_folders.contains(folder);
+
+        if item.writable {
+            println!("Sharing {:?} (uploads allowed)", folder);
+        } else {
+            println!("Sharing {:?}", folder);
+        }
+
+        file_list.push(PathItem::Folder(item));
+    }
+
+    for folder in &config.writable_folders {
+        anyhow::ensure!(
+            config.shared_folders.contains(folder),
+            "Writable folder {:?} is not one of the shared folders",
+            folder
+        );
+    }
+
+    let accounts = load_accounts(config.users, config.access, &config.shared_folders)?;
+
+    let api_keys = Arc::new(load_api_keys(config.api_keys, &config.shared_folders)?);
+
+    let share_links = Arc::new(load_share_links(
+        config.share_link_secret,
+        config.share_links,
+        &config.shared_folders,
+        &config.share_link_state,
+    )?);
+
+    let identity = ServerIdentity::load_or_generate(&config.certificate, &config.private_key)?;
+
+    //A server which cannot record what it does does not start
+    let audit = AuditLog::open(config.audit_log.clone())?;
+
+    println!("Recording requests in {:?}", audit.path());
+
+    println!(
+        "Certificate fingerprint (SHA-256): {}",
+        identity.fingerprint
+    );
+
+    let (sx, rx) = mpsc::channel::<()>(1);
+
+    tokio::spawn(async move {
+        match shutdown_signal().await {
+            Ok(signal) => println!("Received {}, shutting down", signal),
+            Err(err) => println!("Failed to listen for shutdown signals: {}", err),
+        }
+
+        let _ = sx.send(()).await;
+    });
+
+    println!("Listening on port {}", config.port);
+
+    let accounts = Arc::new(Accounts::new(accounts));
+
+    let saver = tokio::spawn(keep_share_link_state(
+        share_links.clone(),
+        config.share_link_state.clone(),
+    ));
+
+    let audit = Arc::new(audit);
+    let audit_reporter = tokio::spawn(report_audit_failures(audit.clone()));
+
+    let access = AccessControl {
+        sessions: Arc::new(Sessions::new(accounts.clone())),
+        accounts,
+        api_keys,
+        share_links: share_links.clone(),
+        guard: Arc::new(Guard::new(config.rate_limit)),
+        audit,
+    };
+
+    let result = server_spawner(
+        access,
+        config.port,
+        rx,
+        file_list,
+        config.max_upload_size.saturating_mul(1024 * 1024),
+        identity.identity,
+    )
+    .await;
+
+    saver.abort();
+    audit_reporter.abort();
+
+    //The downloads are saved even if the server failed, so no use of a link is forgotten
+    if !share_links.list().is_empty() {
+        save_share_link_state(&config.share_link_state, &share_links.list())?;
+    }
+
+    result?;
+
+    println!("Server stopped");
+
+    Ok(())
+}
+
+///Checks the users and grants of the config file
+fn load_accounts(
+    users: Vec<UserAccount>,
+    access: BTreeMap<PathBuf, BTreeMap<String, Access>>,
+    shared_folders: &[PathBuf],
+) -> anyhow::Result<AccountsConfig> {
+    let mut accounts = AccountsConfig {
+        users: Vec::new(),
+        access,
+    };
+
+    for user in users {
+        accounts
+            .check_name(&user.name)
+            .map_err(anyhow::Error::msg)?;
+
+        anyhow::ensure!(
+            !is_password(&user.password_hash, EXAMPLE_PASSWORD)
+                .with_context(|| format!("Invalid password_hash of user {}", user.name))?,
+            "The password of {} is the one from the example config, hash a new one with `server --hash-password`",
+            user.name
+        );
+
+        if user.has_empty_password()? {
+            println!("################################################################");
+            println!(
+                "WARNING: The password of {} is empty, anyone can log in as them",
+                user.name
+            );
+            println!("################################################################");
+        }
+
+        accounts.users.push(user);
+    }
+
+    if accounts.users.is_empty() {
+        println!("################################################################");
+        println!("WARNING: There are no users, nobody can log in to this server");
+        println!("################################################################");
+    }
+
+    for (folder, grants) in &accounts.access {
+        anyhow::ensure!(
+            shared_folders.contains(folder),
+            "Access is granted to {:?}, which is not one of the shared folders",
+            folder
+        );
+
+        for name in grants.keys() {
+            anyhow::ensure!(
+                name.starts_with('@') || accounts.user(name).is_some(),
+                "Access to {:?} is granted to {}, who is not one of the users",
+                folder,
+                name
+            );
+        }
+    }
+
+    Ok(accounts)
+}
+
+///Checks the API keys of the config file, they get their IDs in the order they are listed in
+fn load_api_keys(
+    configured: Vec<ConfiguredApiKey>,
+    shared_folders: &[PathBuf],
+) -> anyhow::Result<ApiKeys> {
+    let mut keys = Vec::new();
+
+    for (id, key) in (1..).zip(configured) {
+        anyhow::ensure!(
+            key.sha256.len() == 64 && key.sha256.bytes().all(|byte| byte.is_ascii_hexdigit()),
+            "The sha256 of API key {} is not a SHA-256 digest in hex, use the line printed by `server --mint-api-key`",
+            key.name
+        );
+
+        for share in &key.shares {
+            anyhow::ensure!(
+                shared_folders.contains(share),
+                "API key {} can be used in {:?}, which is not one of the shared folders",
+                key.name,
+                share
+            );
+        }
+
+        let expires = key
+            .expires
+            .as_deref()
+            .map(humantime::parse_rfc3339_weak)
+            .transpose()
+            .with_context(|| format!("Invalid expiry of API key {}", key.name))?;
+
+        keys.push(ApiKey::with_digest(
+            ApiKeyId(id),
+            key.name,
+            key.sha256,
+            key.shares,
+            key.operations,
+            expires,
+        ));
+    }
+
+    Ok(ApiKeys::new(keys, 1))
+}
+
+///Checks the share links of the config file, and restores how often they have been downloaded
+fn load_share_links(
+    secret: Option<String>,
+    configured: Vec<ConfiguredShareLink>,
+    shared_folders: &[PathBuf],
+    state_path: &Path,
+) -> anyhow::Result<ShareLinks> {
+    if configured.is_empty() {
+        return Ok(ShareLinks::default());
+    }
+
+    let secret = secret.filter(|secret| secret.len() >= 32).with_context(|| {
+        format!(
+            "Share links need a share_link_secret of at least 32 characters, like share_link_secret = \"{}\"",
+            to_hex(&random_bytes())
+        )
+    })?;
+
+    let saved: Vec<ShareLink> = match std::fs::read_to_string(state_path) {
+        Ok(contents) => serde_json::from_str(&contents)
+            .with_context(|| format!("Failed to read share link state {:?}", state_path))?,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(err) => {
+            return Err(err)
+                .with_context(|| format!("Failed to read share link state {:?}", state_path))
+        }
+    };
+
+    let mut links: Vec<ShareLink> = Vec::new();
+
+    for link in configured {
+        anyhow::ensure!(
+            !links.iter().any(|other| other.id.0 == link.id),
+            "There are several share links with the ID {}",
+            link.id
+        );
+
+        anyhow::ensure!(
+            shared_folders
+                .iter()
+                .any(|share| link.path.starts_with(share)),
+            "Share link {} is for {:?}, which is not in one of the shared folders",
+            link.name,
+            link.path
+        );
+
+        let expires = humantime::parse_rfc3339_weak(&link.expires)
+            .with_context(|| format!("Invalid expiry of share link {}", link.name))?;
+
+        //A link which has been changed is a new link, its old downloads do not count
+        let (downloads, partial_bytes) = saved
+            .iter()
+            .find(|saved| {
+                saved.id.0 == link.id && saved.path == link.path && saved.expires == expires
+            })
+            .map_or((0, 0), |saved| (saved.downloads, saved.partial_bytes));
+
+        let mut link = ShareLink::new(
+            ShareLinkId(link.id),
+            link.name,
+            link.path,
+            expires,
+            link.max_downloads,
+            downloads,
+        );
+        link.partial_bytes = partial_bytes;
+
+        links.push(link);
+    }
+
+    Ok(ShareLinks::new(secret, links, 1))
+}
+
+///Prints when requests stop being recorded, as there is no audit log tab to show it in
+async fn report_audit_failures(audit: Arc<AuditLog>) {
+    let mut reported = ((0, 0), None);
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let current = (audit.lost(), audit.write_error());
+
+        if current == reported {
+            continue;
+        }
+
+        if let Some(err) = &current.1 {
+            println!("Failed to write the audit log: {}", err);
+        }
+
+        let (dropped, failed) = current.0;
+
+        if current.0 != reported.0 {
+            println!(
+                "{} requests have not been recorded, {} because the disk could not keep up and {} because writing failed",
+                dropped + failed,
+                dropped,
+                failed
+            );
+        }
+
+        reported = current;
+    }
+}
+
+///Saves the download counts of the share links whenever they have changed
+async fn keep_share_link_state(share_links: Arc<ShareLinks>, path: PathBuf) {
+    let downloads = |links: &[ShareLink]| -> Vec<(u32, u64)> {
+        links
+            .iter()
+            .map(|link| (link.downloads, link.partial_bytes))
+            .collect()
+    };
+
+    let mut saved = downloads(&share_links.list());
+    let mut interval = tokio::time::interval(Duration::from_secs(5));
+
+    loop {
+        interval.tick().await;
+
+        let links = share_links.list();
+
+        if downloads(&links) == saved {
+            continue;
+        }
+
+        match save_share_link_state(&path, &links) {
+            Ok(()) => saved = downloads(&links),
+            Err(err) => println!("{:#}", err),
+        }
+    }
+}
+
+///Writes to a temporary file first, so a crash while saving does not lose the counts
+fn save_share_link_state(path: &Path, links: &[ShareLink]) -> anyhow::Result<()> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+
+    //A file left behind by a save which has failed is not reused, as it might be readable by others
+    let _ = std::fs::remove_file(&temporary);
+
+    write_private_file(Path::new(&temporary), &serde_json::to_vec_pretty(links)?)
+        .and_then(|()| std::fs::rename(&temporary, path))
+        .with_context(|| format!("Failed to save share link state {:?}", path))
+}
+
+///Prints a new API key and the line which adds it to the config file, the key itself is not stored anywhere
+pub fn print_new_api_key() {
+    let key = api_keys::generate_key();
+
+    println!("API key (it is only shown once): {}", key);
+    println!(
+        "Add it to an [[api_keys]] entry with: sha256 = \"{}\"",
+        api_keys::digest(&key)
+    );
+}
+
+///Prints the token of a share link of the config file, so it can be handed out
+pub fn print_share_link_token(config_path: &Path, id: &str) -> anyhow::Result<()> {
+    let config = HeadlessConfig::load(config_path)?;
+
+    let id = ShareLinkId(id.parse().context("The ID of a share link is a number")?);
+
+    let share_links = load_share_links(
+        config.share_link_secret,
+        config.share_links,
+        &config.shared_folders,
+        &config.share_link_state,
+    )?;
+
+    let token = share_links
+        .token(id)
+        .with_context(|| format!("There is no share link with the ID {}", id.0))?;
+
+    println!("{}", token);
+
+    Ok(())
+}
+
+///Reads a password from the standard input and prints its hash for the config file, which holds the stored key rather than the Argon2 output
+pub fn print_password_hash() -> anyhow::Result<()> {
+    let mut password = String::new();
+
+    std::io::stdin().read_line(&mut password)?;
+
+    //Only the line break is dropped, the password can start or end with spaces
+    let password = password.trim_end_matches(['\n', '\r']);
+
+    println!("{}", hash_password(password)?);
+
+    Ok(())
+}
+
+///Waits for SIGINT (Ctrl+C) or on unix SIGTERM, returns the name of the signal received
+async fn shutdown_signal() -> std::io::Result<&'static str> {
+    #[cfg(unix)]
+    {
+        let mut terminate =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result.map(|_| "SIGINT"),
+            _ = terminate.recv() => Ok("SIGTERM"),
+        }
+    }
+
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.map(|_| "Ctrl+C")
+    }
+}
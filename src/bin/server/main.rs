@@ -1,18 +1,82 @@
 #![warn(clippy::all, rust_2018_idioms)]
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+use std::path::PathBuf;
+
+use clap::{ArgGroup, Parser};
 use eframe::NativeOptions;
 
+mod headless;
 mod ui;
 
 use ui::Server;
 
-pub mod file_hosting {
-    tonic::include_proto!("file_hosting");
+/// File Hosting server, it opens a window unless one of the options is given
+#[derive(Parser, Debug)]
+#[command(name = "server", version, group(ArgGroup::new("mode").multiple(false)))]
+struct Args {
+    /// Run without a window, with the settings of the config file
+    #[arg(
+        long,
+        value_name = "CONFIG",
+        num_args = 0..=1,
+        default_missing_value = "server.toml",
+        group = "mode"
+    )]
+    headless: Option<PathBuf>,
+
+    /// Hash a password read from stdin for the users of the config file: echo password | server --hash-password
+    #[arg(long, group = "mode")]
+    hash_password: bool,
+
+    /// Mint an API key, its digest is added to the config file
+    #[arg(long, group = "mode")]
+    mint_api_key: bool,
+
+    /// Print the token of a share link of the config file (server.toml if it is left out)
+    #[arg(
+        long,
+        value_names = ["CONFIG", "ID"],
+        num_args = 1..=2,
+        group = "mode"
+    )]
+    share_link_token: Option<Vec<String>>,
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<(), Box<dyn std::error::Error>> {
+    let args = Args::parse();
+
+    if let Some(config_path) = args.headless {
+        headless::run(&config_path).await?;
+
+        return Ok(());
+    }
+
+    if args.hash_password {
+        headless::print_password_hash()?;
+
+        return Ok(());
+    }
+
+    if args.mint_api_key {
+        headless::print_new_api_key();
+
+        return Ok(());
+    }
+
+    if let Some(values) = args.share_link_token {
+        let (config_path, id) = match values.as_slice() {
+            [config_path, id] => (PathBuf::from(config_path), id),
+            [id] => (PathBuf::from("server.toml"), id),
+            _ => unreachable!("clap takes one or two values"),
+        };
+
+        headless::print_share_link_token(&config_path, id)?;
+
+        return Ok(());
+    }
+
     eframe::run_native(
         "File Hosting Server",
         NativeOptions {
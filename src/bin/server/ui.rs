@@ -1,5 +1,9 @@
 #![warn(clippy::all)]
 
+mod api_keys;
 mod app;
-mod backend;
+mod audit;
+pub mod backend;
+mod share_links;
+mod users;
 pub use app::Server;
@@ -0,0 +1,240 @@
+use std::{
+    collections::BTreeSet,
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use egui::{Color32, RichText};
+
+use crate::ui::backend::api_keys::{ApiKeys, Operation};
+
+///The key being minted in the API keys tab
+pub struct ApiKeysTab {
+    name: String,
+    shares: BTreeSet<PathBuf>,
+    operations: BTreeSet<Operation>,
+    expires: bool,
+    expires_in_days: u64,
+    ///The last minted key, it cannot be shown again once this is cleared
+    minted: Option<String>,
+    ///Why the key could not be minted
+    error: Option<String>,
+}
+
+impl Default for ApiKeysTab {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            shares: BTreeSet::new(),
+            operations: BTreeSet::from([Operation::List, Operation::Download]),
+            expires: false,
+            expires_in_days: 30,
+            minted: None,
+            error: None,
+        }
+    }
+}
+
+impl ApiKeysTab {
+    ///Mints and lists the keys, they apply to the running server immediately
+    pub fn show(&mut self, ui: &mut egui::Ui, api_keys: &ApiKeys, shares: &[PathBuf]) {
+        ui.heading("API keys");
+
+        ui.label("Scripts can send an API key instead of logging in as a user, it can only do what it has been scoped to.");
+
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut self.name);
+            });
+
+            ui.horizontal_wrapped(|ui| {
+                ui.label("Shared folders");
+
+                for share in shares {
+                    let mut selected = self.shares.contains(share);
+
+                    if ui
+                        .checkbox(&mut selected, folder_name(share))
+                        .on_hover_text(format!("Full path: {:?}", share))
+                        .changed()
+                    {
+                        toggle(&mut self.shares, share.clone(), selected);
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Operations");
+
+                for operation in Operation::ALL {
+                    let mut selected = self.operations.contains(&operation);
+
+                    if ui.checkbox(&mut selected, operation.to_string()).changed() {
+                        toggle(&mut self.operations, operation, selected);
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.expires, "Expires in");
+
+                ui.add_enabled(
+                    self.expires,
+                    egui::DragValue::new(&mut self.expires_in_days)
+                        .clamp_range(1..=3650)
+                        .suffix(" days"),
+                );
+            });
+
+            if ui.button("Mint key").clicked() {
+                self.mint(api_keys);
+            }
+
+            if let Some(err) = &self.error {
+                ui.label(RichText::from(err).color(Color32::RED));
+            }
+
+            let mut hide = false;
+
+            if let Some(key) = &self.minted {
+                ui.label("Copy the key now, it cannot be shown again");
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::from(key).monospace());
+
+                    if ui.button("Copy").clicked() {
+                        ui.output_mut(|output| output.copied_text = key.clone());
+                    }
+
+                    hide = ui.button("Hide").clicked();
+                });
+            }
+
+            if hide {
+                self.minted = None;
+            }
+        });
+
+        ui.separator();
+
+        let keys = api_keys.list();
+
+        if keys.is_empty() {
+            ui.label("No API keys have been minted");
+        }
+
+        egui::Grid::new("api_keys").striped(true).show(ui, |ui| {
+            if !keys.is_empty() {
+                for header in [
+                    "Name",
+                    "Shared folders",
+                    "Operations",
+                    "Created",
+                    "Expires",
+                    "Last used",
+                ] {
+                    ui.label(RichText::from(header).strong());
+                }
+
+                ui.end_row();
+            }
+
+            for key in &keys {
+                ui.label(&key.name);
+
+                ui.label(
+                    key.shares
+                        .iter()
+                        .map(|share| folder_name(share))
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+
+                ui.label(
+                    key.operations
+                        .iter()
+                        .map(|operation| operation.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+
+                ui.label(format_time(key.created));
+
+                match key.expires {
+                    Some(_) if key.is_expired() => {
+                        ui.label(RichText::from("Expired").color(Color32::RED))
+                    }
+                    Some(expires) => ui.label(format_time(expires)),
+                    None => ui.label("Never"),
+                };
+
+                match key.last_used {
+                    Some(last_used) => ui.label(format_ago(last_used)),
+                    None => ui.label("Never"),
+                };
+
+                if ui.button("Revoke").clicked() {
+                    api_keys.revoke(key.id);
+                }
+
+                ui.end_row();
+            }
+        });
+    }
+
+    fn mint(&mut self, api_keys: &ApiKeys) {
+        let error = if self.name.trim().is_empty() {
+            Some("The key needs a name")
+        } else if self.shares.is_empty() {
+            Some("Select at least one shared folder")
+        } else if self.operations.is_empty() {
+            Some("Select at least one operation")
+        } else {
+            None
+        };
+
+        if let Some(error) = error {
+            self.error = Some(error.to_string());
+
+            return;
+        }
+
+        let expires = self
+            .expires
+            .then(|| SystemTime::now() + Duration::from_secs(self.expires_in_days * 24 * 60 * 60));
+
+        self.minted = Some(api_keys.mint(
+            std::mem::take(&mut self.name).trim().to_string(),
+            self.shares.clone(),
+            self.operations.clone(),
+            expires,
+        ));
+        self.error = None;
+    }
+}
+
+fn toggle<T: Ord>(set: &mut BTreeSet<T>, value: T, selected: bool) {
+    if selected {
+        set.insert(value);
+    } else {
+        set.remove(&value);
+    }
+}
+
+pub(super) fn folder_name(path: &std::path::Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+///Seconds are enough, the rest would only be noise
+pub(super) fn format_time(time: SystemTime) -> String {
+    humantime::format_rfc3339_seconds(time).to_string()
+}
+
+fn format_ago(time: SystemTime) -> String {
+    let elapsed = Duration::from_secs(time.elapsed().unwrap_or_default().as_secs());
+
+    format!("{} ago", humantime::format_duration(elapsed))
+}
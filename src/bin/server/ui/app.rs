@@ -1,7 +1,39 @@
+use common_definitions::{apply_change, find_folder_mut, render_path, PathAction, TreeChange};
+use common_definitions::{tls::ServerIdentity, FolderItem, PathItem};
 use egui::{vec2, Color32, RichText};
-use tokio::{sync::mpsc, task::JoinHandle};
-use common_definitions::render_path;
-use common_definitions::{FolderItem, PathItem};
+use std::{collections::HashSet, sync::Arc, time::Duration};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+
+use crate::ui::{
+    api_keys::ApiKeysTab,
+    audit::AuditTab,
+    backend::{
+        accounts::{password_warnings, Access, Accounts, AccountsConfig, UserAccount},
+        api_keys::{ApiKey, ApiKeys},
+        audit::AuditLog,
+        guard::Guard,
+        server::AccessControl,
+        sessions::Sessions,
+        share_links::{ShareLink, ShareLinks},
+        watcher::ShareWatcher,
+    },
+    share_links::ShareLinksTab,
+    users::{UsersEdit, UsersTab},
+};
+
+///What the window below the settings shows
+#[derive(PartialEq)]
+enum Tab {
+    Shares,
+    Users,
+    ApiKeys,
+    ShareLinks,
+    Audit,
+}
+
 #[derive(serde::Deserialize, serde::Serialize)]
 #[serde(default)]
 pub struct Server {
@@ -9,41 +41,388 @@ pub struct Server {
     //Server doe not persist
     #[serde(skip)]
     server: Option<JoinHandle<()>>,
-    server_password: String,
+    ///The user accounts and what they can access, only the hashes of the passwords are saved
+    accounts: AccountsConfig,
+    ///The accounts the running server checks the requests against, they are replaced whenever they are edited
+    #[serde(skip)]
+    live_accounts: Arc<Accounts>,
+    ///The users anyone can log in as, they are checked whenever the users change
+    #[serde(skip)]
+    password_warnings: Vec<String>,
+    ///The check of the passwords which is running, hashing them would hold up the window
+    #[serde(skip)]
+    password_check: Option<oneshot::Receiver<Vec<String>>>,
+    ///The API keys with when they have last been used, they are taken from the live ones when the app is saved
+    api_keys: Vec<ApiKey>,
+    ///The ID the next API key gets, so the IDs of revoked keys are not handed out again
+    api_key_next_id: u64,
+    ///The API keys the running server accepts, the tab mints and revokes them here
+    #[serde(skip)]
+    live_api_keys: Arc<ApiKeys>,
+    ///The share links with how often they have been downloaded, they are taken from the live ones when the app is saved
+    share_links: Vec<ShareLink>,
+    ///The secret the tokens of the share links are signed with, they stop working if it is lost
+    share_link_secret: String,
+    ///The ID the next share link gets, so the IDs of revoked links are not handed out again
+    share_link_next_id: u64,
+    ///The share links the running server accepts, the tab makes and revokes them here
+    #[serde(skip)]
+    live_share_links: Arc<ShareLinks>,
+    ///Older versions had a single password, its hash becomes the password of an admin account
+    #[serde(skip_serializing, rename = "password_hash")]
+    legacy_password_hash: Option<String>,
+    ///Even older versions have saved the password itself
+    #[serde(skip_serializing, rename = "server_password")]
+    legacy_password: Option<String>,
+    #[serde(skip)]
+    tab: Tab,
+    #[serde(skip)]
+    users_tab: UsersTab,
+    #[serde(skip)]
+    api_keys_tab: ApiKeysTab,
+    #[serde(skip)]
+    share_links_tab: ShareLinksTab,
+    #[serde(skip)]
+    audit_tab: AuditTab,
+    ///Every request of the server is recorded in it, it is kept next to the certificate
+    #[serde(skip)]
+    audit: Option<Arc<AuditLog>>,
+    ///Why we could not open the audit log
+    #[serde(skip)]
+    audit_error: Option<String>,
+    ///The logged in clients and the locked out addresses of the running server
+    #[serde(skip)]
+    access: Option<AccessControl>,
     server_port: i64,
+    ///The largest file clients can upload, in megabytes
+    max_upload_size: u64,
+    ///How many requests each address can send per second, 0 turns the limit off
+    rate_limit: u32,
     #[serde(skip)]
     rx: mpsc::Receiver<()>,
     #[serde(skip)]
     sx: mpsc::Sender<()>,
+    ///The certificate the server encrypts the connection with, this is loaded from the disk instead of the storage
+    #[serde(skip)]
+    identity: Option<ServerIdentity>,
+    ///Why we could not load or generate the certificate
+    #[serde(skip)]
+    identity_error: Option<String>,
+    ///Keeps the shared folders up to date with the disk
+    #[serde(skip)]
+    watcher: Option<ShareWatcher>,
+    ///The changes the watcher has seen since the last frame, or why it has failed to see them
+    #[serde(skip)]
+    changes: mpsc::UnboundedReceiver<Result<TreeChange, String>>,
+    ///Why the shared folders are not kept up to date with the disk
+    #[serde(skip)]
+    watch_error: Option<String>,
 }
 
 impl Default for Server {
     fn default() -> Self {
         //Default channel, this is not going to be used
         let (sx, rx) = mpsc::channel::<()>(1);
+        let (_, changes) = mpsc::unbounded_channel();
         Self {
             shared_folders: Vec::new(),
             server: None,
-            server_password: String::new(),
+            accounts: AccountsConfig::default(),
+            live_accounts: Arc::default(),
+            password_warnings: Vec::new(),
+            password_check: None,
+            api_keys: Vec::new(),
+            api_key_next_id: 1,
+            live_api_keys: Arc::default(),
+            share_links: Vec::new(),
+            share_link_secret: String::new(),
+            share_link_next_id: 1,
+            live_share_links: Arc::default(),
+            legacy_password_hash: None,
+            legacy_password: None,
+            tab: Tab::Shares,
+            users_tab: UsersTab::default(),
+            api_keys_tab: ApiKeysTab::default(),
+            share_links_tab: ShareLinksTab::default(),
+            audit_tab: AuditTab::default(),
+            audit: None,
+            audit_error: None,
+            access: None,
             server_port: 0,
+            max_upload_size: 1024,
+            rate_limit: 20,
             rx,
             sx,
+            identity: None,
+            identity_error: None,
+            watcher: None,
+            changes,
+            watch_error: None,
         }
     }
 }
 
 impl Server {
     pub fn new(cc: &eframe::CreationContext<'_>) -> Self {
-        if let Some(storage) = cc.storage {
-            return eframe::get_value(storage, eframe::APP_KEY).unwrap_or_default();
+        let mut server: Self = cc
+            .storage
+            .and_then(|storage| eframe::get_value(storage, eframe::APP_KEY))
+            .unwrap_or_default();
+
+        server.migrate_password();
+        server.live_accounts = Arc::new(Accounts::new(server.accounts.clone()));
+        server.check_passwords(&cc.egui_ctx);
+        server.live_api_keys = Arc::new(ApiKeys::new(
+            std::mem::take(&mut server.api_keys),
+            server.api_key_next_id,
+        ));
+
+        //The first start has no secret yet, a new one is generated
+        server.live_share_links = Arc::new(match std::mem::take(&mut server.share_link_secret) {
+            secret if secret.is_empty() => ShareLinks::default(),
+            secret => ShareLinks::new(
+                secret,
+                std::mem::take(&mut server.share_links),
+                server.share_link_next_id,
+            ),
+        });
+
+        server.load_identity();
+        server.open_audit_log();
+        server.rescan_shares();
+        server.start_watching(cc.egui_ctx.clone());
+
+        server
+    }
+
+    ///The single password of older versions becomes an admin account, which can read and write every shared folder
+    fn migrate_password(&mut self) {
+        let legacy_password = self.legacy_password.take();
+        let legacy_hash = self.legacy_password_hash.take();
+
+        if !self.accounts.users.is_empty() {
+            return;
+        }
+
+        let password_hash = match (legacy_hash, legacy_password) {
+            (Some(hash), _) => hash,
+            //An empty password meant that there was none
+            (None, Some(password)) if !password.is_empty() => {
+                match common_definitions::auth::hash_password(&password) {
+                    Ok(hash) => hash,
+                    Err(err) => {
+                        dbg!(err);
+
+                        return;
+                    }
+                }
+            }
+            _ => return,
+        };
+
+        self.accounts.users.push(UserAccount {
+            name: "admin".to_string(),
+            password_hash,
+            groups: Vec::new(),
+        });
+
+        for folder in &self.shared_folders {
+            self.accounts
+                .access
+                .entry(folder.get_path())
+                .or_default()
+                .insert("admin".to_string(), Access::ReadWrite);
+        }
+    }
+
+    ///Checks every user for an empty password like the headless server does, the result replaces the warnings once it is done
+    fn check_passwords(&mut self, ctx: &egui::Context) {
+        let (sx, warnings) = oneshot::channel();
+        let users = self.accounts.users.clone();
+        let ctx = ctx.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _ = sx.send(password_warnings(&users));
+
+            ctx.request_repaint();
+        });
+
+        //A check of older users is dropped
+        self.password_check = Some(warnings);
+    }
+
+    fn finish_password_check(&mut self) {
+        let Some(check) = &mut self.password_check else {
+            return;
+        };
+
+        match check.try_recv() {
+            Ok(warnings) => self.password_warnings = warnings,
+            Err(oneshot::error::TryRecvError::Empty) => return,
+            Err(oneshot::error::TryRecvError::Closed) => {
+                self.password_warnings =
+                    vec!["WARNING: The passwords could not be checked".to_string()]
+            }
+        }
+
+        self.password_check = None;
+    }
+
+    ///Applies the edits of the users tab to the running server, clients whose password has changed have to log in again
+    fn apply_users_edit(&mut self, ctx: &egui::Context, edit: UsersEdit) {
+        self.live_accounts.replace(self.accounts.clone());
+        self.check_passwords(ctx);
+
+        if let (UsersEdit::Credentials(user), Some(access)) = (edit, &self.access) {
+            access.sessions.revoke_user(&user);
+        }
+    }
+
+    ///Lists the shared folders, they can be removed and their uploads allowed while the server is stopped
+    fn show_shares(&mut self, ui: &mut egui::Ui) {
+        //Kind of cheat the rust compiler
+        let mut should_remove: Option<usize> = None;
+
+        //iter over all added folders
+        for (index, group) in self.shared_folders.iter_mut().enumerate() {
+            ui.group(|ui| {
+                //Folder name and delete button
+                ui.horizontal(|ui| {
+                    //Folder name
+                    ui.label(
+                        RichText::from(format!(
+                            "Folder: {}",
+                            group.get_path().file_name().unwrap().to_string_lossy()
+                        ))
+                        .size(20.),
+                    )
+                    .on_hover_text(format!("Full path: {:?}", group.get_path()));
+                    //Check if server has started
+                    ui.add_enabled_ui(self.server.is_none(), |ui| {
+                        //and delete button
+                        ui.allocate_ui(vec2(20., 20.), |ui| {
+                            if ui
+                                .add(egui::widgets::ImageButton::new(egui::include_image!(
+                                    "../../../../assets/cross.png"
+                                )))
+                                .clicked()
+                            {
+                                should_remove = Some(index);
+                            }
+                        });
+
+                        if let PathItem::Folder(folder) = group {
+                            ui.checkbox(&mut folder.writable, "Allow uploads");
+                        }
+                    });
+                });
+                if let PathItem::Folder(folder) = group {
+                    //Files cannot be downloaded here, but opened folders have to be listed
+                    if let Some(PathAction::FolderOpened(path)) =
+                        render_path(&mut folder.entries, &HashSet::new(), None, ui)
+                    {
+                        if let Some(folder) = find_folder_mut(&mut folder.entries, &path) {
+                            let _ = folder.load();
+                        }
+                    }
+                }
+            });
+        }
+
+        //Check if we need any deletion
+        if let Some(remove_index) = should_remove {
+            let removed = self.shared_folders.remove(remove_index);
+
+            if let Some(watcher) = &mut self.watcher {
+                let _ = watcher.unwatch(&removed.get_path());
+            }
+        }
+    }
+
+    ///The saved tree might be out of date, so only the shared folders themselves are kept from it
+    fn rescan_shares(&mut self) {
+        for item in &mut self.shared_folders {
+            let mut folder = FolderItem::new(item.get_path());
+
+            if let PathItem::Folder(saved) = item {
+                folder.writable = saved.writable;
+            }
+
+            //A folder which has been deleted is kept, it might be on a drive which is not mounted
+            let _ = folder.load();
+
+            *item = PathItem::Folder(folder);
+        }
+    }
+
+    ///Watches the shared folders, the changes are applied in the next frame
+    fn start_watching(&mut self, ctx: egui::Context) {
+        let (sx, changes) = mpsc::unbounded_channel();
+
+        let errors = sx.clone();
+        let error_ctx = ctx.clone();
+
+        let watcher = ShareWatcher::new(
+            move |change| {
+                let _ = sx.send(Ok(change));
+
+                ctx.request_repaint();
+            },
+            move |err| {
+                let _ = errors.send(Err(err.to_string()));
+
+                error_ctx.request_repaint();
+            },
+        );
+
+        match watcher {
+            Ok(mut watcher) => {
+                for folder in &self.shared_folders {
+                    if let Err(err) = watcher.watch(&folder.get_path()) {
+                        self.watch_error =
+                            Some(format!("{}: {}", folder.get_path().display(), err));
+                    }
+                }
+
+                self.watcher = Some(watcher);
+                self.changes = changes;
+            }
+            Err(err) => self.watch_error = Some(err.to_string()),
+        }
+    }
+
+    ///Loads the certificate of the server from the app's storage directory, a new one is generated on the first start
+    fn load_identity(&mut self) {
+        let directory = eframe::storage_dir("File Hosting Server").unwrap_or_default();
+
+        match ServerIdentity::load_or_generate(
+            &directory.join("server_cert.pem"),
+            &directory.join("server_key.pem"),
+        ) {
+            Ok(identity) => self.identity = Some(identity),
+            Err(err) => self.identity_error = Some(err.to_string()),
         }
+    }
+
+    ///Opens the audit log in the app's storage directory, the server does not start without it
+    fn open_audit_log(&mut self) {
+        let directory = eframe::storage_dir("File Hosting Server").unwrap_or_default();
 
-        Default::default()
+        match AuditLog::open(directory.join("audit.jsonl")) {
+            Ok(audit) => self.audit = Some(Arc::new(audit)),
+            Err(err) => self.audit_error = Some(format!("{:#}", err)),
+        }
     }
 }
 
 impl eframe::App for Server {
     fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        self.api_keys = self.live_api_keys.list();
+        self.api_key_next_id = self.live_api_keys.next_id();
+        self.share_links = self.live_share_links.list();
+        self.share_link_secret = self.live_share_links.secret().to_string();
+        self.share_link_next_id = self.live_share_links.next_id();
+
         eframe::set_value(storage, eframe::APP_KEY, self);
     }
 
@@ -51,41 +430,57 @@ impl eframe::App for Server {
         //Image loading
         egui_extras::install_image_loaders(ctx);
 
-        egui::TopBottomPanel::top("settings").show(ctx, |ui| {
+        while let Ok(change) = self.changes.try_recv() {
+            match change {
+                Ok(change) => apply_change(&mut self.shared_folders, change),
+                Err(err) => self.watch_error = Some(err),
+            }
+        }
+
+        self.finish_password_check();
 
+        egui::TopBottomPanel::top("settings").show(ctx, |ui| {
             ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, Tab::Shares, "Shares");
+                ui.selectable_value(&mut self.tab, Tab::Users, "Users");
+                ui.selectable_value(&mut self.tab, Tab::ApiKeys, "API keys");
+                ui.selectable_value(&mut self.tab, Tab::ShareLinks, "Share links");
+                ui.selectable_value(&mut self.tab, Tab::Audit, "Audit log");
+
+                ui.separator();
+
                 //Display hint
                 if self.shared_folders.is_empty() {
-
                     ui.label("Add a folder to the shared folders");
-
                 } else {
-
                     ui.label(format!("Added folders: {}", self.shared_folders.len()));
-
                 }
-                
+
                 //Add folder
                 ui.add_enabled_ui(self.server.is_none(), |ui| {
-
                     if ui.button("Add folder").clicked() {
-
                         //Add folder
                         if let Some(added_folders) = rfd::FileDialog::new().pick_folders() {
-
                             for folder in added_folders {
+                                let mut folder = FolderItem::new(folder);
 
-                                self.shared_folders
-                                    .push(PathItem::Folder(FolderItem::new(folder)));
+                                //Deeper folders are listed when they are opened
+                                let _ = folder.load();
 
-                            }
+                                if let Some(watcher) = &mut self.watcher {
+                                    if let Err(err) = watcher.watch(&folder.path) {
+                                        self.watch_error =
+                                            Some(format!("{}: {}", folder.path.display(), err));
+                                    }
+                                }
 
+                                self.shared_folders.push(PathItem::Folder(folder));
+                            }
                         };
                     }
                 })
                 .response
                 .on_hover_text(
-
                     //Display warning message
                     if self.server.is_some() {
                         "You cannot add folders while the server is running"
@@ -97,55 +492,34 @@ impl eframe::App for Server {
         });
 
         egui::CentralPanel::default().show(ctx, |ui| {
-
             egui::ScrollArea::both()
                 .auto_shrink([false, false])
                 .show(ui, |ui| {
-
-                    //Kind of cheat the rust compiler
-                    let mut should_remove: Option<usize> = None;
-
-                    //iter over all added folders
-                    for (index, group) in self.shared_folders.iter_mut().enumerate() {
-                        ui.group(|ui| {
-                            //Folder name and delete button
-                            ui.horizontal(|ui| {
-                                //Folder name
-                                ui.label(
-                                    RichText::from(format!(
-                                        "Folder: {}",
-                                        group.get_path().file_name().unwrap().to_string_lossy()
-                                    ))
-                                    .size(20.),
-                                )
-                                .on_hover_text(format!("Full path: {:?}", group.get_path()));
-                                //Check if server has started
-                                ui.add_enabled_ui(self.server.is_none(), |ui| {
-                                    //and delete button
-                                    ui.allocate_ui(vec2(20., 20.), |ui| {
-                                        if ui
-                                            .add(egui::widgets::ImageButton::new(
-                                                egui::include_image!(
-                                                    "../../../../assets/cross.png"
-                                                ),
-                                            ))
-                                            .clicked()
-                                        {
-                                            should_remove = Some(index);
-                                        }
-                                    });
-                                });
-                            });
-                            if let PathItem::Folder(folder) = group {
-                                //We can ignore what this returns
-                                render_path(&mut folder.entries, ui);
+                    let shares: Vec<_> = self
+                        .shared_folders
+                        .iter()
+                        .map(|folder| folder.get_path())
+                        .collect();
+
+                    match self.tab {
+                        Tab::Shares => self.show_shares(ui),
+                        Tab::Users => {
+                            if let Some(edit) = self.users_tab.show(ui, &mut self.accounts, &shares)
+                            {
+                                self.apply_users_edit(ctx, edit);
                             }
-                        });
-                    }
-
-                    //Check if we need any deletion
-                    if let Some(remove_index) = should_remove {
-                        self.shared_folders.remove(remove_index);
+                        }
+                        Tab::ApiKeys => self.api_keys_tab.show(ui, &self.live_api_keys, &shares),
+                        Tab::ShareLinks => {
+                            self.share_links_tab
+                                .show(ui, &self.live_share_links, &shares)
+                        }
+                        Tab::Audit => match &self.audit {
+                            Some(audit) => self.audit_tab.show(ui, audit),
+                            None => {
+                                ui.label("The audit log could not be opened");
+                            }
+                        },
                     }
                 });
         });
@@ -156,27 +530,88 @@ impl eframe::App for Server {
                     ui.label("Start file-hosting service");
 
                     ui.add_enabled_ui(self.server.is_none(), |ui| {
-                        ui.label("Password");
-
-                        ui.add(egui::widgets::TextEdit::singleline(
-                            &mut self.server_password,
-                        ));
                         ui.label("Port (double click to edit)");
-                        
+
                         ui.add(
                             egui::widgets::DragValue::new(&mut self.server_port)
                                 .clamp_range(0..=65535),
                         );
+
+                        ui.label("Upload size limit (MB)");
+
+                        ui.add(
+                            egui::widgets::DragValue::new(&mut self.max_upload_size)
+                                .clamp_range(1..=u64::MAX / (1024 * 1024)),
+                        );
+
+                        ui.label("Requests per second per address (0 for no limit)");
+
+                        ui.add(egui::widgets::DragValue::new(&mut self.rate_limit));
                     });
 
                     ui.separator();
 
+                    //Display the fingerprint, so it can be compared with the one the clients see
+                    if let Some(identity) = &self.identity {
+                        ui.label("Certificate fingerprint (SHA-256)");
+
+                        ui.horizontal(|ui| {
+                            ui.label(RichText::from(&identity.fingerprint).monospace());
+
+                            if ui.button("Copy").clicked() {
+                                ui.output_mut(|output| {
+                                    output.copied_text = identity.fingerprint.clone()
+                                });
+                            }
+                        });
+                    } else if let Some(err) = &self.identity_error {
+                        ui.label(
+                            RichText::from(format!("Failed to load certificate: {}", err))
+                                .color(Color32::RED),
+                        );
+                    }
+
+                    if let Some(err) = &self.audit_error {
+                        ui.label(
+                            RichText::from(format!("Failed to open the audit log: {}", err))
+                                .color(Color32::RED),
+                        );
+                    }
+
+                    if let Some(err) = &self.watch_error {
+                        ui.label(
+                            RichText::from(format!(
+                                "Changes of the shared folders are not seen: {}",
+                                err
+                            ))
+                            .color(Color32::RED),
+                        );
+                    }
+
+                    ui.separator();
+
                     if ui
-                        .add_enabled(self.server.is_none(), |ui: &mut egui::Ui| {
-                            ui.button("Start")
-                        })
+                        .add_enabled(
+                            self.server.is_none()
+                                && self.identity.is_some()
+                                && self.audit.is_some(),
+                            |ui: &mut egui::Ui| ui.button("Start"),
+                        )
                         .clicked()
                     {
+                        //The edits of the users, API keys and share links tabs apply to the running server
+                        let access = AccessControl {
+                            sessions: Arc::new(Sessions::new(self.live_accounts.clone())),
+                            accounts: self.live_accounts.clone(),
+                            api_keys: self.live_api_keys.clone(),
+                            share_links: self.live_share_links.clone(),
+                            guard: Arc::new(Guard::new(self.rate_limit)),
+                            //The start button is disabled until the log is open
+                            audit: self.audit.clone().unwrap(),
+                        };
+
+                        self.access = Some(access.clone());
+
                         //Spawn channels
                         let (sx, rx) = mpsc::channel::<()>(1);
 
@@ -184,14 +619,23 @@ impl eframe::App for Server {
                         self.sx = sx;
 
                         //force ownership
-                        let password = self.server_password.clone();
                         let port = self.server_port;
                         let folder = self.shared_folders.clone();
+                        let max_upload_size = self.max_upload_size.saturating_mul(1024 * 1024);
+                        //The start button is disabled until the certificate is loaded
+                        let identity = self.identity.clone().unwrap().identity;
                         //Server
                         self.server = Some(tokio::spawn(async move {
-                            crate::ui::backend::server::server_spawner(password, port, rx, folder)
-                                .await
-                                .unwrap();
+                            crate::ui::backend::server::server_spawner(
+                                access,
+                                port,
+                                rx,
+                                folder,
+                                max_upload_size,
+                                identity,
+                            )
+                            .await
+                            .unwrap();
                         }));
                     };
 
@@ -208,20 +652,109 @@ impl eframe::App for Server {
 
                         //Reset state
                         self.server = None;
+                        self.access = None;
+                    }
+
+                    if let Some(access) = &self.access {
+                        ui.separator();
+
+                        show_sessions(ui, &access.sessions);
+
+                        ui.separator();
+
+                        show_lockouts(ui, &access.guard);
                     }
                 });
 
                 //Display status
                 if self.server.is_none() {
-
                     ui.label(RichText::from("Offline").color(Color32::RED));
-
                 } else {
-
                     ui.label(RichText::from("Online").color(Color32::GREEN));
+                }
 
+                if self.accounts.users.is_empty() {
+                    ui.label(
+                        RichText::from("WARNING: There are no users, nobody can log in")
+                            .strong()
+                            .color(Color32::RED),
+                    );
+                }
+
+                for warning in &self.password_warnings {
+                    ui.label(RichText::from(warning).strong().color(Color32::RED));
                 }
             });
         });
     }
 }
+
+///Lists the logged in clients, each of them can be logged out
+fn show_sessions(ui: &mut egui::Ui, sessions: &Sessions) {
+    let active = sessions.list();
+
+    ui.label(format!("Logged in clients ({})", active.len()));
+
+    for session in &active {
+        ui.horizontal(|ui| {
+            let peer = session
+                .peer
+                .map(|peer| peer.to_string())
+                .unwrap_or_else(|| "Unknown address".to_string());
+
+            //Seconds are enough, the rest would only be noise
+            let logged_in =
+                Duration::from_secs(session.started.elapsed().unwrap_or_default().as_secs());
+
+            ui.label(format!(
+                "{} from {} for {}",
+                session.user,
+                peer,
+                humantime::format_duration(logged_in)
+            ));
+
+            if ui.button("Revoke").clicked() {
+                sessions.revoke(session.id);
+            }
+        });
+    }
+
+    if ui
+        .add_enabled(!active.is_empty(), egui::Button::new("Revoke all"))
+        .clicked()
+    {
+        sessions.revoke_all();
+    }
+}
+
+///Lists the addresses which are locked out after failing to log in, each of them can be let in again
+fn show_lockouts(ui: &mut egui::Ui, guard: &Guard) {
+    let lockouts = guard.lockouts();
+
+    ui.label(format!("Locked out addresses ({})", lockouts.len()));
+
+    for lockout in &lockouts {
+        ui.horizontal(|ui| {
+            //Seconds are enough, the rest would only be noise
+            let remaining = Duration::from_secs(lockout.remaining.as_secs());
+
+            ui.label(format!(
+                "{} after {} failed logins, for {}",
+                lockout.address,
+                lockout.failures,
+                humantime::format_duration(remaining)
+            ));
+
+            if ui.button("Clear").clicked() {
+                guard.clear(lockout.address);
+            }
+        });
+    }
+
+    if ui
+        .add_enabled(!lockouts.is_empty(), egui::Button::new("Clear all"))
+        .clicked()
+    {
+        guard.clear_all();
+    }
+}
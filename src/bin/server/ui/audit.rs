@@ -0,0 +1,220 @@
+use egui::{Color32, RichText};
+
+use crate::ui::backend::audit::{AuditAction, AuditEntry, AuditLog, AuditOutcome, AuditPage};
+
+///How many entries are shown at once, older ones are on the next pages
+const PAGE_SIZE: usize = 200;
+
+///The filters of the audit log tab, and the page read when it has last been refreshed
+#[derive(Default)]
+pub struct AuditTab {
+    ///Matched against the user, the names of the key and the link, the paths and the address
+    search: String,
+    action: Option<AuditAction>,
+    errors_only: bool,
+    ///Which page is shown, 0 is the newest
+    page: usize,
+    ///`None` until the log is first read
+    entries: Option<AuditPage>,
+    ///Why the log could not be read
+    error: Option<String>,
+}
+
+impl AuditTab {
+    ///Shows the newest entries of the log which match the filters
+    pub fn show(&mut self, ui: &mut egui::Ui, audit: &AuditLog) {
+        ui.heading("Audit log");
+
+        ui.label(format!("Every request is recorded in {:?}", audit.path()));
+
+        if let Some(err) = audit.write_error() {
+            ui.label(
+                RichText::from(format!("Failed to write the audit log: {}", err))
+                    .color(Color32::RED),
+            );
+        }
+
+        match audit.lost() {
+            (0, 0) => {}
+            (dropped, failed) => {
+                ui.label(
+                    RichText::from(format!(
+                        "{} requests have not been recorded, {} because the disk could not keep up and {} because writing failed",
+                        dropped + failed,
+                        dropped,
+                        failed
+                    ))
+                    .color(Color32::RED),
+                );
+            }
+        }
+
+        let mut changed = self.entries.is_none();
+
+        ui.horizontal(|ui| {
+            ui.label("Search");
+            let search = ui
+                .text_edit_singleline(&mut self.search)
+                .on_hover_text("User, API key, share link, path or address, press Enter to search");
+
+            //Searching reads the logs, so it is not done on every key press
+            changed |= search.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+
+            let action = self.action;
+
+            egui::ComboBox::from_label("Action")
+                .selected_text(match self.action {
+                    Some(action) => action.to_string(),
+                    None => "All".to_string(),
+                })
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut self.action, None, "All");
+
+                    for action in AuditAction::ALL {
+                        ui.selectable_value(&mut self.action, Some(action), action.to_string());
+                    }
+                });
+
+            changed |= action != self.action;
+            changed |= ui
+                .checkbox(&mut self.errors_only, "Only failed requests")
+                .changed();
+            changed |= ui.button("Refresh").clicked();
+        });
+
+        //The pages move when new requests are recorded, so changing the filters starts at the newest entries again
+        if changed {
+            self.page = 0;
+            self.refresh(audit);
+        }
+
+        if let Some(err) = &self.error {
+            ui.label(RichText::from(err).color(Color32::RED));
+        }
+
+        let Some(page) = &self.entries else {
+            return;
+        };
+
+        let more = page.more;
+
+        if page.entries.is_empty() && self.page == 0 {
+            ui.label("No requests have been recorded which match the filters");
+
+            return;
+        }
+
+        let mut turned = None;
+
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(self.page > 0, egui::Button::new("Newer"))
+                .clicked()
+            {
+                turned = Some(self.page - 1);
+            }
+
+            ui.label(format!("Page {}", self.page + 1));
+
+            if ui.add_enabled(more, egui::Button::new("Older")).clicked() {
+                turned = Some(self.page + 1);
+            }
+        });
+
+        if let Some(page) = turned {
+            self.page = page;
+            self.refresh(audit);
+        }
+
+        let shown = self
+            .entries
+            .as_ref()
+            .map(|page| page.entries.as_slice())
+            .unwrap_or_default();
+
+        egui::Grid::new("audit_log").striped(true).show(ui, |ui| {
+            for header in [
+                "Time", "Address", "User", "Action", "Paths", "Bytes", "Outcome",
+            ] {
+                ui.label(RichText::from(header).strong());
+            }
+
+            ui.end_row();
+
+            for entry in shown {
+                ui.label(humantime::format_rfc3339_seconds(entry.timestamp).to_string());
+
+                ui.label(entry.peer.map(|peer| peer.to_string()).unwrap_or_default());
+
+                match (&entry.user, &entry.api_key, &entry.share_link) {
+                    (Some(user), _, _) => ui.label(user),
+                    (None, Some(key), _) => ui.label(format!("API key {}", key)),
+                    (None, None, Some(link)) => ui.label(format!("Share link {}", link)),
+                    (None, None, None) => ui.label(""),
+                };
+
+                ui.label(entry.action.to_string());
+
+                ui.label(
+                    entry
+                        .paths
+                        .iter()
+                        .map(|path| path.display().to_string())
+                        .collect::<Vec<_>>()
+                        .join(", "),
+                );
+
+                ui.label(entry.bytes.to_string());
+
+                match &entry.error {
+                    Some(err) => ui.label(RichText::from(err).color(Color32::RED)),
+                    None if !entry.skipped.is_empty() => ui.label(
+                        RichText::from(format!("Skipped {}", entry.skipped.join(", ")))
+                            .color(Color32::YELLOW),
+                    ),
+                    None => ui.label("Ok"),
+                };
+
+                ui.end_row();
+            }
+        });
+    }
+
+    ///Reads the page which is shown, with the filters applied while the log is read
+    fn refresh(&mut self, audit: &AuditLog) {
+        let search = self.search.to_lowercase();
+
+        let matches = |entry: &AuditEntry| {
+            self.action.is_none_or(|action| entry.action == action)
+                && (!self.errors_only || entry.outcome == AuditOutcome::Error)
+                && (search.is_empty() || matches_search(entry, &search))
+        };
+
+        match audit.read_page(matches, self.page * PAGE_SIZE, PAGE_SIZE) {
+            Ok(page) => {
+                self.entries = Some(page);
+                self.error = None;
+            }
+            Err(err) => {
+                self.entries = Some(AuditPage {
+                    entries: Vec::new(),
+                    more: false,
+                });
+                self.error = Some(format!("Failed to read the audit log: {:#}", err));
+            }
+        }
+    }
+}
+
+fn matches_search(entry: &AuditEntry, search: &str) -> bool {
+    let matches = |text: &str| text.to_lowercase().contains(search);
+
+    entry.user.as_deref().is_some_and(matches)
+        || entry.api_key.as_deref().is_some_and(matches)
+        || entry.share_link.as_deref().is_some_and(matches)
+        || entry.peer.is_some_and(|peer| matches(&peer.to_string()))
+        || entry
+            .paths
+            .iter()
+            .any(|path| matches(&path.to_string_lossy()))
+}
@@ -0,0 +1,188 @@
+use anyhow::Context;
+use common_definitions::auth::is_empty_password;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{RwLock, RwLockReadGuard},
+};
+
+///What a user can do inside of a shared folder
+#[derive(
+    serde::Deserialize,
+    serde::Serialize,
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Default,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Access {
+    ///The folder is not listed, and nothing inside of it can be requested
+    #[default]
+    None,
+    Read,
+    ///Files can also be uploaded, if the folder allows uploads
+    ReadWrite,
+}
+
+impl Access {
+    pub const ALL: [Access; 3] = [Access::None, Access::Read, Access::ReadWrite];
+}
+
+impl Display for Access {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Access::None => "No access",
+            Access::Read => "Read",
+            Access::ReadWrite => "Read and write",
+        })
+    }
+}
+
+///A named account clients log in with
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct UserAccount {
+    pub name: String,
+    ///The PHC string of the Argon2 hash of the user's password, it is printed by `server --hash-password`
+    pub password_hash: String,
+    ///Shared folders can grant access to a group instead of each of its users
+    #[serde(default)]
+    pub groups: Vec<String>,
+}
+
+impl UserAccount {
+    ///Checks if anyone can log in as the user, the server warns about it when it starts
+    pub fn has_empty_password(&self) -> anyhow::Result<bool> {
+        is_empty_password(&self.password_hash)
+            .with_context(|| format!("Invalid password_hash of user {}", self.name))
+    }
+}
+
+///The warnings about users anyone can log in as, or nobody can, hashing each of them takes a while
+pub fn password_warnings(users: &[UserAccount]) -> Vec<String> {
+    users
+        .iter()
+        .filter_map(|user| match user.has_empty_password() {
+            Ok(false) => None,
+            Ok(true) => Some(format!(
+                "WARNING: The password of {} is empty, anyone can log in as them",
+                user.name
+            )),
+            Err(err) => Some(format!("WARNING: {:#}", err)),
+        })
+        .collect()
+}
+
+///The user accounts and what each of them can access
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct AccountsConfig {
+    pub users: Vec<UserAccount>,
+    ///What can be done in each shared folder, by the folder's path and then by the name of a user, or of a group prefixed with `@`
+    pub access: BTreeMap<PathBuf, BTreeMap<String, Access>>,
+}
+
+impl AccountsConfig {
+    pub fn user(&self, name: &str) -> Option<&UserAccount> {
+        self.users.iter().find(|user| user.name == name)
+    }
+
+    ///A grant to the user overrides the ones to their groups, of which the highest one counts
+    pub fn access(&self, user: &str, share: &Path) -> Access {
+        let Some(grants) = self.access.get(share) else {
+            return Access::None;
+        };
+
+        if let Some(access) = grants.get(user) {
+            return *access;
+        }
+
+        self.user(user)
+            .into_iter()
+            .flat_map(|user| &user.groups)
+            .filter_map(|group| grants.get(&group_key(group)))
+            .max()
+            .copied()
+            .unwrap_or_default()
+    }
+
+    ///Every group at least one user is a member of
+    pub fn groups(&self) -> BTreeSet<String> {
+        self.users
+            .iter()
+            .flat_map(|user| user.groups.iter().cloned())
+            .collect()
+    }
+
+    ///User names cannot be confused with groups, and every user needs a different one
+    pub fn check_name(&self, name: &str) -> Result<(), String> {
+        if name.trim().is_empty() {
+            Err("The name cannot be empty".to_string())
+        } else if name.starts_with('@') {
+            Err("The name cannot start with @, that is how groups are written".to_string())
+        } else if self.user(name).is_some() {
+            Err(format!("There already is a user named {}", name))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+///How a group is written in the grants of a shared folder
+pub fn group_key(group: &str) -> String {
+    format!("@{}", group)
+}
+
+///The accounts the running server checks the requests against, the server's window replaces them whenever they are edited
+#[derive(Default)]
+pub struct Accounts(RwLock<AccountsConfig>);
+
+impl Accounts {
+    pub fn new(config: AccountsConfig) -> Self {
+        Self(RwLock::new(config))
+    }
+
+    pub fn read(&self) -> RwLockReadGuard<'_, AccountsConfig> {
+        self.0.read().unwrap()
+    }
+
+    pub fn replace(&self, config: AccountsConfig) {
+        *self.0.write().unwrap() = config;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_definitions::auth::hash_password;
+
+    use super::*;
+
+    fn user(name: &str, password_hash: String) -> UserAccount {
+        UserAccount {
+            name: name.to_string(),
+            password_hash,
+            groups: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn warns_about_empty_and_invalid_passwords() {
+        let users = [
+            user("alice", hash_password("a real password").unwrap()),
+            user("bob", hash_password("").unwrap()),
+            user("carol", "not a hash".to_string()),
+        ];
+
+        let warnings = password_warnings(&users);
+
+        assert_eq!(warnings.len(), 2);
+        assert!(warnings[0].contains("The password of bob is empty"));
+        assert!(warnings[1].contains("Invalid password_hash of user carol"));
+    }
+}
@@ -0,0 +1,279 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex, RwLock,
+    },
+    time::SystemTime,
+};
+
+use common_definitions::{auth::random_bytes, error::ServiceError, to_hex};
+use sha2::{Digest, Sha256};
+
+///What a request made with an API key can do
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum Operation {
+    ///Listing the shared folders and their contents, and watching them for changes
+    List,
+    ///Downloading files and archives
+    Download,
+    ///Uploading files, into the folders which allow uploads
+    Upload,
+}
+
+impl Operation {
+    pub const ALL: [Operation; 3] = [Operation::List, Operation::Download, Operation::Upload];
+}
+
+impl Display for Operation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Operation::List => "List",
+            Operation::Download => "Download",
+            Operation::Upload => "Upload",
+        })
+    }
+}
+
+///Identifies an API key, the key itself is only shown once when it is minted
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct ApiKeyId(pub u64);
+
+///A key for scripts, which is sent instead of logging in
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ApiKey {
+    pub id: ApiKeyId,
+    ///What the key is used for, it is only shown to the server's admin
+    pub name: String,
+    ///The SHA-256 digest of the key in hex, the key has enough entropy that it does not need a slow hash
+    digest: String,
+    ///The shared folders the key can be used in, by the paths they have been shared with
+    pub shares: BTreeSet<PathBuf>,
+    pub operations: BTreeSet<Operation>,
+    pub created: SystemTime,
+    ///The key is refused after this, if it is set
+    pub expires: Option<SystemTime>,
+    ///When a request has last been made with the key
+    pub last_used: Option<SystemTime>,
+}
+
+impl ApiKey {
+    ///A key which has been minted elsewhere, like the ones in the config of a headless server, by the SHA-256 digest of the key in hex
+    pub fn with_digest(
+        id: ApiKeyId,
+        name: String,
+        digest: String,
+        shares: BTreeSet<PathBuf>,
+        operations: BTreeSet<Operation>,
+        expires: Option<SystemTime>,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            digest: digest.to_ascii_lowercase(),
+            shares,
+            operations,
+            created: SystemTime::now(),
+            expires,
+            last_used: None,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires
+            .is_some_and(|expires| expires <= SystemTime::now())
+    }
+
+    ///Checks if the key can be used for the operation in the shared folder
+    pub fn allows(&self, share: &Path, operation: Operation) -> bool {
+        !self.is_expired() && self.shares.contains(share) && self.operations.contains(&operation)
+    }
+}
+
+///The API keys the running server accepts, they can be minted and revoked while it is running
+pub struct ApiKeys {
+    keys: RwLock<Vec<ApiKey>>,
+    ///When the keys have been used since they have been listed, kept apart so checking a key only needs to read the keys
+    last_used: Mutex<BTreeMap<ApiKeyId, SystemTime>>,
+    ///The ID of the next key, IDs are never reused so a revoked key cannot be mistaken for a newer one
+    next_id: AtomicU64,
+}
+
+impl Default for ApiKeys {
+    fn default() -> Self {
+        Self::new(Vec::new(), 1)
+    }
+}
+
+impl ApiKeys {
+    ///The next ID is saved along with the keys, it is raised past the keys in case it has been lost
+    pub fn new(keys: Vec<ApiKey>, next_id: u64) -> Self {
+        let next_id = keys
+            .iter()
+            .map(|key| key.id.0 + 1)
+            .fold(next_id.max(1), u64::max);
+
+        Self {
+            keys: RwLock::new(keys),
+            last_used: Mutex::default(),
+            next_id: AtomicU64::new(next_id),
+        }
+    }
+
+    ///The keys, with when they have last been used
+    pub fn list(&self) -> Vec<ApiKey> {
+        let mut keys = self.keys.read().unwrap().clone();
+
+        for key in &mut keys {
+            self.mark_used(key);
+        }
+
+        keys
+    }
+
+    fn mark_used(&self, key: &mut ApiKey) {
+        if let Some(used) = self.last_used.lock().unwrap().get(&key.id) {
+            key.last_used = Some(*used);
+        }
+    }
+
+    ///The ID the next key gets, it has to be saved along with the keys
+    pub fn next_id(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    ///Creates a new key, returns it so it can be shown once, only its digest is kept
+    pub fn mint(
+        &self,
+        name: String,
+        shares: BTreeSet<PathBuf>,
+        operations: BTreeSet<Operation>,
+        expires: Option<SystemTime>,
+    ) -> String {
+        let key = generate_key();
+
+        let mut keys = self.keys.write().unwrap();
+
+        keys.push(ApiKey {
+            id: ApiKeyId(self.next_id.fetch_add(1, Ordering::Relaxed)),
+            name,
+            digest: digest(&key),
+            shares,
+            operations,
+            created: SystemTime::now(),
+            expires,
+            last_used: None,
+        });
+
+        key
+    }
+
+    ///The key stops working immediately, even for the requests which are still running
+    pub fn revoke(&self, id: ApiKeyId) {
+        self.keys.write().unwrap().retain(|key| key.id != id);
+        self.last_used.lock().unwrap().remove(&id);
+    }
+
+    ///Finds the key a request has been sent with, and marks it as used
+    ///
+    ///The requests of different keys can be checked at the same time, only marking them as used takes turns
+    pub fn check(&self, key: &str) -> Result<ApiKeyId, ServiceError> {
+        let digest = digest(key);
+
+        let id = match self
+            .keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| key.digest == digest)
+        {
+            Some(key) if key.is_expired() => {
+                return Err(ServiceError::Unauthenticated(
+                    "The API key has expired".to_string(),
+                ))
+            }
+            Some(key) => key.id,
+            None => return Err(ServiceError::Unauthenticated("Invalid API key".to_string())),
+        };
+
+        self.last_used.lock().unwrap().insert(id, SystemTime::now());
+
+        Ok(id)
+    }
+
+    ///The key, unless it has been revoked
+    pub fn get(&self, id: ApiKeyId) -> Option<ApiKey> {
+        let mut key = self
+            .keys
+            .read()
+            .unwrap()
+            .iter()
+            .find(|key| key.id == id)
+            .cloned()?;
+
+        self.mark_used(&mut key);
+
+        Some(key)
+    }
+}
+
+///A new key, it has enough entropy that it cannot be guessed
+pub fn generate_key() -> String {
+    format!("fhk_{}", to_hex(&random_bytes()))
+}
+
+///The SHA-256 digest of a key in hex, only this is kept of the key
+pub fn digest(key: &str) -> String {
+    to_hex(&Sha256::digest(key.as_bytes()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    fn mint(keys: &ApiKeys, expires: Option<SystemTime>) -> String {
+        keys.mint(
+            "script".to_string(),
+            [PathBuf::from("/shared")].into(),
+            [Operation::Download].into(),
+            expires,
+        )
+    }
+
+    #[test]
+    fn checking_a_key_marks_it_as_used() {
+        let keys = ApiKeys::default();
+        let key = mint(&keys, None);
+
+        assert!(keys.list()[0].last_used.is_none());
+
+        let id = keys.check(&key).unwrap();
+
+        assert!(keys.get(id).unwrap().last_used.is_some());
+        assert!(keys.list()[0].last_used.is_some());
+    }
+
+    #[test]
+    fn rejects_unknown_expired_and_revoked_keys() {
+        let keys = ApiKeys::default();
+        let expired = mint(&keys, Some(SystemTime::now() - Duration::from_secs(1)));
+        let revoked = mint(&keys, None);
+
+        assert!(keys.check(&generate_key()).is_err());
+        assert!(keys.check(&expired).is_err());
+
+        keys.revoke(keys.check(&revoked).unwrap());
+
+        assert!(keys.check(&revoked).is_err());
+        assert!(keys.list().iter().all(|key| key.last_used.is_none()));
+    }
+}
@@ -0,0 +1,292 @@
+use std::{
+    collections::HashSet,
+    ffi::OsString,
+    fs::{self, File, Metadata},
+    io::{self, Write},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+use common_definitions::{messages::FileChunk, ArchiveFormat};
+use tokio::sync::mpsc;
+use tonic::Status;
+use zip::{
+    write::{SimpleFileOptions, StreamWriter},
+    CompressionMethod, ZipWriter,
+};
+
+use super::server::is_inside;
+
+///The size of a single chunk sent by `StreamArchive`
+const ARCHIVE_CHUNK_SIZE: usize = 256 * 1024;
+
+///The archive is written into this, it sends the archive to the client chunk by chunk
+///
+///Sending blocks while the client is behind, so it has to be used outside of the async runtime
+pub struct ChunkWriter {
+    buffer: Vec<u8>,
+    sx: mpsc::Sender<Result<FileChunk, Status>>,
+    ///How many bytes of the archive have been sent, it is also known if writing the archive fails
+    sent: Arc<AtomicU64>,
+}
+
+impl ChunkWriter {
+    pub fn new(sx: mpsc::Sender<Result<FileChunk, Status>>, sent: Arc<AtomicU64>) -> Self {
+        Self {
+            buffer: Vec::with_capacity(ARCHIVE_CHUNK_SIZE),
+            sx,
+            sent,
+        }
+    }
+
+    fn send(&mut self) -> io::Result<()> {
+        let data = std::mem::replace(&mut self.buffer, Vec::with_capacity(ARCHIVE_CHUNK_SIZE));
+        let length = data.len() as u64;
+
+        self.sx
+            .blocking_send(Ok(FileChunk {
+                data,
+                ..Default::default()
+            }))
+            //The client has dropped the stream
+            .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+
+        self.sent.fetch_add(length, Ordering::Relaxed);
+
+        Ok(())
+    }
+
+    ///Sends the rest of the archive
+    fn finish(mut self) -> io::Result<()> {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        self.send()
+    }
+}
+
+impl Write for ChunkWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+
+        if self.buffer.len() >= ARCHIVE_CHUNK_SIZE {
+            self.send()?;
+        }
+
+        Ok(buf.len())
+    }
+
+    //Chunks are only sent once they are full, or the archive is finished
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+///Writes the selected files and folders into an archive of the format, folders are added with everything inside of them
+///
+///The selected items are pairs of their canonicalized path and their name in the archive, entries below them which point outside of the shared folders are skipped
+///
+///Returns the entries which could not be read, with why, so they can be recorded
+pub fn write_archive(
+    format: ArchiveFormat,
+    selected: Vec<(PathBuf, PathBuf)>,
+    shared_roots: &[PathBuf],
+    writer: ChunkWriter,
+) -> io::Result<Vec<String>> {
+    let mut skipped = Vec::new();
+
+    match format {
+        ArchiveFormat::Tar => {
+            let mut builder = tar::Builder::new(writer);
+
+            add_selected(&mut builder, selected, shared_roots, &mut skipped)?;
+
+            builder.into_inner()?.finish()?;
+        }
+        ArchiveFormat::TarZstd => {
+            let mut builder = tar::Builder::new(zstd::Encoder::new(writer, 0)?);
+
+            add_selected(&mut builder, selected, shared_roots, &mut skipped)?;
+
+            builder.into_inner()?.finish()?.finish()?;
+        }
+        ArchiveFormat::Zip => {
+            //The archive cannot be seeked in, so the sizes of the files are written after them
+            let mut builder = ZipWriter::new_stream(writer);
+
+            add_selected(&mut builder, selected, shared_roots, &mut skipped)?;
+
+            builder.finish()?.into_inner().finish()?;
+        }
+    }
+
+    Ok(skipped)
+}
+
+///How an entry is added differs between the formats
+trait ArchiveBuilder {
+    fn add_folder(&mut self, name: &Path, source: &Path) -> io::Result<()>;
+
+    fn add_file(&mut self, name: &Path, file: &mut File) -> io::Result<()>;
+}
+
+impl<W: Write> ArchiveBuilder for tar::Builder<W> {
+    fn add_folder(&mut self, name: &Path, source: &Path) -> io::Result<()> {
+        self.append_dir(name, source)
+    }
+
+    fn add_file(&mut self, name: &Path, file: &mut File) -> io::Result<()> {
+        self.append_file(name, file)
+    }
+}
+
+impl<W: Write> ArchiveBuilder for ZipWriter<StreamWriter<W>> {
+    fn add_folder(&mut self, name: &Path, source: &Path) -> io::Result<()> {
+        let options = zip_options(&fs::metadata(source)?);
+
+        self.add_directory(zip_name(name), options)?;
+
+        Ok(())
+    }
+
+    fn add_file(&mut self, name: &Path, file: &mut File) -> io::Result<()> {
+        let options = zip_options(&file.metadata()?);
+
+        self.start_file(zip_name(name), options)?;
+
+        io::copy(file, self)?;
+
+        Ok(())
+    }
+}
+
+fn zip_options(metadata: &Metadata) -> SimpleFileOptions {
+    let mut options = SimpleFileOptions::default()
+        .compression_method(CompressionMethod::Deflated)
+        //Files over 4 GiB need the zip64 extension, which has to be decided before the file is written
+        .large_file(metadata.len() >= u32::MAX as u64);
+
+    if let Some(modified) = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| zip::DateTime::try_from(time::OffsetDateTime::from(modified)).ok())
+    {
+        options = options.last_modified_time(modified);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+
+        options = options.unix_permissions(metadata.permissions().mode());
+    }
+
+    options
+}
+
+///Names inside of zip archives are always separated by slashes
+fn zip_name(name: &Path) -> String {
+    name.components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn add_selected(
+    builder: &mut impl ArchiveBuilder,
+    selected: Vec<(PathBuf, PathBuf)>,
+    shared_roots: &[PathBuf],
+    skipped: &mut Vec<String>,
+) -> io::Result<()> {
+    let mut names = HashSet::new();
+
+    for (source, name) in selected {
+        //Items from different shared folders can have the same name
+        let name = unique_name(&mut names, name);
+
+        add_entry(builder, &source, &name, shared_roots, skipped)?;
+    }
+
+    Ok(())
+}
+
+///Appends a counter to the name until it is not taken
+fn unique_name(taken: &mut HashSet<PathBuf>, name: PathBuf) -> PathBuf {
+    let mut unique = name.clone();
+    let mut counter = 1;
+
+    while !taken.insert(unique.clone()) {
+        counter += 1;
+
+        let mut renamed = OsString::from(&name);
+        renamed.push(format!(" ({})", counter));
+
+        unique = PathBuf::from(renamed);
+    }
+
+    unique
+}
+
+///Adds the file, or the folder with everything inside of it
+///
+///Entries which cannot be read are skipped and added to `skipped` by their name in the archive, only failing to write the archive is an error
+fn add_entry(
+    builder: &mut impl ArchiveBuilder,
+    source: &Path,
+    name: &Path,
+    shared_roots: &[PathBuf],
+    skipped: &mut Vec<String>,
+) -> io::Result<()> {
+    if source.is_file() {
+        match File::open(source) {
+            Ok(mut file) => builder.add_file(name, &mut file)?,
+            Err(err) => skipped.push(format!("{}: {}", name.display(), err)),
+        }
+
+        return Ok(());
+    }
+
+    builder.add_folder(name, source)?;
+
+    let mut children: Vec<PathBuf> = match fs::read_dir(source) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+            .collect(),
+        Err(err) => {
+            skipped.push(format!("{}: {}", name.display(), err));
+
+            return Ok(());
+        }
+    };
+
+    //The same folder is always archived the same way
+    children.sort();
+
+    for child in children {
+        let is_link = child
+            .symlink_metadata()
+            .is_ok_and(|metadata| metadata.file_type().is_symlink());
+
+        //Links are only followed to files inside of the shared folders, a link to a folder could form a cycle
+        if is_link
+            && !child
+                .canonicalize()
+                .is_ok_and(|target| target.is_file() && is_inside(shared_roots, &target))
+        {
+            continue;
+        }
+
+        let child_name = name.join(child.file_name().unwrap_or_default());
+
+        add_entry(builder, &child, &child_name, shared_roots, skipped)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,553 @@
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
+    net::SocketAddr,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::JoinHandle,
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use common_definitions::error::ServiceError;
+
+///The log is rotated once it grows past this, in bytes
+const MAX_LOG_SIZE: u64 = 10 * 1024 * 1024;
+
+///How many entries can wait for the disk, the ones of further requests are dropped and counted until it has caught up
+const QUEUE_SIZE: usize = 10_000;
+
+///How many rotated logs are kept next to the current one, as `<name>.1` (the newest) to `<name>.5`
+const ROTATED_LOGS: usize = 5;
+
+///What a request has asked the server to do
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditAction {
+    ///A nonce has been asked for, which is the first step of logging in
+    Challenge,
+    Login,
+    Refresh,
+    Logout,
+    ///A request which the interceptor has turned away before it has reached the service
+    Rejected,
+    List,
+    ListDirectory,
+    Download,
+    ///The metadata and the digest of a file have been asked for
+    Stat,
+    Archive,
+    Upload,
+    Watch,
+    ///The holder of a share link has asked which file it is for
+    DescribeLink,
+}
+
+impl AuditAction {
+    pub const ALL: [AuditAction; 13] = [
+        AuditAction::Challenge,
+        AuditAction::Login,
+        AuditAction::Refresh,
+        AuditAction::Logout,
+        AuditAction::Rejected,
+        AuditAction::List,
+        AuditAction::ListDirectory,
+        AuditAction::Download,
+        AuditAction::Stat,
+        AuditAction::Archive,
+        AuditAction::Upload,
+        AuditAction::Watch,
+        AuditAction::DescribeLink,
+    ];
+}
+
+impl Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            AuditAction::Challenge => "Challenge",
+            AuditAction::Login => "Login",
+            AuditAction::Refresh => "Refresh",
+            AuditAction::Logout => "Logout",
+            AuditAction::Rejected => "Rejected",
+            AuditAction::List => "List",
+            AuditAction::ListDirectory => "List folder",
+            AuditAction::Download => "Download",
+            AuditAction::Stat => "Stat",
+            AuditAction::Archive => "Archive",
+            AuditAction::Upload => "Upload",
+            AuditAction::Watch => "Watch",
+            AuditAction::DescribeLink => "Describe link",
+        })
+    }
+}
+
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuditOutcome {
+    Ok,
+    Error,
+}
+
+///A line of the audit log
+///
+///Only what is set through its methods is written, so passwords, proofs, tokens and API keys can never end up in the log
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct AuditEntry {
+    #[serde(with = "rfc3339")]
+    pub timestamp: SystemTime,
+    pub peer: Option<SocketAddr>,
+    ///The user the request has been made as, for failed logins the one which has been tried
+    pub user: Option<String>,
+    ///The name of the API key the request has been made with
+    pub api_key: Option<String>,
+    ///The name of the share link the request has been made with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub share_link: Option<String>,
+    pub action: AuditAction,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub paths: Vec<PathBuf>,
+    ///How many bytes of files have been sent or received
+    #[serde(default)]
+    pub bytes: u64,
+    pub outcome: AuditOutcome,
+    ///Why the request has failed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    ///What has been left out of an archive because it could not be read, with why
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+impl AuditEntry {
+    pub fn new(action: AuditAction, peer: Option<SocketAddr>) -> Self {
+        Self {
+            timestamp: SystemTime::now(),
+            peer,
+            user: None,
+            api_key: None,
+            share_link: None,
+            action,
+            paths: Vec::new(),
+            bytes: 0,
+            outcome: AuditOutcome::Ok,
+            error: None,
+            skipped: Vec::new(),
+        }
+    }
+
+    pub fn user(mut self, user: impl Into<String>) -> Self {
+        self.user = Some(user.into());
+        self
+    }
+
+    ///Only the name the key has been given is logged, never the key
+    pub fn api_key(mut self, name: impl Into<String>) -> Self {
+        self.api_key = Some(name.into());
+        self
+    }
+
+    ///Only the name the link has been given is logged, never its token
+    pub fn share_link(mut self, name: impl Into<String>) -> Self {
+        self.share_link = Some(name.into());
+        self
+    }
+
+    pub fn paths(mut self, paths: Vec<PathBuf>) -> Self {
+        self.paths = paths;
+        self
+    }
+
+    pub fn bytes(mut self, bytes: u64) -> Self {
+        self.bytes = bytes;
+        self
+    }
+
+    pub fn skipped(mut self, skipped: Vec<String>) -> Self {
+        self.skipped = skipped;
+        self
+    }
+
+    ///Marks the request as failed, the messages of service errors never contain secrets
+    pub fn error(mut self, err: &ServiceError) -> Self {
+        self.outcome = AuditOutcome::Error;
+        self.error = Some(err.to_string());
+        self
+    }
+
+    ///Sets the outcome from the result of the request
+    pub fn result<T>(self, result: &Result<T, ServiceError>) -> Self {
+        match result {
+            Ok(_) => self,
+            Err(err) => self.error(err),
+        }
+    }
+}
+
+///An append-only JSON Lines file, one line per request, rotated once it grows too large
+pub struct AuditLog {
+    path: PathBuf,
+    ///The entries are written by their own thread, so requests never wait for the disk
+    sender: Option<mpsc::SyncSender<AuditEntry>>,
+    writer: Option<JoinHandle<()>>,
+    health: Arc<Health>,
+}
+
+///What has gone wrong with recording the requests, it is shown in the audit log tab
+#[derive(Default)]
+struct Health {
+    ///Entries which have been dropped because too many were waiting to be written
+    dropped: AtomicU64,
+    ///Entries which could not be written
+    failed: AtomicU64,
+    ///Why the last entry could not be written, until one has been written again
+    error: Mutex<Option<String>>,
+}
+
+impl AuditLog {
+    pub fn open(path: PathBuf) -> anyhow::Result<Self> {
+        let mut writer = LogWriter {
+            path: path.clone(),
+            file: Some(open_log(&path)?),
+        };
+
+        let (sender, receiver) = mpsc::sync_channel::<AuditEntry>(QUEUE_SIZE);
+        let health = Arc::new(Health::default());
+        let writer_health = health.clone();
+
+        let thread = std::thread::Builder::new()
+            .name("audit log".to_string())
+            .spawn(move || {
+                for entry in receiver {
+                    let error = match writer.append(&entry) {
+                        Ok(()) => None,
+                        Err(err) => {
+                            writer_health.failed.fetch_add(1, Ordering::Relaxed);
+
+                            Some(format!("{:#}", err))
+                        }
+                    };
+
+                    *writer_health.error.lock().unwrap() = error;
+                }
+            })?;
+
+        Ok(Self {
+            path,
+            sender: Some(sender),
+            writer: Some(thread),
+            health,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    ///Queues the entry to be appended, a request never fails because it could not be logged
+    ///
+    ///The entry is dropped if the queue is full, so a flood of requests cannot fill the memory while the disk lags behind
+    pub fn record(&self, entry: AuditEntry) {
+        if let Some(sender) = &self.sender {
+            if let Err(mpsc::TrySendError::Full(_)) = sender.try_send(entry) {
+                self.health.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    ///How many entries have been dropped because the queue was full, and how many could not be written
+    pub fn lost(&self) -> (u64, u64) {
+        (
+            self.health.dropped.load(Ordering::Relaxed),
+            self.health.failed.load(Ordering::Relaxed),
+        )
+    }
+
+    ///Why the last entry could not be written, `None` once one has been written again
+    pub fn write_error(&self) -> Option<String> {
+        self.health.error.lock().unwrap().clone()
+    }
+
+    ///Reads a page of the entries which match, the newest first, from the current and the rotated logs
+    ///
+    ///The logs are read backwards and only until the page is full, lines which cannot be parsed are skipped, like one which is still being written
+    pub fn read_page(
+        &self,
+        matches: impl Fn(&AuditEntry) -> bool,
+        skip: usize,
+        limit: usize,
+    ) -> anyhow::Result<AuditPage> {
+        let mut page = AuditPage {
+            entries: Vec::new(),
+            more: false,
+        };
+        let mut skipped = 0;
+
+        for index in 0..=ROTATED_LOGS {
+            let path = if index == 0 {
+                self.path.clone()
+            } else {
+                rotated_path(&self.path, index)
+            };
+
+            let Ok(file) = File::open(&path) else {
+                continue;
+            };
+
+            for line in ReverseLines::new(file)? {
+                let Ok(entry) = serde_json::from_slice::<AuditEntry>(&line?) else {
+                    continue;
+                };
+
+                if !matches(&entry) {
+                    continue;
+                }
+
+                if skipped < skip {
+                    skipped += 1;
+                } else if page.entries.len() < limit {
+                    page.entries.push(entry);
+                } else {
+                    page.more = true;
+
+                    return Ok(page);
+                }
+            }
+        }
+
+        Ok(page)
+    }
+}
+
+///Waits until the queued entries have been written, so none are lost when the server stops
+impl Drop for AuditLog {
+    fn drop(&mut self) {
+        self.sender = None;
+
+        if let Some(writer) = self.writer.take() {
+            let _ = writer.join();
+        }
+    }
+}
+
+///The entries of a page of the log, and if there are older ones which match
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub more: bool,
+}
+
+///Owned by the thread which writes the log
+struct LogWriter {
+    path: PathBuf,
+    ///The open log with its size, `None` after writing has failed so the next entry tries to open it again
+    file: Option<(File, u64)>,
+}
+
+impl LogWriter {
+    fn append(&mut self, entry: &AuditEntry) -> anyhow::Result<()> {
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+
+        if self
+            .file
+            .as_ref()
+            .is_some_and(|(_, size)| size + line.len() as u64 > MAX_LOG_SIZE)
+        {
+            self.file = None;
+
+            self.rotate()?;
+        }
+
+        if self.file.is_none() {
+            self.file = Some(open_log(&self.path)?);
+        }
+
+        let (log, size) = self.file.as_mut().unwrap();
+
+        if let Err(err) = log.write_all(&line) {
+            //Reopened with the next entry
+            self.file = None;
+
+            return Err(err.into());
+        }
+
+        *size += line.len() as u64;
+
+        Ok(())
+    }
+
+    ///Shifts the rotated logs by one, the oldest one is dropped
+    fn rotate(&self) -> anyhow::Result<()> {
+        for index in (1..ROTATED_LOGS).rev() {
+            let from = rotated_path(&self.path, index);
+
+            if from.exists() {
+                std::fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+
+        std::fs::rename(&self.path, rotated_path(&self.path, 1))?;
+
+        Ok(())
+    }
+}
+
+///How much of a log is read at once when reading it backwards, in bytes
+const BLOCK_SIZE: u64 = 64 * 1024;
+
+///The lines of a file from the last to the first, so the newest entries can be read without reading the whole log
+struct ReverseLines {
+    file: File,
+    ///Where the part of the file which has not been read yet ends
+    position: u64,
+    ///The start of the earliest line which has been read, its beginning is in the previous block
+    partial: Vec<u8>,
+    ///The complete lines of the block which has been read last, the last one is returned first
+    lines: Vec<Vec<u8>>,
+}
+
+impl ReverseLines {
+    fn new(file: File) -> std::io::Result<Self> {
+        let position = file.metadata()?.len();
+
+        Ok(Self {
+            file,
+            position,
+            partial: Vec::new(),
+            lines: Vec::new(),
+        })
+    }
+
+    fn read_block(&mut self) -> std::io::Result<()> {
+        let length = self.position.min(BLOCK_SIZE);
+        self.position -= length;
+
+        let mut block = vec![0; length as usize];
+
+        self.file.seek(SeekFrom::Start(self.position))?;
+        self.file.read_exact(&mut block)?;
+
+        block.append(&mut self.partial);
+
+        let mut lines = block.split(|byte| *byte == b'\n').map(<[u8]>::to_vec);
+
+        //The first line might continue in the block before this one
+        self.partial = lines.next().unwrap_or_default();
+        self.lines = lines.collect();
+
+        Ok(())
+    }
+}
+
+impl Iterator for ReverseLines {
+    type Item = std::io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.pop() {
+                return Some(Ok(line));
+            }
+
+            if self.position == 0 {
+                return (!self.partial.is_empty()).then(|| Ok(std::mem::take(&mut self.partial)));
+            }
+
+            if let Err(err) = self.read_block() {
+                //Nothing more can be read
+                self.position = 0;
+                self.partial.clear();
+
+                return Some(Err(err));
+            }
+        }
+    }
+}
+
+fn open_log(path: &Path) -> anyhow::Result<(File, u64)> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open the audit log {:?}", path))?;
+
+    let size = file.metadata()?.len();
+
+    Ok((file, size))
+}
+
+fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{}", index));
+
+    PathBuf::from(name)
+}
+
+///Timestamps are written like 2024-01-01T12:00:00.000Z, so the log can be read without tools
+mod rfc3339 {
+    use std::time::SystemTime;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(time: &SystemTime, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(&humantime::format_rfc3339_millis(*time))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<SystemTime, D::Error> {
+        let time = String::deserialize(deserializer)?;
+
+        humantime::parse_rfc3339(&time).map_err(serde::de::Error::custom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use common_definitions::testing::TempFolder;
+
+    use super::*;
+
+    fn entry(action: AuditAction, path: &str) -> AuditEntry {
+        AuditEntry::new(action, None).paths(vec![PathBuf::from(path)])
+    }
+
+    #[test]
+    fn pages_through_the_matching_entries_newest_first() {
+        let folder = TempFolder::new("audit-pages");
+        let audit = AuditLog::open(folder.join("audit.jsonl")).unwrap();
+
+        for index in 0..5 {
+            audit.record(entry(AuditAction::Download, &format!("/file{}", index)));
+            audit.record(entry(AuditAction::List, "/"));
+        }
+
+        //Dropping waits until everything has been written
+        let path = audit.path().to_path_buf();
+        drop(audit);
+        let audit = AuditLog::open(path).unwrap();
+
+        let downloads = |entry: &AuditEntry| entry.action == AuditAction::Download;
+        let paths = |page: &AuditPage| -> Vec<String> {
+            page.entries
+                .iter()
+                .map(|entry| entry.paths[0].display().to_string())
+                .collect()
+        };
+
+        let first = audit.read_page(downloads, 0, 2).unwrap();
+        assert_eq!(paths(&first), ["/file4", "/file3"]);
+        assert!(first.more);
+
+        let last = audit.read_page(downloads, 4, 2).unwrap();
+        assert_eq!(paths(&last), ["/file0"]);
+        assert!(!last.more);
+        assert_eq!(audit.lost(), (0, 0));
+    }
+}
@@ -0,0 +1,462 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, Ipv6Addr, SocketAddr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use common_definitions::error::ServiceError;
+
+///How many logins can fail before the address is locked out
+const FREE_FAILURES: u32 = 5;
+
+///How long the first lockout lasts, every further failure doubles it
+const BASE_LOCKOUT: Duration = Duration::from_secs(30);
+
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+///The failures of an address are forgotten once it has not failed for this long
+const FAILURE_MEMORY: Duration = Duration::from_secs(24 * 60 * 60);
+
+///How many addresses are tracked before the ones which do not matter anymore are dropped
+const MAX_TRACKED: usize = 4096;
+
+///How many challenges each address can ask for per second, even if the rate limit is turned off, as each of them is kept until it is answered
+const CHALLENGE_RATE: u32 = 5;
+
+///The failed logins of an address
+struct Failures {
+    count: u32,
+    last: Instant,
+    locked_until: Option<Instant>,
+}
+
+///The requests an address can still send right away, refilled at the rate limit
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+///An address which cannot log in at the moment, as it is shown in the server's window
+#[derive(Debug, Clone)]
+pub struct Lockout {
+    ///For IPv6 the /64 prefix, with the rest of the address set to zeros
+    pub address: IpAddr,
+    ///How many logins have failed in a row
+    pub failures: u32,
+    pub remaining: Duration,
+}
+
+///Locks out the addresses logins keep failing from, and limits how fast each address can send requests
+///
+///Requests without a remote address are never limited.
+///IPv6 addresses are tracked by their /64 prefix, as a single client usually gets a whole one and can pick any address in it
+pub struct Guard {
+    ///Requests per second of each address, 0 turns the limit off
+    rate_limit: u32,
+    failures: Mutex<HashMap<IpAddr, Failures>>,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    ///The challenges are limited on their own
+    challenge_buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl Guard {
+    pub fn new(rate_limit: u32) -> Self {
+        Self {
+            rate_limit,
+            failures: Mutex::default(),
+            buckets: Mutex::default(),
+            challenge_buckets: Mutex::default(),
+        }
+    }
+
+    ///Fails if the address is locked out
+    pub fn check_lockout(&self, peer: Option<SocketAddr>) -> Result<(), ServiceError> {
+        let Some(peer) = peer else {
+            return Ok(());
+        };
+
+        let failures = self.failures.lock().unwrap();
+
+        match failures
+            .get(&tracked_address(peer))
+            .and_then(|failures| failures.locked_until)
+        {
+            Some(until) if until > Instant::now() => {
+                //Seconds are enough, the rest would only be noise
+                let remaining = Duration::from_secs(
+                    until.saturating_duration_since(Instant::now()).as_secs() + 1,
+                );
+
+                Err(ServiceError::RateLimited(format!(
+                    "Too many failed logins from this address, try again in {}",
+                    humantime::format_duration(remaining)
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    ///Counts a failed login, the address is locked out for longer with every failure past the free ones
+    pub fn record_failure(&self, peer: Option<SocketAddr>) {
+        let Some(peer) = peer else {
+            return;
+        };
+
+        let now = Instant::now();
+        let mut failures = self.failures.lock().unwrap();
+
+        let address = tracked_address(peer);
+
+        if failures.len() >= MAX_TRACKED && !failures.contains_key(&address) {
+            failures.retain(|_, failures| is_remembered(failures, now));
+
+            //Failures from many addresses at once are all remembered, so the ones which have failed the longest ago make room
+            evict_oldest(&mut failures, |failures| failures.last);
+        }
+
+        let entry = failures.entry(address).or_insert(Failures {
+            count: 0,
+            last: now,
+            locked_until: None,
+        });
+
+        if !is_remembered(entry, now) {
+            entry.count = 0;
+        }
+
+        entry.count += 1;
+        entry.last = now;
+
+        if entry.count >= FREE_FAILURES {
+            let doublings = (entry.count - FREE_FAILURES).min(16);
+
+            entry.locked_until = Some(now + (BASE_LOCKOUT * 2u32.pow(doublings)).min(MAX_LOCKOUT));
+        }
+    }
+
+    ///A successful login forgets the failures of the address
+    pub fn record_success(&self, peer: Option<SocketAddr>) {
+        if let Some(peer) = peer {
+            self.failures.lock().unwrap().remove(&tracked_address(peer));
+        }
+    }
+
+    ///Fails if the address has sent more requests than the rate limit allows, a second's worth of them can be sent at once
+    pub fn check_rate(&self, peer: Option<SocketAddr>) -> Result<(), ServiceError> {
+        let (Some(peer), true) = (peer, self.rate_limit > 0) else {
+            return Ok(());
+        };
+
+        if !take_token(&self.buckets, tracked_address(peer), self.rate_limit) {
+            return Err(ServiceError::RateLimited(
+                "Too many requests from this address".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    ///Fails if the address has asked for more challenges than it could answer
+    pub fn check_challenge_rate(&self, peer: Option<SocketAddr>) -> Result<(), ServiceError> {
+        let Some(peer) = peer else {
+            return Ok(());
+        };
+
+        if !take_token(
+            &self.challenge_buckets,
+            tracked_address(peer),
+            CHALLENGE_RATE,
+        ) {
+            return Err(ServiceError::RateLimited(
+                "Too many logins from this address".to_string(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    ///The addresses which are locked out, the longest lockout first
+    pub fn lockouts(&self) -> Vec<Lockout> {
+        let now = Instant::now();
+
+        let mut lockouts: Vec<Lockout> = self
+            .failures
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|(address, failures)| {
+                let until = failures.locked_until.filter(|until| *until > now)?;
+
+                Some(Lockout {
+                    address: *address,
+                    failures: failures.count,
+                    remaining: until.duration_since(now),
+                })
+            })
+            .collect();
+
+        lockouts.sort_by_key(|lockout| std::cmp::Reverse(lockout.remaining));
+
+        lockouts
+    }
+
+    ///Lets the address log in again, and forgets its failures
+    pub fn clear(&self, address: IpAddr) {
+        self.failures
+            .lock()
+            .unwrap()
+            .remove(&tracked_address(SocketAddr::new(address, 0)));
+    }
+
+    pub fn clear_all(&self) {
+        self.failures.lock().unwrap().clear();
+    }
+}
+
+///Takes one of the requests the address can send from its bucket, which refills at the rate per second, false if it is empty
+fn take_token(buckets: &Mutex<HashMap<IpAddr, Bucket>>, address: IpAddr, rate: u32) -> bool {
+    let now = Instant::now();
+    let rate = rate as f64;
+    let mut buckets = buckets.lock().unwrap();
+
+    //Addresses whose buckets have filled up again are the same as new ones
+    if buckets.len() >= MAX_TRACKED && !buckets.contains_key(&address) {
+        buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate < rate
+        });
+
+        evict_oldest(&mut buckets, |bucket| bucket.updated);
+    }
+
+    let bucket = buckets.entry(address).or_insert(Bucket {
+        tokens: rate,
+        updated: now,
+    });
+
+    bucket.tokens =
+        (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * rate).min(rate);
+    bucket.updated = now;
+
+    if bucket.tokens < 1.0 {
+        return false;
+    }
+
+    bucket.tokens -= 1.0;
+
+    true
+}
+
+///Drops the entries which have been updated the longest ago until there is room for another one
+fn evict_oldest<T>(entries: &mut HashMap<IpAddr, T>, updated: impl Fn(&T) -> Instant) {
+    let excess = (entries.len() + 1).saturating_sub(MAX_TRACKED);
+
+    if excess == 0 {
+        return;
+    }
+
+    let mut by_age: Vec<(Instant, IpAddr)> = entries
+        .iter()
+        .map(|(address, entry)| (updated(entry), *address))
+        .collect();
+
+    by_age.sort_unstable();
+
+    for (_, address) in by_age.into_iter().take(excess) {
+        entries.remove(&address);
+    }
+}
+
+///The address a client is tracked by, IPv6 addresses are cut down to their /64 prefix
+fn tracked_address(peer: SocketAddr) -> IpAddr {
+    match peer.ip() {
+        IpAddr::V6(address) => match address.to_ipv4_mapped() {
+            Some(address) => IpAddr::V4(address),
+            None => {
+                let mut segments = address.segments();
+                segments[4..].fill(0);
+
+                IpAddr::V6(Ipv6Addr::from(segments))
+            }
+        },
+        address => address,
+    }
+}
+
+///Failures are remembered while the address is locked out, and for a while after its last one
+fn is_remembered(failures: &Failures, now: Instant) -> bool {
+    failures.locked_until.is_some_and(|until| until > now)
+        || now.duration_since(failures.last) < FAILURE_MEMORY
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn peer(last: u8) -> Option<SocketAddr> {
+        Some(SocketAddr::from(([192, 0, 2, last], 4000)))
+    }
+
+    ///How long the address is locked out for, rounded to seconds
+    fn lockout(guard: &Guard, peer: Option<SocketAddr>) -> Option<u64> {
+        guard
+            .lockouts()
+            .into_iter()
+            .find(|lockout| Some(lockout.address) == peer.map(|peer| peer.ip()))
+            .map(|lockout| (lockout.remaining + Duration::from_millis(500)).as_secs())
+    }
+
+    #[test]
+    fn locks_out_after_the_free_failures() {
+        let guard = Guard::new(0);
+
+        for _ in 1..FREE_FAILURES {
+            guard.record_failure(peer(1));
+        }
+
+        assert!(guard.check_lockout(peer(1)).is_ok());
+        assert_eq!(lockout(&guard, peer(1)), None);
+
+        guard.record_failure(peer(1));
+
+        assert!(matches!(
+            guard.check_lockout(peer(1)),
+            Err(ServiceError::RateLimited(_))
+        ));
+        assert_eq!(lockout(&guard, peer(1)), Some(BASE_LOCKOUT.as_secs()));
+        //Other addresses are not affected
+        assert!(guard.check_lockout(peer(2)).is_ok());
+    }
+
+    #[test]
+    fn every_further_failure_doubles_the_lockout_up_to_the_maximum() {
+        let guard = Guard::new(0);
+
+        for _ in 0..FREE_FAILURES + 2 {
+            guard.record_failure(peer(1));
+        }
+
+        assert_eq!(lockout(&guard, peer(1)), Some(BASE_LOCKOUT.as_secs() * 4));
+
+        for _ in 0..32 {
+            guard.record_failure(peer(1));
+        }
+
+        assert_eq!(lockout(&guard, peer(1)), Some(MAX_LOCKOUT.as_secs()));
+    }
+
+    #[test]
+    fn a_success_or_clearing_forgets_the_failures() {
+        let guard = Guard::new(0);
+
+        for _ in 0..FREE_FAILURES {
+            guard.record_failure(peer(1));
+            guard.record_failure(peer(2));
+        }
+
+        guard.record_success(peer(1));
+        guard.clear(peer(2).unwrap().ip());
+
+        assert!(guard.check_lockout(peer(1)).is_ok());
+        assert!(guard.check_lockout(peer(2)).is_ok());
+
+        //The count starts over
+        guard.record_failure(peer(1));
+        assert!(guard.check_lockout(peer(1)).is_ok());
+    }
+
+    #[test]
+    fn requests_without_an_address_are_never_limited() {
+        let guard = Guard::new(1);
+
+        for _ in 0..FREE_FAILURES * 2 {
+            guard.record_failure(None);
+            assert!(guard.check_rate(None).is_ok());
+            assert!(guard.check_challenge_rate(None).is_ok());
+        }
+
+        assert!(guard.check_lockout(None).is_ok());
+        assert!(guard.lockouts().is_empty());
+    }
+
+    #[test]
+    fn limits_the_requests_per_second() {
+        let guard = Guard::new(3);
+
+        for _ in 0..3 {
+            assert!(guard.check_rate(peer(1)).is_ok());
+        }
+
+        assert!(guard.check_rate(peer(1)).is_err());
+        assert!(guard.check_rate(peer(2)).is_ok());
+
+        //A limit of 0 turns it off
+        let unlimited = Guard::new(0);
+
+        for _ in 0..100 {
+            assert!(unlimited.check_rate(peer(1)).is_ok());
+        }
+    }
+
+    #[test]
+    fn limits_the_challenges_even_without_a_rate_limit() {
+        let guard = Guard::new(0);
+
+        for _ in 0..CHALLENGE_RATE {
+            assert!(guard.check_challenge_rate(peer(1)).is_ok());
+        }
+
+        assert!(guard.check_challenge_rate(peer(1)).is_err());
+    }
+
+    #[test]
+    fn ipv6_addresses_of_the_same_prefix_are_locked_out_together() {
+        let guard = Guard::new(0);
+        let address = |last: u16| {
+            Some(SocketAddr::from((
+                [0x2001, 0xdb8, 0, 1, 0, 0, 0, last],
+                4000,
+            )))
+        };
+
+        for last in 0..FREE_FAILURES as u16 {
+            guard.record_failure(address(last));
+        }
+
+        assert!(guard.check_lockout(address(1000)).is_err());
+        assert!(guard
+            .check_lockout(Some(SocketAddr::from((
+                [0x2001, 0xdb8, 0, 2, 0, 0, 0, 1],
+                4000
+            ))))
+            .is_ok());
+    }
+
+    #[test]
+    fn failures_from_too_many_addresses_drop_the_oldest() {
+        let guard = Guard::new(0);
+        let address = |index: usize| {
+            Some(SocketAddr::from((
+                [10, (index >> 16) as u8, (index >> 8) as u8, index as u8],
+                4000,
+            )))
+        };
+
+        for _ in 0..FREE_FAILURES {
+            guard.record_failure(address(0));
+        }
+
+        for index in 1..MAX_TRACKED + 10 {
+            guard.record_failure(address(index));
+        }
+
+        assert!(guard.failures.lock().unwrap().len() <= MAX_TRACKED);
+        //The newest failures are kept, the first address has made room even though it is locked out
+        assert!(guard
+            .failures
+            .lock()
+            .unwrap()
+            .contains_key(&address(MAX_TRACKED + 9).unwrap().ip()));
+        assert!(guard.check_lockout(address(0)).is_ok());
+    }
+}
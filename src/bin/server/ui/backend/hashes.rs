@@ -0,0 +1,84 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use common_definitions::{hash_file, FileVersion};
+
+///How many digests are kept, the ones which have been used least recently are dropped first
+const MAX_DIGESTS: usize = 10_000;
+
+///The SHA-256 digests of the files which have been hashed, a digest is only reused while the file's size and modification time stay the same
+#[derive(Default)]
+pub struct HashCache {
+    digests: Mutex<Digests>,
+}
+
+#[derive(Default)]
+struct Digests {
+    ///The digests with the version they belong to and when they have last been used
+    entries: HashMap<PathBuf, (FileVersion, String, u64)>,
+    ///Counts up every time a digest is used
+    clock: u64,
+}
+
+impl HashCache {
+    ///Returns the digest if it has already been computed for this version of the file
+    pub fn cached(&self, path: &Path, version: &FileVersion) -> Option<String> {
+        let mut digests = self.digests.lock().unwrap();
+
+        digests.clock += 1;
+        let now = digests.clock;
+
+        digests
+            .entries
+            .get_mut(path)
+            .filter(|(cached_version, _, _)| cached_version == version)
+            .map(|(_, digest, used)| {
+                *used = now;
+
+                digest.clone()
+            })
+    }
+
+    ///Remembers a digest which has been computed elsewhere, like while the file was being sent
+    pub fn insert(&self, path: PathBuf, version: FileVersion, digest: String) {
+        let mut digests = self.digests.lock().unwrap();
+
+        digests.clock += 1;
+        let now = digests.clock;
+
+        if digests.entries.len() >= MAX_DIGESTS && !digests.entries.contains_key(&path) {
+            let oldest = digests
+                .entries
+                .iter()
+                .min_by_key(|(_, (_, _, used))| *used)
+                .map(|(path, _)| path.clone());
+
+            if let Some(oldest) = oldest {
+                digests.entries.remove(&oldest);
+            }
+        }
+
+        digests.entries.insert(path, (version, digest, now));
+    }
+
+    ///Returns the digest of the file, it is computed if it is not in the cache yet
+    pub async fn digest(&self, path: &Path, version: FileVersion) -> std::io::Result<String> {
+        if let Some(digest) = self.cached(path, &version) {
+            return Ok(digest);
+        }
+
+        let owned_path = path.to_path_buf();
+
+        //Hashing reads the whole file, so it should not block the runtime
+        let digest = tokio::task::spawn_blocking(move || hash_file(&owned_path))
+            .await
+            .map_err(std::io::Error::other)??;
+
+        self.insert(path.to_path_buf(), version, digest.clone());
+
+        Ok(digest)
+    }
+}
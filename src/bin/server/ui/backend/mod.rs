@@ -1 +1,10 @@
-pub mod server;
\ No newline at end of file
+pub mod accounts;
+pub mod api_keys;
+pub mod archive;
+pub mod audit;
+pub mod guard;
+pub mod hashes;
+pub mod server;
+pub mod sessions;
+pub mod share_links;
+pub mod watcher;
@@ -1,19 +1,486 @@
-use self::messages::{serving_server::Serving, serving_server::ServingServer};
-use tokio::sync::mpsc::Receiver;
+//tonic::Status is large, but it is what the interceptor has to return
+#![allow(clippy::result_large_err)]
 
-use tonic::{async_trait, transport::Server, Request, Response, Status};
-use common_definitions::{ClientRequest, PathItem, ServerFile, ServerList, ServerReply};
+use common_definitions::messages::{
+    auth_server::AuthServer,
+    serving_server::{Serving, ServingServer},
+    upload_chunk::Chunk,
+    ArchiveRequest, DescribeLinkRequest, FileChunk, HostReply, HostRequest, LinkDescription,
+    TreeEvent, TreeEventKind, UploadChunk, UploadHeader, WatchRequest,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    io::SeekFrom,
+    net::SocketAddr,
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt, AsyncWriteExt, Take},
+    sync::{
+        broadcast::{self, error::RecvError},
+        mpsc::{self, Receiver},
+    },
+};
+use tokio_stream::wrappers::ReceiverStream;
 
-pub mod messages {
-    tonic::include_proto!("file_hosting");
+use super::{
+    accounts::{Access, Accounts},
+    api_keys::{ApiKeyId, ApiKeys, Operation},
+    archive::{write_archive, ChunkWriter},
+    audit::{AuditAction, AuditEntry, AuditLog},
+    guard::Guard,
+    hashes::HashCache,
+    sessions::{AuthService, SessionUser, Sessions},
+    share_links::{self, ShareLinkId, ShareLinks},
+    watcher::ShareWatcher,
+};
+
+use common_definitions::{
+    auth::{api_key, bearer_token, random_bytes, share_link},
+    error::ServiceError,
+    iter_folder, to_hex, ClientRequest, DirectoryListing, FileMetadata, FileStruct, FileVersion,
+    FolderItem, PathItem, ServerList, ServerReply, TreeChange,
+};
+use tonic::{
+    async_trait,
+    transport::{Identity, Server, ServerTlsConfig},
+    Request, Response, Status, Streaming,
+};
+
+///The size of a single chunk sent by `StreamFile`
+const FILE_CHUNK_SIZE: usize = 256 * 1024;
+
+///How many changes a client watching the shared folders can fall behind, before it is told to list them again
+const CHANGE_BACKLOG: usize = 256;
+
+///A shared folder as the server has been started with
+#[derive(Clone)]
+struct Share {
+    ///The path the folder has been shared with, access is granted to it by this path
+    path: PathBuf,
+    ///The canonicalized path, clients can only access files inside of these
+    canonical: PathBuf,
+    ///Files can only be uploaded into the folder if this is set, even by users who can write into it
+    writable: bool,
+}
+
+///What the server checks the requests against, the window keeps them to manage the sessions, accounts and keys while the server is running
+#[derive(Clone)]
+pub struct AccessControl {
+    pub sessions: Arc<Sessions>,
+    pub accounts: Arc<Accounts>,
+    pub api_keys: Arc<ApiKeys>,
+    pub share_links: Arc<ShareLinks>,
+    ///Locks out the addresses which keep failing to authenticate, and limits how fast each address can send requests
+    pub guard: Arc<Guard>,
+    ///Every request is recorded in it, also the ones which are turned away
+    pub audit: Arc<AuditLog>,
+}
+
+///Who has sent a request, the interceptor adds this to the extensions of the requests it lets through
+#[derive(Debug, Clone)]
+enum Caller {
+    User(SessionUser),
+    Key(ApiKeyId),
+    ///Can only download the file of the link, through `StreamFile`
+    Link(ShareLinkId),
+}
+
+///Decides what the callers can do in the shared folders, the accounts and keys can change while the server is running
+#[derive(Clone)]
+struct Permissions {
+    accounts: Arc<Accounts>,
+    api_keys: Arc<ApiKeys>,
+    ///Folders which have been deleted since they were shared are left out, they cannot be served anyway
+    shares: Arc<Vec<Share>>,
+}
+
+impl Permissions {
+    ///Uploading also needs a share which allows uploads, even for the callers who could write into it
+    fn allows(&self, caller: &Caller, share: &Share, operation: Operation) -> bool {
+        if operation == Operation::Upload && !share.writable {
+            return false;
+        }
+
+        match caller {
+            Caller::User(user) => {
+                let needed = match operation {
+                    Operation::List | Operation::Download => Access::Read,
+                    Operation::Upload => Access::ReadWrite,
+                };
+
+                self.accounts.read().access(&user.name, &share.path) >= needed
+            }
+            Caller::Key(id) => self
+                .api_keys
+                .get(*id)
+                .is_some_and(|key| key.allows(&share.path, operation)),
+            //Links are not granted any share, their file is checked on its own
+            Caller::Link(_) => false,
+        }
+    }
+
+    ///Checks if the caller can do the operation with the path, `None` if it is not inside of a shared folder
+    ///
+    ///Shares can be nested, it is enough if one of them allows it
+    fn path_allows(&self, caller: &Caller, path: &Path, operation: Operation) -> Option<bool> {
+        self.shares
+            .iter()
+            //Deleted files cannot be canonicalized, so the changes of the watcher can also be inside of the shared path
+            .filter(|share| path.starts_with(&share.canonical) || path.starts_with(&share.path))
+            .map(|share| self.allows(caller, share, operation))
+            .max()
+    }
+
+    ///The canonicalized paths of the shares the caller can do the operation in
+    fn roots(&self, caller: &Caller, operation: Operation) -> Vec<PathBuf> {
+        self.shares
+            .iter()
+            .filter(|share| self.allows(caller, share, operation))
+            .map(|share| share.canonical.clone())
+            .collect()
+    }
+
+    ///Leaves out the changes the caller cannot see, folders read from the disk do not know if the caller can upload into them
+    ///
+    ///A move between a folder the caller can see and one they cannot looks to them like the item has been removed or created
+    fn visible_change(&self, caller: &Caller, change: TreeChange) -> Option<TreeChange> {
+        let can_list = |path: &Path| self.path_allows(caller, path, Operation::List) == Some(true);
+
+        let mut change = match change {
+            TreeChange::Renamed { from, to } => match (can_list(&from), can_list(&to.get_path())) {
+                (true, true) => TreeChange::Renamed { from, to },
+                (true, false) => TreeChange::Removed(from),
+                (false, true) => TreeChange::Created(to),
+                (false, false) => return None,
+            },
+            change if can_list(&change.path()) => change,
+            _ => return None,
+        };
+
+        if let TreeChange::Created(PathItem::Folder(folder))
+        | TreeChange::Modified(PathItem::Folder(folder))
+        | TreeChange::Renamed {
+            to: PathItem::Folder(folder),
+            ..
+        } = &mut change
+        {
+            folder.writable = folder.path.canonicalize().is_ok_and(|canonical| {
+                self.path_allows(caller, &canonical, Operation::Upload) == Some(true)
+            });
+        }
+
+        Some(change)
+    }
 }
 
 pub struct FileService {
-    password: String,
+    ///The logged in clients, the interceptor only lets their requests through
+    sessions: Arc<Sessions>,
+    permissions: Permissions,
     file_list: Vec<PathItem>,
+    ///The largest file which can be uploaded, in bytes
+    max_upload_size: u64,
+    ///Digests are computed while a file is first downloaded
+    hashes: Arc<HashCache>,
+    ///The changes inside of the shared folders, sent to every client which is watching
+    changes: broadcast::Sender<TreeChange>,
+    share_links: Arc<ShareLinks>,
+    audit: Arc<AuditLog>,
 }
 
-use messages::{HostReply, HostRequest};
+impl FileService {
+    pub fn new(access: AccessControl, file_list: Vec<PathItem>, max_upload_size: u64) -> Self {
+        let shares = file_list
+            .iter()
+            .filter_map(|item| {
+                Some(Share {
+                    path: item.get_path(),
+                    canonical: item.get_path().canonicalize().ok()?,
+                    writable: matches!(item, PathItem::Folder(folder) if folder.writable),
+                })
+            })
+            .collect::<Vec<_>>()
+            .into();
+
+        Self {
+            sessions: access.sessions,
+            permissions: Permissions {
+                accounts: access.accounts,
+                api_keys: access.api_keys,
+                shares,
+            },
+            file_list,
+            max_upload_size,
+            hashes: Arc::default(),
+            changes: broadcast::channel(CHANGE_BACKLOG).0,
+            share_links: access.share_links,
+            audit: access.audit,
+        }
+    }
+
+    ///Starts the audit entry of a request, with the user or the name of the key it has been sent by
+    fn audit_entry(
+        &self,
+        caller: &Caller,
+        action: AuditAction,
+        peer: Option<SocketAddr>,
+    ) -> AuditEntry {
+        let entry = AuditEntry::new(action, peer);
+
+        match caller {
+            Caller::User(user) => entry.user(&user.name),
+            //The key might have been revoked since the interceptor has let the request through
+            Caller::Key(id) => entry.api_key(
+                self.permissions
+                    .api_keys
+                    .get(*id)
+                    .map(|key| key.name)
+                    .unwrap_or_else(|| format!("#{}", id.0)),
+            ),
+            Caller::Link(id) => entry.share_link(
+                self.share_links
+                    .get(*id)
+                    .map(|link| link.name)
+                    .unwrap_or_else(|| format!("#{}", id.0)),
+            ),
+        }
+    }
+
+    ///Records the request as failed, returns the error to send to the client
+    fn reject(&self, entry: AuditEntry, err: ServiceError) -> Status {
+        self.audit.record(entry.error(&err));
+
+        err.into()
+    }
+
+    ///Records the entry with the outcome of the request, and passes the result on
+    fn record<T>(
+        &self,
+        entry: AuditEntry,
+        result: Result<T, ServiceError>,
+    ) -> Result<T, ServiceError> {
+        self.audit.record(entry.result(&result));
+
+        result
+    }
+
+    ///Lists the shared folders the caller can list with their immediate children, deeper folders are listed with ListDirectory
+    fn list_shares(&self, caller: &Caller) -> ServerList {
+        ServerList::new(
+            self.file_list
+                .iter()
+                .filter_map(|item| {
+                    //Folders which have been deleted are still listed, nothing can be uploaded into them
+                    let share = self
+                        .permissions
+                        .shares
+                        .iter()
+                        .find(|share| share.path == item.get_path())
+                        .cloned()
+                        .unwrap_or(Share {
+                            path: item.get_path(),
+                            canonical: item.get_path(),
+                            writable: false,
+                        });
+
+                    if !self.permissions.allows(caller, &share, Operation::List) {
+                        return None;
+                    }
+
+                    let mut folder = FolderItem::new(item.get_path());
+
+                    //A folder we cannot read is still shown, just without entries
+                    let _ = folder.load();
+
+                    folder.writable = self.permissions.allows(caller, &share, Operation::Upload);
+                    mark_writable(&mut folder.entries, folder.writable);
+
+                    if let Ok(canonical) = folder.path.canonicalize() {
+                        self.fill_cached_hashes(&canonical, &mut folder.entries);
+                    }
+
+                    Some(PathItem::Folder(folder))
+                })
+                .collect(),
+        )
+    }
+
+    ///Lists a page of the immediate children of a shared folder or one of its subfolders
+    fn list_directory(
+        &self,
+        caller: &Caller,
+        path: &Path,
+        offset: u64,
+        limit: u64,
+    ) -> Result<DirectoryListing, ServiceError> {
+        let canonical = self.confine_path(caller, path, Operation::List)?;
+
+        if !canonical.is_dir() {
+            return Err(ServiceError::NotFound(
+                "The requested path is not a folder".to_string(),
+            ));
+        }
+
+        //Entries are listed under the path the client knows the folder by, links only if the caller could list their target
+        let mut entries = iter_folder(&path.to_path_buf(), |target| {
+            self.permissions
+                .path_allows(caller, target, Operation::List)
+                == Some(true)
+        })
+        .map_err(ServiceError::internal)?;
+
+        mark_writable(
+            &mut entries,
+            self.permissions
+                .path_allows(caller, &canonical, Operation::Upload)
+                == Some(true),
+        );
+        self.fill_cached_hashes(&canonical, &mut entries);
+
+        let total = entries.len() as u64;
+
+        let entries = entries
+            .into_iter()
+            .skip(offset as usize)
+            .take(if limit == 0 {
+                usize::MAX
+            } else {
+                limit as usize
+            })
+            .collect();
+
+        Ok(DirectoryListing {
+            path: path.to_path_buf(),
+            entries,
+            offset,
+            total,
+        })
+    }
+
+    ///Adds the digests which have already been computed to the files, the rest are left empty so listing stays fast
+    fn fill_cached_hashes(&self, canonical_folder: &Path, entries: &mut [PathItem]) {
+        for entry in entries {
+            if let PathItem::File(FileStruct {
+                path,
+                metadata: Some(metadata),
+            }) = entry
+            {
+                let version = FileVersion {
+                    file_size: metadata.file_size,
+                    file_modified: metadata.file_modified,
+                };
+
+                metadata.sha256 = self.hashes.cached(
+                    &canonical_folder.join(path.file_name().unwrap_or_default()),
+                    &version,
+                );
+            }
+        }
+    }
+
+    ///Resolves the path the client asked for, and makes sure it points inside of a shared folder the caller can do the operation in
+    fn confine_path(
+        &self,
+        caller: &Caller,
+        requested: &Path,
+        operation: Operation,
+    ) -> Result<PathBuf, ServiceError> {
+        if requested
+            .components()
+            .any(|component| component == Component::ParentDir)
+        {
+            return Err(ServiceError::OutsideShare(
+                "Parent directory references are not allowed".to_string(),
+            ));
+        }
+
+        //This also resolves symlinks, so a link pointing outside of a shared folder is rejected too
+        let canonical = requested
+            .canonicalize()
+            .map_err(|err| ServiceError::NotFound(format!("{}: {}", requested.display(), err)))?;
+
+        match self.permissions.path_allows(caller, &canonical, operation) {
+            Some(true) => Ok(canonical),
+            Some(false) if operation == Operation::Upload => Err(ServiceError::PermissionDenied(
+                format!("Files cannot be uploaded into {}", requested.display()),
+            )),
+            Some(false) => Err(ServiceError::PermissionDenied(format!(
+                "You cannot access {}",
+                requested.display()
+            ))),
+            None => Err(ServiceError::OutsideShare(format!(
+                "{} is not inside of a shared folder",
+                requested.display()
+            ))),
+        }
+    }
+
+    ///Makes sure the client asks for the file of the link, and that it is still inside of a shared folder
+    fn confine_link(&self, id: ShareLinkId, requested: &Path) -> Result<PathBuf, ServiceError> {
+        let link = self.share_links.get(id).ok_or_else(share_links::revoked)?;
+
+        if requested != link.path {
+            return Err(ServiceError::PermissionDenied(
+                "The share link is for another file".to_string(),
+            ));
+        }
+
+        let canonical = requested
+            .canonicalize()
+            .map_err(|err| ServiceError::NotFound(format!("{}: {}", requested.display(), err)))?;
+
+        //The folder might not be shared anymore
+        if !self
+            .permissions
+            .shares
+            .iter()
+            .any(|share| canonical.starts_with(&share.canonical))
+        {
+            return Err(ServiceError::OutsideShare(format!(
+                "{} is not inside of a shared folder",
+                requested.display()
+            )));
+        }
+
+        Ok(canonical)
+    }
+
+    ///Tells the holder of a link which file it is for
+    fn link_description(&self, caller: &Caller) -> Result<LinkDescription, ServiceError> {
+        let Caller::Link(id) = caller else {
+            return Err(ServiceError::InvalidRequest(
+                "Only share links can be described".to_string(),
+            ));
+        };
+
+        let link = self.share_links.get(*id).ok_or_else(share_links::revoked)?;
+
+        let canonical = self.confine_link(*id, &link.path)?;
+
+        let metadata = std::fs::metadata(canonical)
+            .map_err(|err| ServiceError::NotFound(format!("{}: {}", link.path.display(), err)))?;
+
+        if !metadata.is_file() {
+            return Err(ServiceError::NotFound(
+                "The file of the share link has been replaced by a folder".to_string(),
+            ));
+        }
+
+        Ok(LinkDescription {
+            path: link.path.to_string_lossy().to_string(),
+            file_size: metadata.len(),
+            expires: Some(link.expires.into()),
+            max_downloads: link.max_downloads.unwrap_or_default().into(),
+            downloads: link.downloads.into(),
+        })
+    }
+}
 
 #[async_trait]
 impl Serving for FileService {
@@ -21,67 +488,1184 @@ impl Serving for FileService {
         &self,
         request: Request<HostRequest>,
     ) -> Result<Response<HostReply>, Status> {
+        let peer = request.remote_addr();
+        let caller = request_caller(&request)?;
         let request = request.into_inner();
 
-        dbg!(request.clone());
+        let request = match parse_request(request) {
+            Ok(request) => request,
+            Err(err) => {
+                let entry = self.audit_entry(&caller, AuditAction::Rejected, peer);
 
-        let struct_string = request.serialized_request;
-        let password = request.password;
+                return Err(self.reject(entry, err));
+            }
+        };
 
-        if password == self.password {
-            if let Ok(req) = serde_json::from_str::<ClientRequest>(&struct_string) {
-                let request = match req {
-                    ClientRequest::FileRequest(path) => ServerReply::File(ServerFile::new(path)),
-                    ClientRequest::ListRequest => {
-                        ServerReply::List(ServerList::new(self.file_list.clone()))
-                    }
+        let (entry, reply) = match request {
+            ClientRequest::ListRequest => (
+                self.audit_entry(&caller, AuditAction::List, peer),
+                Ok(ServerReply::List(self.list_shares(&caller))),
+            ),
+            ClientRequest::ListDirectory {
+                path,
+                offset,
+                limit,
+            } => (
+                self.audit_entry(&caller, AuditAction::ListDirectory, peer)
+                    .paths(vec![path.clone()]),
+                self.list_directory(&caller, &path, offset, limit)
+                    .map(ServerReply::Directory),
+            ),
+            //Files are only sent through StreamFile
+            ClientRequest::FileRequest { path, .. } => (
+                self.audit_entry(&caller, AuditAction::Download, peer)
+                    .paths(vec![path]),
+                Err(ServiceError::InvalidRequest(
+                    "Files can only be requested through StreamFile".to_string(),
+                )),
+            ),
+            ClientRequest::Stat { path } => (
+                self.audit_entry(&caller, AuditAction::Stat, peer)
+                    .paths(vec![path.clone()]),
+                self.stat_file(&caller, &path).await.map(ServerReply::Stat),
+            ),
+        };
+
+        let reply = self.record(entry, reply)?;
+
+        Ok(Response::new(HostReply::reply(reply)))
+    }
+
+    type StreamFileStream = ReceiverStream<Result<FileChunk, Status>>;
+
+    async fn stream_file(
+        &self,
+        request: Request<HostRequest>,
+    ) -> Result<Response<Self::StreamFileStream>, Status> {
+        let peer = request.remote_addr();
+        let caller = request_caller(&request)?;
+        let request = request.into_inner();
+
+        let mut entry = self.audit_entry(&caller, AuditAction::Download, peer);
+
+        let opened = match parse_request(request) {
+            Ok(ClientRequest::FileRequest {
+                path,
+                offset,
+                length,
+                expected_version,
+            }) => {
+                entry = entry.paths(vec![path.clone()]);
+
+                self.open_download(&caller, &path, offset, length, expected_version)
+                    .await
+            }
+            Ok(_) => Err(ServiceError::InvalidRequest(
+                "StreamFile only sends files".to_string(),
+            )),
+            Err(err) => Err(err),
+        };
+
+        let opened = match opened {
+            Ok(opened) => opened,
+            Err(err) => return Err(self.reject(entry, err)),
+        };
+
+        //The download counts against the limit of the link while it is running
+        let file_size = opened.version.file_size;
+        let link = match caller {
+            Caller::Link(id) => match self.share_links.start_download(id) {
+                Ok(()) => Some(id),
+                Err(err) => return Err(self.reject(entry, err)),
+            },
+            _ => None,
+        };
+
+        //The channel is bounded so we only read ahead a few chunks of a slow client
+        let (sx, rx) = mpsc::channel(4);
+        let audit = self.audit.clone();
+        let share_links = self.share_links.clone();
+        let hashes = self.hashes.clone();
+
+        //The download is recorded once it has ended, with how much of it has been sent
+        tokio::spawn(async move {
+            let (sent, result) = send_file(opened, &hashes, sx).await;
+
+            //What has been sent counts even if the client has dropped the stream before its end
+            if let Some(id) = link {
+                share_links.finish_download(id, sent, file_size);
+            }
+
+            audit.record(entry.bytes(sent).result(&result));
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn upload_file(
+        &self,
+        request: Request<Streaming<UploadChunk>>,
+    ) -> Result<Response<HostReply>, Status> {
+        let peer = request.remote_addr();
+        let caller = request_caller(&request)?;
+        let mut stream = request.into_inner();
+
+        let entry = self.audit_entry(&caller, AuditAction::Upload, peer);
+
+        let header = match stream.message().await.map_err(ServiceError::from) {
+            Ok(Some(UploadChunk {
+                chunk: Some(Chunk::Header(header)),
+            })) => header,
+            Ok(_) => {
+                let err = ServiceError::InvalidRequest(
+                    "The upload has to start with a header".to_string(),
+                );
+
+                return Err(self.reject(entry, err));
+            }
+            Err(err) => return Err(self.reject(entry, err)),
+        };
+
+        //Recorded under the path the client knows the folder by
+        let entry = entry.paths(vec![PathBuf::from(&header.folder).join(&header.file_name)]);
+        let file_size = header.file_size;
+
+        let result = self.receive_upload(&caller, header, stream).await;
+
+        let entry = match result {
+            Ok(_) => entry.bytes(file_size),
+            Err(_) => entry,
+        };
+
+        let file = self.record(entry, result)?;
+
+        Ok(Response::new(HostReply::reply(ServerReply::Uploaded(file))))
+    }
+
+    type StreamArchiveStream = ReceiverStream<Result<FileChunk, Status>>;
+
+    async fn stream_archive(
+        &self,
+        request: Request<ArchiveRequest>,
+    ) -> Result<Response<Self::StreamArchiveStream>, Status> {
+        let peer = request.remote_addr();
+        let caller = request_caller(&request)?;
+        let request = request.into_inner();
+
+        let entry = self
+            .audit_entry(&caller, AuditAction::Archive, peer)
+            .paths(request.paths.iter().map(PathBuf::from).collect());
+
+        if request.paths.is_empty() {
+            let err = ServiceError::InvalidRequest("No paths to archive".to_string());
+
+            return Err(self.reject(entry, err));
+        }
+
+        let format = request.format().into();
+
+        //Every path is checked before anything is sent, the items are named in the archive like the client knows them
+        let selected = request
+            .paths
+            .iter()
+            .map(|path| {
+                let path = PathBuf::from(path);
+                let canonical = self.confine_path(&caller, &path, Operation::Download)?;
+
+                let name = path
+                    .file_name()
+                    .or(canonical.file_name())
+                    .map(PathBuf::from)
+                    .unwrap_or_default();
+
+                Ok((canonical, name))
+            })
+            .collect::<Result<Vec<_>, ServiceError>>();
+
+        let selected = match selected {
+            Ok(selected) => selected,
+            Err(err) => return Err(self.reject(entry, err)),
+        };
+
+        //Links are only followed into the shares the caller can download from
+        let shared_roots = self.permissions.roots(&caller, Operation::Download);
+
+        //The channel is bounded so we only generate a few chunks ahead of a slow client
+        let (sx, rx) = mpsc::channel(4);
+
+        let audit = self.audit.clone();
+
+        //The archivers only write into blocking writers
+        tokio::task::spawn_blocking(move || {
+            let sent = Arc::new(AtomicU64::new(0));
+            let writer = ChunkWriter::new(sx.clone(), sent.clone());
+
+            let result = write_archive(format, selected, &shared_roots, writer).map_err(|err| {
+                //Nobody is left to tell if the client has disconnected
+                if err.kind() == std::io::ErrorKind::BrokenPipe {
+                    return client_gone();
+                }
+
+                let err = ServiceError::internal(err);
+                let _ = sx.blocking_send(Err(err.clone().into()));
+
+                err
+            });
+
+            //The archive is recorded once it has ended, with how much of it has been sent and what has been left out
+            let entry = match &result {
+                Ok(skipped) => entry.skipped(skipped.clone()),
+                Err(_) => entry,
+            };
+
+            audit.record(entry.bytes(sent.load(Ordering::Relaxed)).result(&result));
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    async fn describe_link(
+        &self,
+        request: Request<DescribeLinkRequest>,
+    ) -> Result<Response<LinkDescription>, Status> {
+        let peer = request.remote_addr();
+        let caller = request_caller(&request)?;
+
+        let result = self.link_description(&caller);
+
+        let entry = self.audit_entry(&caller, AuditAction::DescribeLink, peer);
+
+        let entry = match &result {
+            Ok(link) => entry.paths(vec![PathBuf::from(&link.path)]),
+            Err(_) => entry,
+        };
+
+        Ok(Response::new(self.record(entry, result)?))
+    }
+
+    type WatchStream = ReceiverStream<Result<TreeEvent, Status>>;
+
+    async fn watch(
+        &self,
+        request: Request<WatchRequest>,
+    ) -> Result<Response<Self::WatchStream>, Status> {
+        let caller = request_caller(&request)?;
+        let sessions = self.sessions.clone();
+        let permissions = self.permissions.clone();
+
+        let entry = self.audit_entry(&caller, AuditAction::Watch, request.remote_addr());
+
+        if let Caller::Link(_) = caller {
+            let err = ServiceError::PermissionDenied(
+                "Share links can only download their file".to_string(),
+            );
+
+            return Err(self.reject(entry, err));
+        }
+
+        self.audit.record(entry);
+
+        let mut changes = self.changes.subscribe();
+
+        let (sx, rx) = mpsc::channel(16);
+
+        tokio::spawn(async move {
+            loop {
+                let change = changes.recv().await;
+
+                //The stream outlives the check of the interceptor, so a revoked or expired session or key is noticed here
+                let active = match &caller {
+                    Caller::User(user) => sessions.is_active(user.id),
+                    Caller::Key(id) => permissions
+                        .api_keys
+                        .get(*id)
+                        .is_some_and(|key| !key.is_expired()),
+                    //Links are turned away above
+                    Caller::Link(_) => false,
                 };
 
-                return Ok(Response::new(HostReply {
-                    serialized_reply: request.serialize(),
-                }));
-            } else {
-                let response = HostReply {
-                    serialized_reply: "Invalid message? CONTACT ADMIN".to_string(),
+                if !active {
+                    let ended = ServiceError::Unauthenticated("The session has ended".to_string());
+
+                    let _ = sx.send(Err(ended.into())).await;
+
+                    break;
+                }
+
+                let event = match change {
+                    //The grants can change while the client is watching, so they are checked for every change
+                    Ok(change) => match permissions.visible_change(&caller, change) {
+                        Some(change) => TreeEvent::from(change),
+                        None => continue,
+                    },
+                    Err(RecvError::Lagged(_)) => TreeEvent {
+                        kind: TreeEventKind::Resync as i32,
+                        ..Default::default()
+                    },
+                    //The server is shutting down
+                    Err(RecvError::Closed) => break,
                 };
 
-                return Ok(Response::new(response));
+                if sx.send(Ok(event)).await.is_err() {
+                    //The client has stopped watching
+                    break;
+                }
             }
-        } else {
-            let response = HostReply {
-                serialized_reply: "Invalid password!".to_string(),
-            };
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+}
+
+///Checks if the canonicalized path is inside of one of the folders
+pub(super) fn is_inside(roots: &[PathBuf], canonical: &Path) -> bool {
+    roots.iter().any(|root| canonical.starts_with(root))
+}
+
+///The user or API key the interceptor has found the request to be sent by
+fn request_caller<T>(request: &Request<T>) -> Result<Caller, ServiceError> {
+    request
+        .extensions()
+        .get::<Caller>()
+        .cloned()
+        .ok_or_else(|| ServiceError::Unauthenticated("The request has no session".to_string()))
+}
+
+impl FileService {
+    ///Opens the requested part of the file, after making sure it is the version the client expects
+    async fn open_download(
+        &self,
+        caller: &Caller,
+        path: &Path,
+        offset: u64,
+        length: u64,
+        expected_version: Option<FileVersion>,
+    ) -> Result<OpenedFile, ServiceError> {
+        let path = match caller {
+            Caller::Link(id) => self.confine_link(*id, path)?,
+            _ => self.confine_path(caller, path, Operation::Download)?,
+        };
+
+        if !path.is_file() {
+            return Err(ServiceError::NotFound(
+                "The requested path is not a file".to_string(),
+            ));
+        }
+
+        let mut file = File::open(&path)
+            .await
+            .map_err(|err| ServiceError::NotFound(err.to_string()))?;
+
+        let version = file
+            .metadata()
+            .await
+            .and_then(|metadata| FileVersion::from_fs_metadata(&metadata))
+            .map_err(ServiceError::internal)?;
+
+        //The client is resuming a download of a file which has changed since
+        if expected_version.is_some_and(|expected| expected != version) {
+            return Err(ServiceError::FileChanged(
+                "The file has changed since the download started".to_string(),
+            ));
+        }
+
+        //The file has shrunk since the client has started downloading it
+        if offset > version.file_size {
+            return Err(ServiceError::FileChanged(
+                "The offset is past the end of the file".to_string(),
+            ));
+        }
+
+        file.seek(SeekFrom::Start(offset))
+            .await
+            .map_err(ServiceError::internal)?;
+
+        let remaining = version.file_size - offset;
+        let to_end = length == 0 || length >= remaining;
+
+        Ok(OpenedFile {
+            file: file.take(if to_end { remaining } else { length }),
+            path,
+            version,
+            offset,
+            to_end,
+        })
+    }
+
+    ///The metadata of the file with its digest, which is computed unless it has been cached
+    ///
+    ///It can be asked for by everyone who can download the file, as the digest tells about its contents
+    async fn stat_file(&self, caller: &Caller, path: &Path) -> Result<FileStruct, ServiceError> {
+        let canonical = match caller {
+            Caller::Link(id) => self.confine_link(*id, path)?,
+            _ => self.confine_path(caller, path, Operation::Download)?,
+        };
+
+        let metadata = tokio::fs::metadata(&canonical)
+            .await
+            .map_err(|err| ServiceError::NotFound(err.to_string()))?;
+
+        if !metadata.is_file() {
+            return Err(ServiceError::NotFound(
+                "The requested path is not a file".to_string(),
+            ));
+        }
+
+        let version = FileVersion::from_fs_metadata(&metadata).map_err(ServiceError::internal)?;
+
+        let mut metadata =
+            FileMetadata::from_fs_metadata(metadata).map_err(ServiceError::internal)?;
+
+        metadata.sha256 = Some(
+            self.hashes
+                .digest(&canonical, version)
+                .await
+                .map_err(ServiceError::internal)?,
+        );
+
+        Ok(FileStruct {
+            path: path.to_path_buf(),
+            metadata: Some(metadata),
+        })
+    }
+
+    ///Checks where the file is going to be saved, then writes the uploaded chunks into it
+    async fn receive_upload(
+        &self,
+        caller: &Caller,
+        header: UploadHeader,
+        mut stream: Streaming<UploadChunk>,
+    ) -> Result<FileStruct, ServiceError> {
+        //The name cannot be used to escape the folder
+        let mut components = Path::new(&header.file_name).components();
+
+        if !matches!(
+            (components.next(), components.next()),
+            (Some(Component::Normal(_)), None)
+        ) {
+            return Err(ServiceError::InvalidRequest(format!(
+                "Invalid file name {:?}",
+                header.file_name
+            )));
+        }
+
+        let folder = self.confine_path(caller, Path::new(&header.folder), Operation::Upload)?;
+
+        if !folder.is_dir() {
+            return Err(ServiceError::NotFound(
+                "The folder to upload into does not exist".to_string(),
+            ));
+        }
+
+        if header.file_size > self.max_upload_size {
+            return Err(ServiceError::TooLarge(format!(
+                "The file is larger than the upload limit of {} bytes",
+                self.max_upload_size
+            )));
+        }
+
+        let destination = folder.join(&header.file_name);
+
+        //Symlinks are not followed, the link itself would be replaced
+        match std::fs::symlink_metadata(&destination) {
+            Ok(metadata) if metadata.is_dir() => {
+                return Err(ServiceError::InvalidRequest(
+                    "A folder with the same name already exists".to_string(),
+                ))
+            }
+            Ok(_) if !header.overwrite => {
+                return Err(ServiceError::AlreadyExists(format!(
+                    "{} already exists",
+                    header.file_name
+                )))
+            }
+            _ => {}
+        }
+
+        //The upload is written next to the destination, so it can be renamed into place once it is complete
+        let temporary = folder.join(format!(
+            ".{}.{}.upload",
+            header.file_name,
+            to_hex(&random_bytes())
+        ));
+
+        let result = match write_upload(&temporary, header.file_size, &mut stream).await {
+            Ok(()) => move_upload(&temporary, &destination, header.overwrite).await,
+            Err(err) => Err(err),
+        };
+
+        if let Err(err) = result {
+            let _ = tokio::fs::remove_file(&temporary).await;
+
+            return Err(err);
+        }
+
+        //The client knows the folder by the path it has asked for
+        let path = PathBuf::from(&header.folder).join(&header.file_name);
+
+        Ok(FileStruct {
+            metadata: std::fs::metadata(&destination)
+                .ok()
+                .and_then(|metadata| FileMetadata::from_fs_metadata(metadata).ok()),
+            path,
+        })
+    }
+}
+
+///Puts the complete upload in place, a file which has appeared while it was being uploaded is only replaced if the upload asks to overwrite it
+async fn move_upload(
+    temporary: &Path,
+    destination: &Path,
+    overwrite: bool,
+) -> Result<(), ServiceError> {
+    if overwrite {
+        return tokio::fs::rename(temporary, destination)
+            .await
+            .map_err(ServiceError::internal);
+    }
+
+    //Unlike renaming, linking fails if the destination exists
+    match tokio::fs::hard_link(temporary, destination).await {
+        Ok(()) => {
+            let _ = tokio::fs::remove_file(temporary).await;
+
+            Ok(())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => Err(
+            ServiceError::AlreadyExists(format!("{} already exists", destination.display())),
+        ),
+        Err(err) => Err(ServiceError::internal(err)),
+    }
+}
+
+///Writes the uploaded chunks into the file, the upload fails if it is not exactly the size announced in the header
+async fn write_upload(
+    path: &Path,
+    file_size: u64,
+    stream: &mut Streaming<UploadChunk>,
+) -> Result<(), ServiceError> {
+    //A file which already has the name is never written into, it could be a link to somewhere else
+    let mut file = tokio::fs::OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(path)
+        .await
+        .map_err(ServiceError::internal)?;
+
+    let mut written: u64 = 0;
+
+    while let Some(chunk) = stream.message().await? {
+        let data = match chunk.chunk {
+            Some(Chunk::Data(data)) => data,
+            _ => {
+                return Err(ServiceError::InvalidRequest(
+                    "Expected a data chunk".to_string(),
+                ))
+            }
+        };
+
+        written += data.len() as u64;
+
+        //The size limit was checked against the header
+        if written > file_size {
+            return Err(ServiceError::InvalidRequest(
+                "More data was sent than announced in the header".to_string(),
+            ));
+        }
+
+        file.write_all(&data)
+            .await
+            .map_err(ServiceError::internal)?;
+    }
+
+    if written != file_size {
+        return Err(ServiceError::InvalidRequest(
+            "The upload ended before the whole file was sent".to_string(),
+        ));
+    }
+
+    file.sync_all().await.map_err(ServiceError::internal)?;
+
+    Ok(())
+}
+
+///Sets if files can be uploaded into the folders
+fn mark_writable(entries: &mut [PathItem], writable: bool) {
+    for entry in entries {
+        if let PathItem::Folder(folder) = entry {
+            folder.writable = writable;
+        }
+    }
+}
+
+///Fails if the client has sent a malformed request
+fn parse_request(request: HostRequest) -> Result<ClientRequest, ServiceError> {
+    request
+        .request
+        .ok_or_else(|| ServiceError::InvalidRequest("The request is empty".to_string()))
+        .and_then(|request| {
+            ClientRequest::try_from(request)
+                .map_err(|err| ServiceError::InvalidRequest(format!("{:#}", err)))
+        })
+}
+
+///The part of a file a client has asked for, opened and ready to be sent
+struct OpenedFile {
+    file: Take<File>,
+    ///The canonical path, the digest is cached by it
+    path: PathBuf,
+    version: FileVersion,
+    offset: u64,
+    ///The file is sent to its end, so the digest of the whole file is sent after it
+    to_end: bool,
+}
+
+///Reads the file chunk by chunk and sends every chunk to the client, stops when the client disconnects
+///
+///If the file is sent to its end, a last chunk without data carries the digest of the whole file.
+///A download from the start is hashed while it is sent, so the first bytes are not held back by hashing a large file,
+///a resumed one is hashed once its data has been sent unless the digest has been cached.
+///
+///Returns how many bytes of the file have been sent, and if the whole file has been
+async fn send_file(
+    opened: OpenedFile,
+    hashes: &HashCache,
+    sx: mpsc::Sender<Result<FileChunk, Status>>,
+) -> (u64, Result<(), ServiceError>) {
+    let OpenedFile {
+        mut file,
+        path,
+        version,
+        offset,
+        to_end,
+    } = opened;
+
+    //The first chunk is always sent, so the client learns the version even if there is no data
+    let mut first_version = Some(version.into());
+    let mut hasher = (offset == 0 && to_end).then(Sha256::new);
+    let mut sent = 0;
+
+    loop {
+        let mut data = vec![0; FILE_CHUNK_SIZE];
+
+        match read_chunk(&mut file, &mut data).await {
+            //We have reached the end of the file
+            Ok(0) if first_version.is_none() => break,
+            Ok(read) => {
+                data.truncate(read);
+
+                if let Some(hasher) = &mut hasher {
+                    hasher.update(&data);
+                }
+
+                let chunk = FileChunk {
+                    data,
+                    version: first_version.take(),
+                    sha256: String::new(),
+                };
+
+                if sx.send(Ok(chunk)).await.is_err() {
+                    //The client has dropped the stream
+                    return (sent, Err(client_gone()));
+                }
+
+                sent += read as u64;
+            }
+            Err(err) => return (sent, send_error(&sx, ServiceError::internal(err)).await),
+        }
+    }
+
+    if !to_end {
+        return (sent, Ok(()));
+    }
+
+    //A file which has been written to while it was read would have a digest which matches the mix of both versions
+    let current = tokio::fs::metadata(&path)
+        .await
+        .and_then(|metadata| FileVersion::from_fs_metadata(&metadata));
+
+    if current.ok() != Some(version) {
+        let err =
+            ServiceError::FileChanged("The file has changed while it was being sent".to_string());
+
+        return (sent, send_error(&sx, err).await);
+    }
+
+    let sha256 = match hasher {
+        Some(hasher) => {
+            let digest = to_hex(&hasher.finalize());
+
+            hashes.insert(path, version, digest.clone());
+
+            digest
+        }
+        None => match hashes.digest(&path, version).await {
+            Ok(digest) => digest,
+            Err(err) => return (sent, send_error(&sx, ServiceError::internal(err)).await),
+        },
+    };
+
+    let chunk = FileChunk {
+        data: Vec::new(),
+        version: None,
+        sha256,
+    };
+
+    match sx.send(Ok(chunk)).await {
+        Ok(()) => (sent, Ok(())),
+        Err(_) => (sent, Err(client_gone())),
+    }
+}
+
+///Tells the client why the stream has ended early
+async fn send_error(
+    sx: &mpsc::Sender<Result<FileChunk, Status>>,
+    err: ServiceError,
+) -> Result<(), ServiceError> {
+    let _ = sx.send(Err(err.clone().into())).await;
+
+    Err(err)
+}
 
-            return Ok(Response::new(response));
+///A stream has ended early because the client has stopped reading it
+fn client_gone() -> ServiceError {
+    ServiceError::Unavailable("The client has disconnected".to_string())
+}
+
+///Fills up the buffer unless the end of the file is reached, returns the amount of bytes read
+async fn read_chunk(file: &mut Take<File>, buffer: &mut [u8]) -> std::io::Result<usize> {
+    let mut filled = 0;
+
+    while filled < buffer.len() {
+        match file.read(&mut buffer[filled..]).await? {
+            0 => break,
+            read => filled += read,
         }
     }
+
+    Ok(filled)
+}
+
+///Only lets the requests with the token of a session through, the id of the session is added to their extensions
+///
+///The requests it turns away are recorded in the audit log, the services record the rest
+fn interceptor_fn(access: &AccessControl, mut request: Request<()>) -> Result<Request<()>, Status> {
+    let peer = request.remote_addr();
+
+    match authenticate(access, &request) {
+        Ok(caller) => {
+            request.extensions_mut().insert(caller);
+
+            Ok(request)
+        }
+        Err(err) => {
+            access
+                .audit
+                .record(AuditEntry::new(AuditAction::Rejected, peer).error(&err));
+
+            Err(err.into())
+        }
+    }
+}
+
+///Finds who has sent the request, by their API key or the token of their session
+fn authenticate(access: &AccessControl, request: &Request<()>) -> Result<Caller, ServiceError> {
+    let peer = request.remote_addr();
+
+    access.guard.check_rate(peer)?;
+
+    let metadata = request.metadata();
+
+    //Scripts send an API key instead of logging in
+    if let Some(key) = api_key(metadata) {
+        return check_secret(access, peer, || access.api_keys.check(key).map(Caller::Key));
+    }
+
+    if let Some(token) = share_link(metadata) {
+        return check_secret(access, peer, || {
+            access.share_links.check(token).map(Caller::Link)
+        });
+    }
+
+    let token = bearer_token(metadata).ok_or_else(|| {
+        ServiceError::Unauthenticated("The request has no session token".to_string())
+    })?;
+
+    check_secret(access, peer, || {
+        access.sessions.check(token).map(Caller::User)
+    })
+}
+
+///Guessing an API key or the token of a share link or a session counts like a failed login
+///
+///A valid secret is let through even if its address is locked out, so someone failing to log in from the same address cannot lock out scripts or users which are already logged in
+fn check_secret(
+    access: &AccessControl,
+    peer: Option<SocketAddr>,
+    check: impl FnOnce() -> Result<Caller, ServiceError>,
+) -> Result<Caller, ServiceError> {
+    check().map_err(|err| {
+        access.guard.record_failure(peer);
+
+        //A locked out address is told so rather than why the secret is wrong
+        access.guard.check_lockout(peer).err().unwrap_or(err)
+    })
 }
 
-fn interceptor_fn(request: Request<()>) -> Result<Request<()>, Status> {
+///Logins are not authenticated yet, so only their rate is limited
+fn auth_interceptor(
+    guard: &Guard,
+    audit: &AuditLog,
+    request: Request<()>,
+) -> Result<Request<()>, Status> {
+    let peer = request.remote_addr();
+
+    if let Err(err) = guard.check_rate(peer) {
+        audit.record(AuditEntry::new(AuditAction::Rejected, peer).error(&err));
+
+        return Err(err.into());
+    }
+
     Ok(request)
 }
 
+///The failures are recorded in the audit log, as there is nobody else to tell
+fn watch_shares(
+    file_list: &[PathItem],
+    changes: broadcast::Sender<TreeChange>,
+    audit: Arc<AuditLog>,
+) -> Option<ShareWatcher> {
+    let record = |audit: &AuditLog, paths: Vec<PathBuf>, err: notify::Error| {
+        audit.record(
+            AuditEntry::new(AuditAction::Watch, None)
+                .paths(paths)
+                .error(&ServiceError::internal(format!("Failed to watch: {}", err))),
+        );
+    };
+
+    let errors = audit.clone();
+    let watcher = ShareWatcher::new(
+        move |change| {
+            //Nobody might be watching
+            let _ = changes.send(change);
+        },
+        move |err| record(&errors, err.paths.clone(), err),
+    );
+
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            record(&audit, Vec::new(), err);
+
+            return None;
+        }
+    };
+
+    for item in file_list {
+        if let Err(err) = watcher.watch(&item.get_path()) {
+            record(&audit, vec![item.get_path()], err);
+        }
+    }
+
+    Some(watcher)
+}
+
 async fn signal_checker(mut signal: Receiver<()>) {
     signal.recv().await;
 }
 
+///The clients have to log in with the `Auth` service, every other service checks their token in the interceptor
+///
+///The interceptors of both services limit how fast each address can send requests
 pub async fn server_spawner(
-    password: String,
+    access: AccessControl,
     port: i64,
     signal: Receiver<()>,
     file_list: Vec<PathItem>,
+    max_upload_size: u64,
+    identity: Identity,
 ) -> anyhow::Result<()> {
     let addr: std::net::SocketAddr = format!("[::]:{}", port).parse()?;
 
-    let service = FileService {
-        password,
-        file_list,
-    };
+    let service = FileService::new(access.clone(), file_list, max_upload_size);
+    let guard = access.guard.clone();
+    let audit = access.audit.clone();
+
+    //Watches the shared folders until the server stops, a server which cannot watch them still serves them
+    let _watcher = watch_shares(&service.file_list, service.changes.clone(), audit.clone());
 
     Server::builder()
-        .add_service(ServingServer::with_interceptor(service, interceptor_fn))
+        .tls_config(ServerTlsConfig::new().identity(identity))?
+        .add_service(AuthServer::with_interceptor(
+            AuthService::new(access.sessions.clone(), guard.clone(), audit.clone()),
+            move |request| auth_interceptor(&guard, &audit, request),
+        ))
+        .add_service(ServingServer::with_interceptor(service, move |request| {
+            interceptor_fn(&access, request)
+        }))
         .serve_with_shutdown(addr, signal_checker(signal))
         .await?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use common_definitions::testing::TempFolder;
+
+    use super::*;
+    use crate::ui::backend::{
+        accounts::{AccountsConfig, UserAccount},
+        sessions::SessionId,
+    };
+
+    ///`outer` is shared to alice and `outer/inner` is shared to bob on its own
+    struct Fixture {
+        folder: TempFolder,
+        service: FileService,
+    }
+
+    impl Fixture {
+        fn new(name: &str) -> Self {
+            let folder = TempFolder::with_files(
+                &format!("server-{}", name),
+                &["outer/file.txt", "outer/inner/nested.txt", "secret.txt"],
+            );
+
+            let outer = folder.join("outer");
+            let inner = folder.join("outer/inner");
+
+            let user = |name: &str| UserAccount {
+                name: name.to_string(),
+                password_hash: String::new(),
+                groups: Vec::new(),
+            };
+
+            let accounts = Arc::new(Accounts::new(AccountsConfig {
+                users: vec![user("alice"), user("bob")],
+                access: [
+                    (outer.clone(), [("alice".to_string(), Access::Read)].into()),
+                    (
+                        inner.clone(),
+                        [("bob".to_string(), Access::ReadWrite)].into(),
+                    ),
+                ]
+                .into(),
+            }));
+
+            let access = AccessControl {
+                sessions: Arc::new(Sessions::new(accounts.clone())),
+                accounts,
+                api_keys: Arc::default(),
+                share_links: Arc::default(),
+                guard: Arc::new(Guard::new(0)),
+                audit: Arc::new(AuditLog::open(folder.join("audit.jsonl")).unwrap()),
+            };
+
+            let mut writable = FolderItem::new(inner);
+            writable.writable = true;
+
+            let file_list = vec![
+                PathItem::Folder(FolderItem::new(outer)),
+                PathItem::Folder(writable),
+            ];
+
+            Self {
+                service: FileService::new(access, file_list, u64::MAX),
+                folder,
+            }
+        }
+
+        fn confine(
+            &self,
+            user: &str,
+            path: &str,
+            operation: Operation,
+        ) -> Result<PathBuf, ServiceError> {
+            self.service
+                .confine_path(&caller(user), &self.folder.join(path), operation)
+        }
+    }
+
+    fn caller(name: &str) -> Caller {
+        Caller::User(SessionUser {
+            id: SessionId(1),
+            name: name.to_string(),
+        })
+    }
+
+    #[test]
+    fn confine_path_accepts_files_inside_of_a_share() {
+        let fixture = Fixture::new("accept");
+
+        let path = fixture
+            .confine("alice", "outer/file.txt", Operation::Download)
+            .unwrap();
+
+        assert_eq!(
+            path,
+            fixture
+                .folder
+                .join("outer/file.txt")
+                .canonicalize()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn confine_path_rejects_parent_directories() {
+        let fixture = Fixture::new("parent");
+
+        //The path would resolve to a file inside of the share, it is still refused
+        let result = fixture.confine("alice", "outer/inner/../file.txt", Operation::Download);
+        assert!(matches!(result, Err(ServiceError::OutsideShare(_))));
+
+        let result = fixture.confine("alice", "outer/../secret.txt", Operation::Download);
+        assert!(matches!(result, Err(ServiceError::OutsideShare(_))));
+    }
+
+    #[test]
+    fn confine_path_rejects_paths_outside_of_the_shares() {
+        let fixture = Fixture::new("outside");
+
+        let result = fixture.confine("alice", "secret.txt", Operation::Download);
+
+        assert!(matches!(result, Err(ServiceError::OutsideShare(_))));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn confine_path_rejects_symlinks_out_of_a_share() {
+        let fixture = Fixture::new("symlink");
+
+        std::os::unix::fs::symlink(
+            fixture.folder.join("secret.txt"),
+            fixture.folder.join("outer/escape.txt"),
+        )
+        .unwrap();
+        std::os::unix::fs::symlink(
+            fixture.folder.join("outer/file.txt"),
+            fixture.folder.join("outer/inside.txt"),
+        )
+        .unwrap();
+
+        let result = fixture.confine("alice", "outer/escape.txt", Operation::Download);
+        assert!(matches!(result, Err(ServiceError::OutsideShare(_))));
+
+        //A link to a file inside of the share resolves to that file
+        let path = fixture
+            .confine("alice", "outer/inside.txt", Operation::Download)
+            .unwrap();
+        assert_eq!(
+            path,
+            fixture
+                .folder
+                .join("outer/file.txt")
+                .canonicalize()
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn confine_path_rejects_users_without_access() {
+        let fixture = Fixture::new("denied");
+
+        let result = fixture.confine("bob", "outer/file.txt", Operation::Download);
+        assert!(matches!(result, Err(ServiceError::PermissionDenied(_))));
+
+        let result = fixture.confine("mallory", "outer/inner/nested.txt", Operation::List);
+        assert!(matches!(result, Err(ServiceError::PermissionDenied(_))));
+    }
+
+    #[test]
+    fn nested_shares_allow_what_either_of_them_allows() {
+        let fixture = Fixture::new("nested");
+        let permissions = &fixture.service.permissions;
+        let inner = fixture.folder.join("outer/inner").canonicalize().unwrap();
+        let outer = fixture.folder.join("outer").canonicalize().unwrap();
+
+        //Alice reads the inner folder through the outer share
+        assert_eq!(
+            permissions.path_allows(&caller("alice"), &inner, Operation::Download),
+            Some(true)
+        );
+        //Bob only has the inner share
+        assert_eq!(
+            permissions.path_allows(&caller("bob"), &inner, Operation::Download),
+            Some(true)
+        );
+        assert_eq!(
+            permissions.path_allows(&caller("bob"), &outer, Operation::List),
+            Some(false)
+        );
+        //Uploads need a writable share the caller can write into
+        assert_eq!(
+            permissions.path_allows(&caller("bob"), &inner, Operation::Upload),
+            Some(true)
+        );
+        assert_eq!(
+            permissions.path_allows(&caller("alice"), &inner, Operation::Upload),
+            Some(false)
+        );
+        assert_eq!(
+            permissions.path_allows(&caller("alice"), fixture.folder.path(), Operation::List),
+            None
+        );
+    }
+
+    #[test]
+    fn moves_between_visible_and_hidden_folders_are_removals_or_creations() {
+        let fixture = Fixture::new("moves");
+        let permissions = &fixture.service.permissions;
+        let alice = caller("alice");
+
+        let moved = |from: &str, to: &str| TreeChange::Renamed {
+            from: fixture.folder.join(from),
+            to: PathItem::File(FileStruct {
+                path: fixture.folder.join(to),
+                metadata: None,
+            }),
+        };
+        let describe = |change: Option<TreeChange>| match change {
+            Some(TreeChange::Renamed { from, to }) => format!(
+                "renamed {} to {}",
+                fixture.folder.relative(&from),
+                fixture.folder.relative(&to.get_path())
+            ),
+            Some(TreeChange::Removed(path)) => {
+                format!("removed {}", fixture.folder.relative(&path))
+            }
+            Some(TreeChange::Created(item)) => {
+                format!("created {}", fixture.folder.relative(&item.get_path()))
+            }
+            Some(TreeChange::Modified(_)) => "modified".to_string(),
+            None => "hidden".to_string(),
+        };
+
+        let visible =
+            |from: &str, to: &str| describe(permissions.visible_change(&alice, moved(from, to)));
+
+        assert_eq!(
+            visible("outer/file.txt", "outer/renamed.txt"),
+            "renamed outer/file.txt to outer/renamed.txt"
+        );
+        assert_eq!(
+            visible("outer/file.txt", "moved.txt"),
+            "removed outer/file.txt"
+        );
+        assert_eq!(
+            visible("secret.txt", "outer/secret.txt"),
+            "created outer/secret.txt"
+        );
+        assert_eq!(visible("secret.txt", "moved.txt"), "hidden");
+
+        //Bob only sees the inner share
+        assert_eq!(
+            describe(permissions.visible_change(
+                &caller("bob"),
+                moved("outer/file.txt", "outer/inner/file.txt")
+            )),
+            "created outer/inner/file.txt"
+        );
+    }
+}
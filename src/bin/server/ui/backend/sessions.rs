@@ -0,0 +1,443 @@
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+
+use common_definitions::{
+    auth::{
+        bearer_token, decoy_settings, hash_settings, proof, random_bytes, stored_key,
+        verify_client_proof,
+    },
+    error::ServiceError,
+    messages::{
+        auth_server::Auth, ChallengeReply, ChallengeRequest, LoginReply, LoginRequest, LogoutReply,
+        LogoutRequest, RefreshRequest,
+    },
+    to_hex,
+};
+use sha2::{Digest, Sha256};
+use tonic::{async_trait, Request, Response, Status};
+
+use super::{
+    accounts::Accounts,
+    audit::{AuditAction, AuditEntry, AuditLog},
+    guard::Guard,
+};
+
+///How long a client has to answer a challenge, hashing the password takes it a second at most
+const CHALLENGE_TIMEOUT: Duration = Duration::from_secs(30);
+
+///How many challenges can wait for an answer at once, so clients cannot fill the memory with them, the oldest one is dropped for a new one
+const MAX_CHALLENGES: usize = 1024;
+
+///How many of them can be for the same address, so a single client cannot push out the challenges of everyone else
+const MAX_CHALLENGES_PER_ADDRESS: usize = 8;
+
+///How long a session token is valid for, clients refresh it before it expires
+pub const SESSION_LIFETIME: Duration = Duration::from_secs(15 * 60);
+
+///Identifies a session, it stays the same when the token is refreshed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionId(pub u64);
+
+///Who has sent a request, the interceptor adds this to the extensions of the requests it lets through
+#[derive(Debug, Clone)]
+pub struct SessionUser {
+    pub id: SessionId,
+    pub name: String,
+}
+
+///A logged in client, as it is shown in the server's window
+#[derive(Debug, Clone)]
+pub struct SessionInfo {
+    pub id: SessionId,
+    ///The account the client has logged in with
+    pub user: String,
+    ///Where the client has logged in from
+    pub peer: Option<SocketAddr>,
+    pub started: SystemTime,
+    ///When the current token expires, unless it is refreshed
+    pub expires: Instant,
+}
+
+///A nonce which has been handed out
+struct Challenge {
+    user: String,
+    ///Who has asked for it
+    address: Option<IpAddr>,
+    issued: Instant,
+}
+
+///Runs the server's side of the password handshake, and keeps track of the session tokens it has handed out
+pub struct Sessions {
+    ///The users and their password hashes
+    accounts: Arc<Accounts>,
+    ///The salts of the users which do not exist are derived from this, so they stay the same until the server restarts
+    decoy_key: [u8; 32],
+    ///The nonces which have not been answered yet, by the nonce
+    challenges: Mutex<HashMap<Vec<u8>, Challenge>>,
+    ///The sessions by the SHA-256 digests of their tokens, so looking them up does not leak the tokens through timing
+    tokens: Mutex<HashMap<[u8; 32], SessionInfo>>,
+    next_id: AtomicU64,
+}
+
+impl Sessions {
+    pub fn new(accounts: Arc<Accounts>) -> Self {
+        Self {
+            accounts,
+            decoy_key: random_bytes(),
+            challenges: Mutex::default(),
+            tokens: Mutex::default(),
+            next_id: AtomicU64::new(1),
+        }
+    }
+
+    ///Hands out a new nonce, returns it with the settings of the user's password hash
+    pub fn challenge(
+        &self,
+        user: &str,
+        peer: Option<SocketAddr>,
+    ) -> Result<(String, Vec<u8>), ServiceError> {
+        let settings = match self.accounts.read().user(user) {
+            Some(account) => hash_settings(&account.password_hash),
+            None => decoy_settings(&proof(&self.decoy_key, user.as_bytes())),
+        }
+        .map_err(ServiceError::internal)?;
+
+        let address = peer.map(|peer| peer.ip());
+        let mut challenges = self.challenges.lock().unwrap();
+
+        challenges.retain(|_, challenge| challenge.issued.elapsed() < CHALLENGE_TIMEOUT);
+
+        let same_address = challenges
+            .values()
+            .filter(|challenge| address.is_some() && challenge.address == address)
+            .count();
+
+        //Dropping the oldest challenge only fails a login which is taking unusually long
+        if same_address >= MAX_CHALLENGES_PER_ADDRESS || challenges.len() >= MAX_CHALLENGES {
+            let oldest = challenges
+                .iter()
+                .filter(|(_, challenge)| {
+                    same_address < MAX_CHALLENGES_PER_ADDRESS || challenge.address == address
+                })
+                .min_by_key(|(_, challenge)| challenge.issued)
+                .map(|(nonce, _)| nonce.clone());
+
+            if let Some(oldest) = oldest {
+                challenges.remove(&oldest);
+            }
+        }
+
+        let nonce = random_bytes().to_vec();
+
+        challenges.insert(
+            nonce.clone(),
+            Challenge {
+                user: user.to_string(),
+                address,
+                issued: Instant::now(),
+            },
+        );
+
+        Ok((settings, nonce))
+    }
+
+    ///Checks the answer to a challenge, returns a new session token if it is right
+    ///
+    ///The user the challenge has been for is returned either way, unless the challenge has expired
+    pub fn login(
+        &self,
+        nonce: &[u8],
+        proof: &[u8],
+        peer: Option<SocketAddr>,
+    ) -> (Option<String>, Result<String, ServiceError>) {
+        //A nonce can only be answered once, even if the answer is wrong
+        let challenge = self.challenges.lock().unwrap().remove(nonce);
+
+        let user = match challenge {
+            Some(challenge) if challenge.issued.elapsed() < CHALLENGE_TIMEOUT => challenge.user,
+            _ => {
+                return (
+                    None,
+                    Err(ServiceError::Unauthenticated(
+                        "The challenge has expired".to_string(),
+                    )),
+                )
+            }
+        };
+
+        //Users which do not exist fail the same way as wrong passwords
+        let key = self
+            .accounts
+            .read()
+            .user(&user)
+            .and_then(|account| stored_key(&account.password_hash).ok());
+
+        if !key.is_some_and(|key| verify_client_proof(&key, nonce, proof)) {
+            return (
+                Some(user),
+                Err(ServiceError::Unauthenticated(
+                    "Invalid user name or password".to_string(),
+                )),
+            );
+        }
+
+        let token = self.issue(SessionInfo {
+            id: SessionId(self.next_id.fetch_add(1, Ordering::Relaxed)),
+            user: user.clone(),
+            peer,
+            started: SystemTime::now(),
+            expires: Instant::now(),
+        });
+
+        (Some(user), Ok(token))
+    }
+
+    ///Checks if the token belongs to a session which has not expired or been revoked
+    pub fn check(&self, token: &str) -> Result<SessionUser, ServiceError> {
+        let mut tokens = self.tokens.lock().unwrap();
+
+        tokens.retain(|_, session| session.expires > Instant::now());
+
+        tokens
+            .get(&digest(token))
+            .map(|session| SessionUser {
+                id: session.id,
+                name: session.user.clone(),
+            })
+            .ok_or_else(|| {
+                ServiceError::Unauthenticated(
+                    "Not logged in, or the session has expired".to_string(),
+                )
+            })
+    }
+
+    ///Replaces the token with a new one which expires later, the old one stops working, returns it with the session's user
+    pub fn refresh(&self, token: &str) -> Result<(String, String), ServiceError> {
+        let session = self
+            .tokens
+            .lock()
+            .unwrap()
+            .remove(&digest(token))
+            .filter(|session| session.expires > Instant::now());
+
+        match session {
+            Some(session) => {
+                let user = session.user.clone();
+
+                Ok((self.issue(session), user))
+            }
+            None => Err(ServiceError::Unauthenticated(
+                "The session has expired or has been ended".to_string(),
+            )),
+        }
+    }
+
+    ///Ends the session of the token, returns its user if it has not ended already
+    pub fn logout(&self, token: &str) -> Option<String> {
+        self.tokens
+            .lock()
+            .unwrap()
+            .remove(&digest(token))
+            .map(|session| session.user)
+    }
+
+    ///Checks if the session has not expired or been revoked, long running requests use this to stop
+    pub fn is_active(&self, id: SessionId) -> bool {
+        self.tokens
+            .lock()
+            .unwrap()
+            .values()
+            .any(|session| session.id == id && session.expires > Instant::now())
+    }
+
+    ///The sessions which have not expired, the oldest first
+    pub fn list(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<SessionInfo> = self
+            .tokens
+            .lock()
+            .unwrap()
+            .values()
+            .filter(|session| session.expires > Instant::now())
+            .cloned()
+            .collect();
+
+        sessions.sort_by_key(|session| session.id.0);
+
+        sessions
+    }
+
+    ///Ends the session, its client has to log in again
+    pub fn revoke(&self, id: SessionId) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.id != id);
+    }
+
+    ///Ends every session of the user, after their password has changed or their account has been removed
+    pub fn revoke_user(&self, user: &str) {
+        self.tokens
+            .lock()
+            .unwrap()
+            .retain(|_, session| session.user != user);
+    }
+
+    pub fn revoke_all(&self) {
+        self.tokens.lock().unwrap().clear();
+    }
+
+    ///Hands out a new token for the session, which is valid for `SESSION_LIFETIME`
+    fn issue(&self, mut session: SessionInfo) -> String {
+        let token = to_hex(&random_bytes());
+
+        session.expires = Instant::now() + SESSION_LIFETIME;
+
+        self.tokens.lock().unwrap().insert(digest(&token), session);
+
+        token
+    }
+}
+
+fn digest(token: &str) -> [u8; 32] {
+    Sha256::digest(token.as_bytes()).into()
+}
+
+///The logins, every other service checks the tokens it hands out
+pub struct AuthService {
+    sessions: Arc<Sessions>,
+    ///Addresses which keep failing to log in are locked out
+    guard: Arc<Guard>,
+    audit: Arc<AuditLog>,
+}
+
+impl AuthService {
+    pub fn new(sessions: Arc<Sessions>, guard: Arc<Guard>, audit: Arc<AuditLog>) -> Self {
+        Self {
+            sessions,
+            guard,
+            audit,
+        }
+    }
+}
+
+#[async_trait]
+impl Auth for AuthService {
+    async fn challenge(
+        &self,
+        request: Request<ChallengeRequest>,
+    ) -> Result<Response<ChallengeReply>, Status> {
+        let peer = request.remote_addr();
+        let user = request.into_inner().user;
+
+        let result = self
+            .guard
+            .check_lockout(peer)
+            .and_then(|()| self.guard.check_challenge_rate(peer))
+            .and_then(|()| self.sessions.challenge(&user, peer));
+
+        //The user is recorded even if it does not exist, like for failed logins
+        self.audit.record(
+            AuditEntry::new(AuditAction::Challenge, peer)
+                .user(user)
+                .result(&result),
+        );
+
+        let (hash_settings, nonce) = result?;
+
+        Ok(Response::new(ChallengeReply {
+            hash_settings,
+            nonce,
+        }))
+    }
+
+    async fn login(&self, request: Request<LoginRequest>) -> Result<Response<LoginReply>, Status> {
+        let peer = request.remote_addr();
+        let request = request.into_inner();
+
+        if let Err(err) = self.guard.check_lockout(peer) {
+            self.audit
+                .record(AuditEntry::new(AuditAction::Login, peer).error(&err));
+
+            return Err(err.into());
+        }
+
+        let (user, result) = self.sessions.login(&request.nonce, &request.proof, peer);
+
+        let mut entry = AuditEntry::new(AuditAction::Login, peer).result(&result);
+
+        if let Some(user) = user {
+            entry = entry.user(user);
+        }
+
+        self.audit.record(entry);
+
+        match result {
+            Ok(token) => {
+                self.guard.record_success(peer);
+
+                Ok(Response::new(login_reply(token)))
+            }
+            Err(err) => {
+                self.guard.record_failure(peer);
+
+                Err(err.into())
+            }
+        }
+    }
+
+    async fn refresh(
+        &self,
+        request: Request<RefreshRequest>,
+    ) -> Result<Response<LoginReply>, Status> {
+        let peer = request.remote_addr();
+
+        let result = bearer_token(request.metadata())
+            .ok_or_else(|| {
+                ServiceError::Unauthenticated("The request has no session token".to_string())
+            })
+            .and_then(|token| self.sessions.refresh(token));
+
+        let mut entry = AuditEntry::new(AuditAction::Refresh, peer).result(&result);
+
+        if let Ok((_, user)) = &result {
+            entry = entry.user(user);
+        }
+
+        self.audit.record(entry);
+
+        let (token, _) = result?;
+
+        Ok(Response::new(login_reply(token)))
+    }
+
+    async fn logout(
+        &self,
+        request: Request<LogoutRequest>,
+    ) -> Result<Response<LogoutReply>, Status> {
+        let mut entry = AuditEntry::new(AuditAction::Logout, request.remote_addr());
+
+        if let Some(user) =
+            bearer_token(request.metadata()).and_then(|token| self.sessions.logout(token))
+        {
+            entry = entry.user(user);
+        }
+
+        self.audit.record(entry);
+
+        Ok(Response::new(LogoutReply {}))
+    }
+}
+
+fn login_reply(token: String) -> LoginReply {
+    LoginReply {
+        token,
+        expires_in: SESSION_LIFETIME.as_secs(),
+    }
+}
@@ -0,0 +1,522 @@
+use std::{
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use common_definitions::{
+    auth::{proof, random_bytes, verify_proof},
+    error::ServiceError,
+    to_hex,
+};
+
+///Identifies a share link, it is the first part of its token
+#[derive(
+    serde::Deserialize, serde::Serialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub struct ShareLinkId(pub u64);
+
+///Lets anyone with its token download a single file without logging in, until it expires or has been used up
+#[derive(serde::Deserialize, serde::Serialize, Debug, Clone)]
+pub struct ShareLink {
+    pub id: ShareLinkId,
+    ///Who the link has been made for, it is only shown to the server's admin
+    pub name: String,
+    ///The file the link is for, by the path clients request it with
+    pub path: PathBuf,
+    pub created: SystemTime,
+    pub expires: SystemTime,
+    ///How many times the file can be downloaded, `None` if it can be downloaded until the link expires
+    pub max_downloads: Option<u32>,
+    ///How many times the whole file has been sent
+    pub downloads: u32,
+    ///The bytes sent by downloads which have not sent the whole file, they use the link up once they add up to its size
+    ///
+    ///Otherwise the file could be fetched in pieces which are each dropped before they end
+    #[serde(default)]
+    pub partial_bytes: u64,
+    ///The downloads which are still running count against the limit, so a one-time link cannot be downloaded twice at once
+    #[serde(skip)]
+    running: u32,
+}
+
+impl ShareLink {
+    ///A link which has been made elsewhere, like the ones in the config of a headless server
+    pub fn new(
+        id: ShareLinkId,
+        name: String,
+        path: PathBuf,
+        expires: SystemTime,
+        max_downloads: Option<u32>,
+        downloads: u32,
+    ) -> Self {
+        Self {
+            id,
+            name,
+            path,
+            created: SystemTime::now(),
+            expires,
+            max_downloads,
+            downloads,
+            partial_bytes: 0,
+            running: 0,
+        }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires <= SystemTime::now()
+    }
+
+    ///Checks if the limit has been reached, the running downloads count as used until they have ended
+    pub fn is_used_up(&self) -> bool {
+        self.max_downloads
+            .is_some_and(|max| self.downloads + self.running >= max)
+    }
+}
+
+///The share links the running server accepts, they can be made and revoked while it is running
+///
+///The tokens are signed with the secret, so a link cannot be altered to point to another file or to expire later
+pub struct ShareLinks {
+    ///The key of the HMACs the tokens are signed with, in hex
+    secret: String,
+    links: RwLock<Vec<ShareLink>>,
+    ///The ID of the next link, IDs are never reused so the downloads of a revoked link are not counted for a newer one
+    next_id: AtomicU64,
+}
+
+///A new secret, the links signed with the old one stop working
+impl Default for ShareLinks {
+    fn default() -> Self {
+        Self::new(to_hex(&random_bytes()), Vec::new(), 1)
+    }
+}
+
+impl ShareLinks {
+    ///The next ID is saved along with the links, it is raised past the links in case it has been lost
+    pub fn new(secret: String, links: Vec<ShareLink>, next_id: u64) -> Self {
+        let next_id = links
+            .iter()
+            .map(|link| link.id.0 + 1)
+            .fold(next_id.max(1), u64::max);
+
+        Self {
+            secret,
+            links: RwLock::new(links),
+            next_id: AtomicU64::new(next_id),
+        }
+    }
+
+    ///The secret the tokens are signed with, it has to be saved along with the links
+    pub fn secret(&self) -> &str {
+        &self.secret
+    }
+
+    ///The ID the next link gets, it has to be saved along with the links
+    pub fn next_id(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    ///The links, with how often they have been downloaded
+    pub fn list(&self) -> Vec<ShareLink> {
+        self.links.read().unwrap().clone()
+    }
+
+    ///Makes a new link for the file, returns its token so it can be handed out
+    pub fn create(
+        &self,
+        name: String,
+        path: PathBuf,
+        expires: SystemTime,
+        max_downloads: Option<u32>,
+    ) -> String {
+        let mut links = self.links.write().unwrap();
+
+        let link = ShareLink {
+            id: ShareLinkId(self.next_id.fetch_add(1, Ordering::Relaxed)),
+            name,
+            path,
+            created: SystemTime::now(),
+            expires,
+            max_downloads,
+            downloads: 0,
+            partial_bytes: 0,
+            running: 0,
+        };
+
+        let token = self.sign(&link);
+
+        links.push(link);
+
+        token
+    }
+
+    ///The token of the link, so the links of a headless server can be handed out
+    pub fn token(&self, id: ShareLinkId) -> Option<String> {
+        self.get(id).map(|link| self.sign(&link))
+    }
+
+    fn sign(&self, link: &ShareLink) -> String {
+        format!(
+            "fhl_{}_{}",
+            link.id.0,
+            to_hex(&proof(self.secret.as_bytes(), &signed_message(link)))
+        )
+    }
+
+    ///The link stops working immediately, the downloads which are already running are finished
+    pub fn revoke(&self, id: ShareLinkId) {
+        self.links.write().unwrap().retain(|link| link.id != id);
+    }
+
+    ///Finds the link a request has been sent with, and checks that it can still be used
+    pub fn check(&self, token: &str) -> Result<ShareLinkId, ServiceError> {
+        let invalid = || ServiceError::Unauthenticated("Invalid share link".to_string());
+
+        let (id, signature) = token
+            .strip_prefix("fhl_")
+            .and_then(|token| token.split_once('_'))
+            .ok_or_else(invalid)?;
+
+        let id = ShareLinkId(id.parse().map_err(|_| invalid())?);
+        let signature = unhex(signature).ok_or_else(invalid)?;
+
+        let links = self.links.read().unwrap();
+
+        //Revoked links are not told apart from ones which have never existed
+        let link = links
+            .iter()
+            .find(|link| link.id == id)
+            .filter(|link| verify_proof(self.secret.as_bytes(), &signed_message(link), &signature))
+            .ok_or_else(invalid)?;
+
+        usable(link)?;
+
+        Ok(id)
+    }
+
+    ///The link, unless it has been revoked
+    pub fn get(&self, id: ShareLinkId) -> Option<ShareLink> {
+        self.links
+            .read()
+            .unwrap()
+            .iter()
+            .find(|link| link.id == id)
+            .cloned()
+    }
+
+    ///Counts a download against the limit of the link, until it has ended
+    pub fn start_download(&self, id: ShareLinkId) -> Result<(), ServiceError> {
+        let mut links = self.links.write().unwrap();
+
+        let link = links
+            .iter_mut()
+            .find(|link| link.id == id)
+            .ok_or_else(revoked)?;
+
+        usable(link)?;
+
+        link.running += 1;
+
+        Ok(())
+    }
+
+    ///Uses the link up by how much of the file has been sent, a download which has been dropped early only gives back what it has not sent
+    pub fn finish_download(&self, id: ShareLinkId, sent: u64, file_size: u64) {
+        if let Some(link) = self
+            .links
+            .write()
+            .unwrap()
+            .iter_mut()
+            .find(|link| link.id == id)
+        {
+            link.running = link.running.saturating_sub(1);
+
+            //An empty file has been sent as soon as its download has started
+            if file_size == 0 {
+                link.downloads = link.downloads.saturating_add(1);
+
+                return;
+            }
+
+            link.partial_bytes = link.partial_bytes.saturating_add(sent);
+
+            let whole = link.partial_bytes / file_size;
+
+            link.downloads = link
+                .downloads
+                .saturating_add(u32::try_from(whole).unwrap_or(u32::MAX));
+            link.partial_bytes %= file_size;
+        }
+    }
+}
+
+pub fn revoked() -> ServiceError {
+    ServiceError::Unauthenticated("The share link has been revoked".to_string())
+}
+
+fn usable(link: &ShareLink) -> Result<(), ServiceError> {
+    if link.is_expired() {
+        return Err(ServiceError::Unauthenticated(
+            "The share link has expired".to_string(),
+        ));
+    }
+
+    if link.is_used_up() {
+        return Err(ServiceError::Unauthenticated(
+            "The share link has been used up".to_string(),
+        ));
+    }
+
+    Ok(())
+}
+
+///What the token of a link signs, the file and the expiry cannot be changed without invalidating it
+fn signed_message(link: &ShareLink) -> Vec<u8> {
+    let expires = link
+        .expires
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos();
+
+    let mut message = format!("{}\n{}\n", link.id.0, expires).into_bytes();
+    message.extend_from_slice(link.path.as_os_str().as_encoded_bytes());
+
+    message
+}
+
+fn unhex(text: &str) -> Option<Vec<u8>> {
+    if !text.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(text.get(index..index + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+
+    const SECRET: &str = "0123456789abcdef0123456789abcdef";
+
+    fn in_an_hour() -> SystemTime {
+        SystemTime::now() + Duration::from_secs(3600)
+    }
+
+    fn link(path: &str, expires: SystemTime) -> ShareLink {
+        ShareLink::new(
+            ShareLinkId(1),
+            "friend".to_string(),
+            PathBuf::from(path),
+            expires,
+            None,
+            0,
+        )
+    }
+
+    fn is_rejected(result: Result<ShareLinkId, ServiceError>, message: &str) -> bool {
+        matches!(result, Err(ServiceError::Unauthenticated(text)) if text == message)
+    }
+
+    #[test]
+    fn accepts_a_valid_token() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+
+        let token = links.create(
+            "friend".to_string(),
+            PathBuf::from("/shared/file.txt"),
+            in_an_hour(),
+            Some(1),
+        );
+
+        assert_eq!(links.check(&token).unwrap(), ShareLinkId(1));
+        assert_eq!(links.token(ShareLinkId(1)).unwrap(), token);
+    }
+
+    #[test]
+    fn rejects_a_token_with_another_id() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+
+        let token = links.create(
+            "first".to_string(),
+            PathBuf::from("/shared/first.txt"),
+            in_an_hour(),
+            None,
+        );
+        links.create(
+            "second".to_string(),
+            PathBuf::from("/shared/second.txt"),
+            in_an_hour(),
+            None,
+        );
+
+        let tampered = token.replacen("fhl_1_", "fhl_2_", 1);
+
+        assert!(is_rejected(links.check(&tampered), "Invalid share link"));
+        assert!(is_rejected(links.check("fhl_x_00"), "Invalid share link"));
+        assert!(is_rejected(links.check("fhl_1_zz"), "Invalid share link"));
+        assert!(is_rejected(links.check("garbage"), "Invalid share link"));
+    }
+
+    #[test]
+    fn rejects_a_token_signed_for_another_path_or_expiry() {
+        let expires = in_an_hour();
+        let token = ShareLinks::new(
+            SECRET.to_string(),
+            vec![link("/shared/file.txt", expires)],
+            1,
+        )
+        .token(ShareLinkId(1))
+        .unwrap();
+
+        let moved = ShareLinks::new(
+            SECRET.to_string(),
+            vec![link("/shared/other.txt", expires)],
+            1,
+        );
+        assert!(is_rejected(moved.check(&token), "Invalid share link"));
+
+        let extended = ShareLinks::new(
+            SECRET.to_string(),
+            vec![link("/shared/file.txt", expires + Duration::from_secs(1))],
+            1,
+        );
+        assert!(is_rejected(extended.check(&token), "Invalid share link"));
+
+        let resigned = ShareLinks::new(
+            "another secret which is long enough".to_string(),
+            vec![link("/shared/file.txt", expires)],
+            1,
+        );
+        assert!(is_rejected(resigned.check(&token), "Invalid share link"));
+    }
+
+    #[test]
+    fn rejects_an_expired_link() {
+        let links = ShareLinks::new(
+            SECRET.to_string(),
+            vec![link(
+                "/shared/file.txt",
+                SystemTime::now() - Duration::from_secs(1),
+            )],
+            1,
+        );
+        let token = links.token(ShareLinkId(1)).unwrap();
+
+        assert!(is_rejected(
+            links.check(&token),
+            "The share link has expired"
+        ));
+    }
+
+    #[test]
+    fn rejects_a_revoked_link() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+        let token = links.create(
+            "friend".to_string(),
+            PathBuf::from("/shared/file.txt"),
+            in_an_hour(),
+            None,
+        );
+
+        links.revoke(ShareLinkId(1));
+
+        assert!(is_rejected(links.check(&token), "Invalid share link"));
+        assert!(links.start_download(ShareLinkId(1)).is_err());
+    }
+
+    #[test]
+    fn rejects_a_used_up_link_until_a_download_is_interrupted() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+        let token = links.create(
+            "friend".to_string(),
+            PathBuf::from("/shared/file.txt"),
+            in_an_hour(),
+            Some(1),
+        );
+
+        //A running download already counts against the limit
+        links.start_download(ShareLinkId(1)).unwrap();
+        assert!(is_rejected(
+            links.check(&token),
+            "The share link has been used up"
+        ));
+
+        links.finish_download(ShareLinkId(1), 40, 100);
+        assert!(links.check(&token).is_ok());
+
+        links.start_download(ShareLinkId(1)).unwrap();
+        links.finish_download(ShareLinkId(1), 100, 100);
+        assert!(is_rejected(
+            links.check(&token),
+            "The share link has been used up"
+        ));
+    }
+
+    #[test]
+    fn a_download_dropped_after_the_whole_file_uses_the_link_up() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+        let token = links.create(
+            "friend".to_string(),
+            PathBuf::from("/shared/file.txt"),
+            in_an_hour(),
+            Some(1),
+        );
+
+        //The client has closed the stream before the digest, but it has all of the data
+        links.start_download(ShareLinkId(1)).unwrap();
+        links.finish_download(ShareLinkId(1), 100, 100);
+
+        assert!(is_rejected(
+            links.check(&token),
+            "The share link has been used up"
+        ));
+    }
+
+    #[test]
+    fn pieces_of_dropped_downloads_add_up_to_a_download() {
+        let links = ShareLinks::new(SECRET.to_string(), Vec::new(), 1);
+        let token = links.create(
+            "friend".to_string(),
+            PathBuf::from("/shared/file.txt"),
+            in_an_hour(),
+            Some(2),
+        );
+
+        for _ in 0..3 {
+            links.start_download(ShareLinkId(1)).unwrap();
+            links.finish_download(ShareLinkId(1), 50, 100);
+        }
+
+        let link = links.get(ShareLinkId(1)).unwrap();
+        assert_eq!((link.downloads, link.partial_bytes), (1, 50));
+        assert!(links.check(&token).is_ok());
+
+        links.start_download(ShareLinkId(1)).unwrap();
+        links.finish_download(ShareLinkId(1), 50, 100);
+
+        assert!(is_rejected(
+            links.check(&token),
+            "The share link has been used up"
+        ));
+    }
+
+    #[test]
+    fn ids_are_not_reused_after_a_restart() {
+        let links = ShareLinks::new(
+            SECRET.to_string(),
+            vec![link("/shared/file.txt", in_an_hour())],
+            1,
+        );
+
+        assert_eq!(links.next_id(), 2);
+    }
+}
@@ -0,0 +1,126 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use common_definitions::{PathItem, TreeChange};
+use notify::{
+    event::{EventKind, ModifyKind, RenameMode},
+    RecommendedWatcher, RecursiveMode, Watcher,
+};
+
+///Changes are collected for this long before they are reported, so a file being written is not reported on every write
+const COALESCE_WINDOW: Duration = Duration::from_millis(250);
+
+///Watches the shared folders and reports every change inside of them
+pub struct ShareWatcher {
+    watcher: RecommendedWatcher,
+}
+
+impl ShareWatcher {
+    ///The callbacks are called from the watcher's own threads, errors are the ones which happen while watching like a full event queue
+    pub fn new(
+        on_change: impl Fn(TreeChange) + Send + 'static,
+        on_error: impl Fn(notify::Error) + Send + 'static,
+    ) -> notify::Result<Self> {
+        let (sx, rx) = mpsc::channel();
+
+        let watcher =
+            notify::recommended_watcher(move |event: notify::Result<notify::Event>| match event {
+                Ok(event) => {
+                    for change in changes(event) {
+                        let _ = sx.send(change);
+                    }
+                }
+                Err(err) => on_error(err),
+            })?;
+
+        //Stops when the watcher is dropped, as that drops the sender
+        std::thread::spawn(move || coalesce(rx, on_change));
+
+        Ok(Self { watcher })
+    }
+
+    pub fn watch(&mut self, root: &Path) -> notify::Result<()> {
+        self.watcher.watch(root, RecursiveMode::Recursive)
+    }
+
+    pub fn unwatch(&mut self, root: &Path) -> notify::Result<()> {
+        self.watcher.unwatch(root)
+    }
+}
+
+///Turns the event into changes of the tree, the disk tells us what the paths look like now
+fn changes(event: notify::Event) -> Vec<TreeChange> {
+    let mut paths = event.paths;
+
+    match event.kind {
+        //Reading a file does not change the tree
+        EventKind::Access(_) => Vec::new(),
+        EventKind::Remove(_) | EventKind::Modify(ModifyKind::Name(RenameMode::From)) => {
+            paths.into_iter().map(TreeChange::Removed).collect()
+        }
+        EventKind::Modify(ModifyKind::Name(RenameMode::Both)) if paths.len() == 2 => {
+            let to = paths.pop().unwrap();
+            let from = paths.pop().unwrap();
+
+            vec![match PathItem::from_disk(to) {
+                Some(to) => TreeChange::Renamed { from, to },
+                //It has already been moved again
+                None => TreeChange::Removed(from),
+            }]
+        }
+        EventKind::Create(_) | EventKind::Modify(ModifyKind::Name(_)) => paths
+            .into_iter()
+            .map(|path| read_change(path, TreeChange::Created))
+            .collect(),
+        _ => paths
+            .into_iter()
+            .map(|path| read_change(path, TreeChange::Modified))
+            .collect(),
+    }
+}
+
+fn read_change(path: PathBuf, change: fn(PathItem) -> TreeChange) -> TreeChange {
+    match PathItem::from_disk(path.clone()) {
+        Some(item) => change(item),
+        None => TreeChange::Removed(path),
+    }
+}
+
+///Collects the changes for a short while, and merges the modifications of the same path
+fn coalesce(rx: mpsc::Receiver<TreeChange>, on_change: impl Fn(TreeChange)) {
+    //Wait for the first change of the batch
+    while let Ok(first) = rx.recv() {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + COALESCE_WINDOW;
+
+        while let Ok(change) = rx.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            merge(&mut batch, change);
+        }
+
+        for change in batch {
+            on_change(change);
+        }
+    }
+}
+
+fn merge(batch: &mut Vec<TreeChange>, change: TreeChange) {
+    if let TreeChange::Modified(item) = &change {
+        let path = item.get_path();
+
+        //The last change of the path decides what it looks like
+        if let Some(TreeChange::Created(previous) | TreeChange::Modified(previous)) = batch
+            .iter_mut()
+            .rev()
+            .find(|previous| previous.path() == path)
+        {
+            *previous = item.clone();
+
+            return;
+        }
+    }
+
+    batch.push(change);
+}
@@ -0,0 +1,200 @@
+use std::{
+    path::PathBuf,
+    time::{Duration, SystemTime},
+};
+
+use egui::{Color32, RichText};
+
+use crate::ui::{
+    api_keys::{folder_name, format_time},
+    backend::share_links::ShareLinks,
+};
+
+///The link being made in the share links tab
+pub struct ShareLinksTab {
+    name: String,
+    path: Option<PathBuf>,
+    expires_in_hours: u64,
+    limit_downloads: bool,
+    max_downloads: u32,
+    ///The token of the last link, it cannot be shown again once this is cleared
+    created: Option<String>,
+    ///Why the link could not be made
+    error: Option<String>,
+}
+
+impl Default for ShareLinksTab {
+    fn default() -> Self {
+        Self {
+            name: String::new(),
+            path: None,
+            expires_in_hours: 72,
+            limit_downloads: true,
+            max_downloads: 1,
+            created: None,
+            error: None,
+        }
+    }
+}
+
+impl ShareLinksTab {
+    ///Makes and lists the links, they apply to the running server immediately
+    pub fn show(&mut self, ui: &mut egui::Ui, share_links: &ShareLinks, shares: &[PathBuf]) {
+        ui.heading("Share links");
+
+        ui.label("A share link lets anyone with its token download a single file without logging in, until it expires or has been used up.");
+
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("For");
+                ui.text_edit_singleline(&mut self.name)
+                    .on_hover_text("Who the link is handed to, it is only shown here");
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("File");
+
+                match &self.path {
+                    Some(path) => ui
+                        .label(folder_name(path))
+                        .on_hover_text(format!("Full path: {:?}", path)),
+                    None => ui.label("None"),
+                };
+
+                if ui.button("Select").clicked() {
+                    let mut dialog = rfd::FileDialog::new();
+
+                    if let Some(share) = shares.first() {
+                        dialog = dialog.set_directory(share);
+                    }
+
+                    if let Some(path) = dialog.pick_file() {
+                        self.path = Some(path);
+                    }
+                }
+            });
+
+            ui.horizontal(|ui| {
+                ui.label("Expires in");
+
+                ui.add(
+                    egui::DragValue::new(&mut self.expires_in_hours)
+                        .clamp_range(1..=24 * 365)
+                        .suffix(" hours"),
+                );
+            });
+
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut self.limit_downloads, "Limit downloads to");
+
+                ui.add_enabled(
+                    self.limit_downloads,
+                    egui::DragValue::new(&mut self.max_downloads).clamp_range(1..=1000),
+                )
+                .on_hover_text("A limit of 1 makes a one-time link");
+            });
+
+            if ui.button("Create link").clicked() {
+                self.create(share_links, shares);
+            }
+
+            if let Some(err) = &self.error {
+                ui.label(RichText::from(err).color(Color32::RED));
+            }
+
+            let mut hide = false;
+
+            if let Some(token) = &self.created {
+                ui.label("Copy the link now, it cannot be shown again. It is redeemed with the server's address, port and certificate fingerprint.");
+
+                ui.horizontal(|ui| {
+                    ui.label(RichText::from(token).monospace());
+
+                    if ui.button("Copy").clicked() {
+                        ui.output_mut(|output| output.copied_text = token.clone());
+                    }
+
+                    hide = ui.button("Hide").clicked();
+                });
+            }
+
+            if hide {
+                self.created = None;
+            }
+        });
+
+        ui.separator();
+
+        let links = share_links.list();
+
+        if links.is_empty() {
+            ui.label("No share links have been made");
+        }
+
+        egui::Grid::new("share_links").striped(true).show(ui, |ui| {
+            if !links.is_empty() {
+                for header in ["For", "File", "Created", "Expires", "Downloads"] {
+                    ui.label(RichText::from(header).strong());
+                }
+
+                ui.end_row();
+            }
+
+            for link in &links {
+                ui.label(&link.name);
+
+                ui.label(folder_name(&link.path))
+                    .on_hover_text(format!("Full path: {:?}", link.path));
+
+                ui.label(format_time(link.created));
+
+                if link.is_expired() {
+                    ui.label(RichText::from("Expired").color(Color32::RED));
+                } else {
+                    ui.label(format_time(link.expires));
+                }
+
+                match link.max_downloads {
+                    Some(_) if link.is_used_up() => ui.label(
+                        RichText::from(format!("{} (used up)", link.downloads)).color(Color32::RED),
+                    ),
+                    Some(max) => ui.label(format!("{} of {}", link.downloads, max)),
+                    None => ui.label(link.downloads.to_string()),
+                };
+
+                if ui.button("Revoke").clicked() {
+                    share_links.revoke(link.id);
+                }
+
+                ui.end_row();
+            }
+        });
+    }
+
+    fn create(&mut self, share_links: &ShareLinks, shares: &[PathBuf]) {
+        let error = match &self.path {
+            _ if self.name.trim().is_empty() => Some("Enter who the link is for"),
+            None => Some("Select the file to share"),
+            Some(path) if !shares.iter().any(|share| path.starts_with(share)) => {
+                Some("The file has to be inside of a shared folder")
+            }
+            Some(_) => None,
+        };
+
+        if let Some(error) = error {
+            self.error = Some(error.to_string());
+
+            return;
+        }
+
+        let expires = SystemTime::now() + Duration::from_secs(self.expires_in_hours * 60 * 60);
+
+        self.created = Some(share_links.create(
+            std::mem::take(&mut self.name).trim().to_string(),
+            self.path.take().unwrap_or_default(),
+            expires,
+            self.limit_downloads.then_some(self.max_downloads),
+        ));
+        self.error = None;
+    }
+}
@@ -0,0 +1,341 @@
+use std::{collections::HashMap, path::PathBuf};
+
+use common_definitions::auth::hash_password;
+use egui::{Color32, RichText};
+use tokio::sync::oneshot;
+
+use crate::ui::backend::accounts::{group_key, Access, AccountsConfig, UserAccount};
+
+///What has been edited in the users tab this frame
+pub enum UsersEdit {
+    ///Users have been added, or grants and groups have changed
+    Changed,
+    ///The user's password has changed or the user has been removed, their sessions have to end
+    Credentials(String),
+}
+
+///The text typed into the users tab, until it is applied
+#[derive(Default)]
+pub struct UsersTab {
+    new_name: String,
+    new_password: String,
+    new_groups: String,
+    ///The new passwords and groups typed for the existing users, by their names
+    passwords: HashMap<String, String>,
+    groups: HashMap<String, String>,
+    ///Why the last edit could not be applied
+    error: Option<String>,
+    ///The password which is being hashed, hashing takes long enough to freeze the window
+    hashing: Option<Hashing>,
+}
+
+///A password which is hashed on a blocking thread, it is applied once the hash has arrived
+struct Hashing {
+    target: HashTarget,
+    hash: oneshot::Receiver<Result<String, String>>,
+}
+
+///What the hash is for
+enum HashTarget {
+    NewUser { name: String, groups: Vec<String> },
+    Password(String),
+}
+
+impl UsersTab {
+    ///Shows the users and what they can access, returns what has been edited
+    pub fn show(
+        &mut self,
+        ui: &mut egui::Ui,
+        accounts: &mut AccountsConfig,
+        shares: &[PathBuf],
+    ) -> Option<UsersEdit> {
+        let mut edit = self.finish_hashing(accounts);
+        let idle = self.hashing.is_none();
+
+        ui.heading("Users");
+
+        if let Some(err) = &self.error {
+            ui.label(RichText::from(err).color(Color32::RED));
+        }
+
+        if !idle {
+            ui.horizontal(|ui| {
+                ui.spinner();
+                ui.label("Hashing the password");
+            });
+        }
+
+        ui.group(|ui| {
+            ui.horizontal(|ui| {
+                ui.label("Name");
+                ui.text_edit_singleline(&mut self.new_name);
+                ui.label("Password");
+                ui.add(egui::TextEdit::singleline(&mut self.new_password).password(true));
+                ui.label("Groups");
+                ui.text_edit_singleline(&mut self.new_groups)
+                    .on_hover_text("Separated by commas");
+
+                if ui
+                    .add_enabled(idle, egui::Button::new("Add user"))
+                    .clicked()
+                {
+                    self.add_user(ui.ctx(), accounts);
+                }
+            });
+        });
+
+        let mut removed = None;
+
+        let mut new_password = None;
+
+        for user in &mut accounts.users {
+            ui.horizontal(|ui| {
+                ui.label(RichText::from(&user.name).strong());
+
+                let password = self.passwords.entry(user.name.clone()).or_default();
+
+                ui.add(
+                    egui::TextEdit::singleline(password)
+                        .password(true)
+                        .hint_text("New password"),
+                );
+
+                if ui
+                    .add_enabled(idle, egui::Button::new("Set password"))
+                    .clicked()
+                {
+                    new_password = Some((user.name.clone(), std::mem::take(password)));
+                }
+
+                let groups = self
+                    .groups
+                    .entry(user.name.clone())
+                    .or_insert_with(|| user.groups.join(", "));
+
+                ui.add(egui::TextEdit::singleline(groups).hint_text("Groups"));
+
+                if ui.button("Set groups").clicked() {
+                    user.groups = parse_groups(groups);
+                    edit = Some(UsersEdit::Changed);
+                }
+
+                if ui.button("Remove").clicked() {
+                    removed = Some(user.name.clone());
+                }
+            });
+        }
+
+        if let Some((name, password)) = new_password {
+            self.start_hashing(ui.ctx(), HashTarget::Password(name), password);
+        }
+
+        if let Some(name) = removed {
+            accounts.users.retain(|user| user.name != name);
+
+            //A new user with the same name should not get the old one's access
+            for grants in accounts.access.values_mut() {
+                grants.remove(&name);
+            }
+
+            self.passwords.remove(&name);
+            self.groups.remove(&name);
+
+            edit = Some(UsersEdit::Credentials(name));
+        }
+
+        ui.separator();
+
+        ui.heading("Access");
+
+        if shares.is_empty() {
+            ui.label("Add a folder to the shared folders to grant access to it");
+        } else if show_access(ui, accounts, shares) {
+            edit = edit.or(Some(UsersEdit::Changed));
+        }
+
+        edit
+    }
+
+    fn add_user(&mut self, ctx: &egui::Context, accounts: &AccountsConfig) {
+        let name = self.new_name.trim().to_string();
+
+        if let Err(err) = accounts.check_name(&name) {
+            self.error = Some(err);
+
+            return;
+        }
+
+        let target = HashTarget::NewUser {
+            name,
+            groups: parse_groups(&self.new_groups),
+        };
+
+        let password = std::mem::take(&mut self.new_password);
+
+        self.start_hashing(ctx, target, password);
+    }
+
+    fn start_hashing(&mut self, ctx: &egui::Context, target: HashTarget, password: String) {
+        if password.is_empty() {
+            self.error = Some("The password cannot be empty".to_string());
+
+            return;
+        }
+
+        let (sx, hash) = oneshot::channel();
+        let ctx = ctx.clone();
+
+        tokio::task::spawn_blocking(move || {
+            let _ = sx.send(
+                hash_password(&password)
+                    .map_err(|err| format!("Failed to hash the password: {}", err)),
+            );
+
+            ctx.request_repaint();
+        });
+
+        self.error = None;
+        self.hashing = Some(Hashing { target, hash });
+    }
+
+    ///Applies the hash once it has arrived, the user might have been added or removed in the meantime
+    fn finish_hashing(&mut self, accounts: &mut AccountsConfig) -> Option<UsersEdit> {
+        let hashing = self.hashing.as_mut()?;
+
+        let hash = match hashing.hash.try_recv() {
+            Ok(hash) => hash,
+            Err(oneshot::error::TryRecvError::Empty) => return None,
+            Err(oneshot::error::TryRecvError::Closed) => {
+                Err("Failed to hash the password".to_string())
+            }
+        };
+
+        let target = self.hashing.take()?.target;
+
+        let password_hash = match hash {
+            Ok(hash) => hash,
+            Err(err) => {
+                self.error = Some(err);
+
+                return None;
+            }
+        };
+
+        match target {
+            HashTarget::NewUser { name, groups } => {
+                if let Err(err) = accounts.check_name(&name) {
+                    self.error = Some(err);
+
+                    return None;
+                }
+
+                accounts.users.push(UserAccount {
+                    name,
+                    password_hash,
+                    groups,
+                });
+
+                self.new_name.clear();
+                self.new_groups.clear();
+
+                Some(UsersEdit::Changed)
+            }
+            HashTarget::Password(name) => {
+                let user = accounts.users.iter_mut().find(|user| user.name == name)?;
+
+                user.password_hash = password_hash;
+
+                Some(UsersEdit::Credentials(name))
+            }
+        }
+    }
+}
+
+///A grid of what every user and group can do in each shared folder, returns true if a grant has changed
+fn show_access(ui: &mut egui::Ui, accounts: &mut AccountsConfig, shares: &[PathBuf]) -> bool {
+    let mut changed = false;
+
+    let grantees: Vec<String> = accounts
+        .users
+        .iter()
+        .map(|user| user.name.clone())
+        .chain(accounts.groups().iter().map(|group| group_key(group)))
+        .collect();
+
+    egui::Grid::new("access").striped(true).show(ui, |ui| {
+        ui.label("");
+
+        for share in shares {
+            ui.label(
+                share
+                    .file_name()
+                    .map(|name| name.to_string_lossy())
+                    .unwrap_or_default(),
+            )
+            .on_hover_text(format!("Full path: {:?}", share));
+        }
+
+        ui.end_row();
+
+        for grantee in &grantees {
+            ui.label(grantee);
+
+            for share in shares {
+                let is_group = grantee.starts_with('@');
+                let effective = accounts.access(grantee, share);
+
+                let grants = accounts.access.entry(share.clone()).or_default();
+                let own = grants.get(grantee).copied();
+                let mut selected = own;
+
+                let text = match own {
+                    Some(access) => access.to_string(),
+                    None if is_group => Access::None.to_string(),
+                    None => format!("From groups ({})", effective),
+                };
+
+                egui::ComboBox::from_id_source((grantee, share))
+                    .selected_text(text)
+                    .show_ui(ui, |ui| {
+                        //Users without a grant of their own get the highest one of their groups
+                        if !is_group {
+                            ui.selectable_value(&mut selected, None, "From groups");
+                        }
+
+                        for option in Access::ALL {
+                            ui.selectable_value(&mut selected, Some(option), option.to_string());
+                        }
+                    });
+
+                //Groups without a grant have no access anyway
+                if is_group && selected == Some(Access::None) {
+                    selected = None;
+                }
+
+                if selected != own {
+                    match selected {
+                        Some(access) => grants.insert(grantee.clone(), access),
+                        None => grants.remove(grantee),
+                    };
+
+                    changed = true;
+                }
+            }
+
+            ui.end_row();
+        }
+    });
+
+    ui.label("Users can also upload into the folders which allow uploads, if they can read and write them. A grant to a user overrides the ones to their groups.");
+
+    changed
+}
+
+fn parse_groups(groups: &str) -> Vec<String> {
+    groups
+        .split(',')
+        .map(str::trim)
+        .filter(|group| !group.is_empty())
+        .map(String::from)
+        .collect()
+}
@@ -0,0 +1,351 @@
+//! Resumable downloads and archive downloads, the file is written to a `.part` file next to the destination which is renamed once it is complete and its digest has been verified
+
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context;
+use tokio::{
+    fs::{File, OpenOptions},
+    io::AsyncWriteExt,
+};
+
+use crate::{
+    auth::SessionClient,
+    error::ServiceError,
+    hash_file,
+    messages::{ArchiveRequest, DescribeLinkRequest, HostRequest},
+    ArchiveFormat, ClientRequest, FileVersion, ServerReply,
+};
+
+///The downloaded file does not have the digest the server has sent, it has been deleted
+#[derive(Debug)]
+pub struct HashMismatch {
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for HashMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "The downloaded file is corrupt, expected SHA-256 {} but got {}",
+            self.expected, self.actual
+        )
+    }
+}
+
+impl std::error::Error for HashMismatch {}
+
+///How far a download has got
+#[derive(Debug, Clone, Copy)]
+pub struct DownloadProgress {
+    ///The amount of bytes written, including the ones downloaded before resuming
+    pub written: u64,
+    ///The size of the whole file
+    pub total: u64,
+}
+
+///The file the download is written to until it is complete
+pub fn part_path(destination: &Path) -> PathBuf {
+    with_suffix(destination, ".part")
+}
+
+///Stores the version of the remote file the `.part` file belongs to
+fn version_path(destination: &Path) -> PathBuf {
+    with_suffix(destination, ".part.version")
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = OsString::from(path.file_name().unwrap_or_default());
+    name.push(suffix);
+
+    path.with_file_name(name)
+}
+
+///Streams the remote file to the destination, returns the size of the file
+///
+///If a previous download of the same file was interrupted, it is resumed from where it stopped, unless the remote file has changed since
+pub async fn download_file(
+    client: &mut SessionClient,
+    remote: &Path,
+    destination: &Path,
+) -> anyhow::Result<u64> {
+    download_file_with_progress(client, remote, destination, |_| {}).await
+}
+
+///Same as `download_file`, but the progress is reported after every chunk
+pub async fn download_file_with_progress(
+    client: &mut SessionClient,
+    remote: &Path,
+    destination: &Path,
+    mut on_progress: impl FnMut(DownloadProgress),
+) -> anyhow::Result<u64> {
+    let part = part_path(destination);
+    let version_file = version_path(destination);
+
+    match resume_point(&part, &version_file).await {
+        Some((offset, version)) => {
+            match stream_to_part(
+                client,
+                remote,
+                destination,
+                offset,
+                Some(version),
+                &mut on_progress,
+            )
+            .await
+            {
+                //The remote file has changed, the downloaded part is useless
+                Err(err) if is_stale(&err) => {
+                    discard(&part, &version_file).await;
+                }
+                result => return result,
+            }
+        }
+        None => discard(&part, &version_file).await,
+    }
+
+    stream_to_part(client, remote, destination, 0, None, &mut on_progress).await
+}
+
+///Deletes what has been downloaded of the file so far, the download starts over the next time
+pub async fn discard_download(destination: &Path) {
+    discard(&part_path(destination), &version_path(destination)).await;
+}
+
+///Returns how much has already been downloaded and the version it belongs to
+async fn resume_point(part: &Path, version_file: &Path) -> Option<(u64, FileVersion)> {
+    let version = tokio::fs::read(version_file).await.ok()?;
+    let version: FileVersion = serde_json::from_slice(&version).ok()?;
+
+    let offset = tokio::fs::metadata(part).await.ok()?.len();
+
+    Some((offset, version))
+}
+
+async fn discard(part: &Path, version_file: &Path) {
+    let _ = tokio::fs::remove_file(part).await;
+    let _ = tokio::fs::remove_file(version_file).await;
+}
+
+///A file which has been downloaded with a share link
+#[derive(Debug)]
+pub struct RedeemedLink {
+    ///The path of the file on the server
+    pub remote: PathBuf,
+    ///Where it has been saved
+    pub destination: PathBuf,
+    pub size: u64,
+}
+
+///Downloads the file of the share link the client sends
+///
+///The file keeps its name if the destination is a folder, an interrupted download is resumed like any other
+pub async fn redeem_share_link(
+    client: &mut SessionClient,
+    destination: &Path,
+) -> anyhow::Result<RedeemedLink> {
+    let link = client
+        .describe_link(DescribeLinkRequest {})
+        .await
+        .map_err(ServiceError::from)?
+        .into_inner();
+
+    let remote = PathBuf::from(&link.path);
+
+    let destination = if destination.is_dir() {
+        destination.join(
+            remote
+                .file_name()
+                .context("The share link is not for a file")?,
+        )
+    } else {
+        destination.to_path_buf()
+    };
+
+    let size = download_file(client, &remote, &destination).await?;
+
+    Ok(RedeemedLink {
+        remote,
+        destination,
+        size,
+    })
+}
+
+///Asks the server for the current version and the digest of the remote file, without downloading it
+pub async fn remote_version(
+    client: &mut SessionClient,
+    remote: &Path,
+) -> anyhow::Result<(FileVersion, String)> {
+    let reply = client
+        .server_provide(HostRequest::new(ClientRequest::Stat {
+            path: remote.to_path_buf(),
+        }))
+        .await
+        .map_err(ServiceError::from)?
+        .into_inner();
+
+    let file = match reply.into_reply()? {
+        ServerReply::Stat(file) => file,
+        reply => anyhow::bail!("Unexpected reply: {:?}", reply),
+    };
+
+    let metadata = file
+        .metadata
+        .context("The server has not sent the metadata")?;
+
+    Ok((
+        FileVersion {
+            file_size: metadata.file_size,
+            file_modified: metadata.file_modified,
+        },
+        metadata
+            .sha256
+            .context("The server has not sent the digest of the file")?,
+    ))
+}
+
+///Checks if the server has refused to resume the download
+fn is_stale(err: &anyhow::Error) -> bool {
+    matches!(ServiceError::find(err), Some(ServiceError::FileChanged(_)))
+}
+
+async fn stream_to_part(
+    client: &mut SessionClient,
+    remote: &Path,
+    destination: &Path,
+    offset: u64,
+    expected_version: Option<FileVersion>,
+    on_progress: &mut impl FnMut(DownloadProgress),
+) -> anyhow::Result<u64> {
+    let part = part_path(destination);
+    let version_file = version_path(destination);
+
+    let mut stream = client
+        .stream_file(HostRequest::new(ClientRequest::FileRequest {
+            path: remote.to_path_buf(),
+            offset,
+            length: 0,
+            expected_version,
+        }))
+        .await
+        .map_err(ServiceError::from)?
+        .into_inner();
+
+    let mut file = if offset == 0 {
+        File::create(&part).await?
+    } else {
+        OpenOptions::new().append(true).open(&part).await?
+    };
+
+    let mut written = offset;
+    let mut total = expected_version.map_or(0, |version| version.file_size);
+    let mut expected_sha256 = None;
+
+    while let Some(chunk) = stream.message().await.map_err(ServiceError::from)? {
+        if !chunk.sha256.is_empty() {
+            expected_sha256 = Some(chunk.sha256);
+        }
+
+        if let Some(version) = chunk.version {
+            let version = FileVersion::try_from(version)?;
+
+            total = version.file_size;
+
+            //Remember the version before any data is written, so an interrupted download can be resumed
+            if offset == 0 {
+                tokio::fs::write(&version_file, serde_json::to_vec(&version)?).await?;
+            }
+        }
+
+        file.write_all(&chunk.data).await?;
+
+        written += chunk.data.len() as u64;
+
+        on_progress(DownloadProgress { written, total });
+    }
+
+    file.sync_all().await?;
+
+    //The server sends the digest after the last chunk, without it the stream has ended early.
+    //The part is kept, so resuming only has to fetch the digest
+    let expected = expected_sha256.with_context(|| {
+        format!(
+            "The download of {} has ended before the server has sent its digest, it has not been verified",
+            remote.display()
+        )
+    })?;
+
+    //The digest covers the whole file, including the part downloaded before resuming
+    let hashed_part = part.clone();
+    let actual = tokio::task::spawn_blocking(move || hash_file(&hashed_part)).await??;
+
+    if actual != expected {
+        discard(&part, &version_file).await;
+
+        return Err(HashMismatch { expected, actual }.into());
+    }
+
+    tokio::fs::rename(&part, destination).await?;
+
+    let _ = tokio::fs::remove_file(&version_file).await;
+
+    Ok(written)
+}
+
+///Streams an archive of the remote files and folders to the destination, returns the size of the archive
+///
+///Archives are generated on the fly, so an interrupted download starts over
+pub async fn download_archive(
+    client: &mut SessionClient,
+    remote: &[PathBuf],
+    format: ArchiveFormat,
+    destination: &Path,
+) -> anyhow::Result<u64> {
+    let part = part_path(destination);
+
+    //A leftover of a file download with the same name cannot be resumed as an archive
+    discard(&part, &version_path(destination)).await;
+
+    let written = match stream_archive_to_part(client, remote, format, &part).await {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = tokio::fs::remove_file(&part).await;
+
+            return Err(err);
+        }
+    };
+
+    tokio::fs::rename(&part, destination).await?;
+
+    Ok(written)
+}
+
+async fn stream_archive_to_part(
+    client: &mut SessionClient,
+    remote: &[PathBuf],
+    format: ArchiveFormat,
+    part: &Path,
+) -> anyhow::Result<u64> {
+    let mut stream = client
+        .stream_archive(ArchiveRequest::new(remote, format))
+        .await
+        .map_err(ServiceError::from)?
+        .into_inner();
+
+    let mut file = File::create(part).await?;
+    let mut written = 0;
+
+    while let Some(chunk) = stream.message().await.map_err(ServiceError::from)? {
+        file.write_all(&chunk.data).await?;
+
+        written += chunk.data.len() as u64;
+    }
+
+    file.sync_all().await?;
+
+    Ok(written)
+}
@@ -0,0 +1,183 @@
+//! The errors the server answers with, they are sent as gRPC statuses with their kind in the details
+
+use std::fmt::Display;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+use crate::messages::{ErrorKind, ServerError};
+
+///Why the server has refused or failed a request
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServiceError {
+    ///The password is wrong, or the session, API key or share link the request has been sent with is not valid
+    Unauthenticated(String),
+    ///The path does not exist, or it is not a file or folder like the request expects
+    NotFound(String),
+    ///The request is not allowed, like uploading into a folder which is not writable
+    PermissionDenied(String),
+    ///The path points outside of the shared folders
+    OutsideShare(String),
+    ///The file is larger than what the server accepts
+    TooLarge(String),
+    ///The client has sent too many requests, it can try again later
+    RateLimited(String),
+    ///The file has changed since the download started, so it cannot be resumed
+    FileChanged(String),
+    ///The uploaded file already exists, and the upload has not asked to overwrite it
+    AlreadyExists(String),
+    ///The request is malformed
+    InvalidRequest(String),
+    ///The server could not be reached, or the connection has broken
+    Unavailable(String),
+    ///The server has failed to do what it has been asked to
+    Internal(String),
+}
+
+impl ServiceError {
+    ///Takes the message of any error, which is what the `map_err`s on the server need
+    pub fn internal(err: impl ToString) -> Self {
+        ServiceError::Internal(err.to_string())
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            ServiceError::Unauthenticated(_) => ErrorKind::Unauthenticated,
+            ServiceError::NotFound(_) => ErrorKind::NotFound,
+            ServiceError::PermissionDenied(_) => ErrorKind::PermissionDenied,
+            ServiceError::OutsideShare(_) => ErrorKind::OutsideShare,
+            ServiceError::TooLarge(_) => ErrorKind::FileTooLarge,
+            ServiceError::RateLimited(_) => ErrorKind::RateLimited,
+            ServiceError::FileChanged(_) => ErrorKind::FileChanged,
+            ServiceError::AlreadyExists(_) => ErrorKind::AlreadyExists,
+            ServiceError::InvalidRequest(_) => ErrorKind::InvalidRequest,
+            ServiceError::Unavailable(_) => ErrorKind::Unavailable,
+            ServiceError::Internal(_) => ErrorKind::Internal,
+        }
+    }
+
+    ///The status code the error is sent with, clients which do not read the details still get the gist of it
+    pub fn code(&self) -> Code {
+        match self {
+            ServiceError::Unauthenticated(_) => Code::Unauthenticated,
+            ServiceError::NotFound(_) => Code::NotFound,
+            ServiceError::PermissionDenied(_) | ServiceError::OutsideShare(_) => {
+                Code::PermissionDenied
+            }
+            ServiceError::TooLarge(_) | ServiceError::RateLimited(_) => Code::ResourceExhausted,
+            ServiceError::FileChanged(_) => Code::FailedPrecondition,
+            ServiceError::AlreadyExists(_) => Code::AlreadyExists,
+            ServiceError::InvalidRequest(_) => Code::InvalidArgument,
+            ServiceError::Unavailable(_) => Code::Unavailable,
+            ServiceError::Internal(_) => Code::Internal,
+        }
+    }
+
+    ///The description the server has given
+    pub fn message(&self) -> &str {
+        match self {
+            ServiceError::Unauthenticated(message)
+            | ServiceError::NotFound(message)
+            | ServiceError::PermissionDenied(message)
+            | ServiceError::OutsideShare(message)
+            | ServiceError::TooLarge(message)
+            | ServiceError::RateLimited(message)
+            | ServiceError::FileChanged(message)
+            | ServiceError::AlreadyExists(message)
+            | ServiceError::InvalidRequest(message)
+            | ServiceError::Unavailable(message)
+            | ServiceError::Internal(message) => message,
+        }
+    }
+
+    fn from_kind(kind: ErrorKind, message: String) -> Self {
+        match kind {
+            ErrorKind::Unauthenticated => ServiceError::Unauthenticated(message),
+            ErrorKind::NotFound => ServiceError::NotFound(message),
+            ErrorKind::PermissionDenied => ServiceError::PermissionDenied(message),
+            ErrorKind::OutsideShare => ServiceError::OutsideShare(message),
+            ErrorKind::FileTooLarge => ServiceError::TooLarge(message),
+            ErrorKind::RateLimited => ServiceError::RateLimited(message),
+            ErrorKind::FileChanged => ServiceError::FileChanged(message),
+            ErrorKind::AlreadyExists => ServiceError::AlreadyExists(message),
+            ErrorKind::InvalidRequest => ServiceError::InvalidRequest(message),
+            ErrorKind::Unavailable => ServiceError::Unavailable(message),
+            ErrorKind::Internal | ErrorKind::Unspecified => ServiceError::Internal(message),
+        }
+    }
+
+    ///Finds the error in the chain, the libraries wrap what the server has answered with in `anyhow` errors
+    pub fn find(err: &anyhow::Error) -> Option<Self> {
+        err.chain().find_map(|cause| {
+            cause
+                .downcast_ref::<ServiceError>()
+                .cloned()
+                .or_else(|| cause.downcast_ref::<Status>().map(Self::from_status))
+        })
+    }
+
+    fn from_status(status: &Status) -> Self {
+        let message = status.message().to_string();
+
+        //Statuses without details come from the transport itself
+        match ServerError::decode(status.details()) {
+            Ok(details) if !status.details().is_empty() => Self::from_kind(details.kind(), message),
+            _ => match status.code() {
+                Code::Unauthenticated => ServiceError::Unauthenticated(message),
+                Code::NotFound => ServiceError::NotFound(message),
+                Code::PermissionDenied => ServiceError::PermissionDenied(message),
+                Code::ResourceExhausted => ServiceError::TooLarge(message),
+                Code::FailedPrecondition => ServiceError::FileChanged(message),
+                Code::AlreadyExists => ServiceError::AlreadyExists(message),
+                Code::InvalidArgument | Code::OutOfRange => ServiceError::InvalidRequest(message),
+                Code::Unavailable | Code::Cancelled | Code::DeadlineExceeded => {
+                    ServiceError::Unavailable(message)
+                }
+                _ => ServiceError::Internal(message),
+            },
+        }
+    }
+}
+
+impl Display for ServiceError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let category = match self {
+            ServiceError::Unauthenticated(_) => "Authentication failed",
+            ServiceError::NotFound(_) => "Not found",
+            ServiceError::PermissionDenied(_) => "Permission denied",
+            ServiceError::OutsideShare(_) => "Not inside of a shared folder",
+            ServiceError::TooLarge(_) => "Too large",
+            ServiceError::RateLimited(_) => "Too many requests, try again later",
+            ServiceError::FileChanged(_) => "The file has changed on the server",
+            ServiceError::AlreadyExists(_) => "Already exists",
+            ServiceError::InvalidRequest(_) => "Invalid request",
+            ServiceError::Unavailable(_) => "The server is unavailable",
+            ServiceError::Internal(_) => "Server error",
+        };
+
+        write!(f, "{}: {}", category, self.message())
+    }
+}
+
+impl std::error::Error for ServiceError {}
+
+impl From<ServiceError> for Status {
+    fn from(value: ServiceError) -> Self {
+        let details = ServerError {
+            kind: value.kind() as i32,
+            message: value.message().to_string(),
+        };
+
+        Status::with_details(
+            value.code(),
+            value.message(),
+            details.encode_to_vec().into(),
+        )
+    }
+}
+
+impl From<Status> for ServiceError {
+    fn from(value: Status) -> Self {
+        Self::from_status(&value)
+    }
+}
@@ -1,48 +1,23 @@
 use egui::vec2;
 use std::{
+    collections::HashSet,
     fmt::Debug,
     fs::{self},
-    path::PathBuf,
+    path::{Path, PathBuf},
     time::SystemTime,
 };
 
-///Master packet, when asking for the file
-#[derive(serde::Serialize, serde::Deserialize, Clone)]
-pub struct ServerFile {
-    pub bytes: Option<std::vec::Vec<u8>>,
-    pub path: PathBuf,
-    pub error: Option<String>,
-}
-
-impl Debug for ServerFile {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&format!("File: {:?}\nError: {:?}", self.bytes, self.error))
-    }
-}
-
-impl ServerFile {
-    pub fn new(path: PathBuf) -> Self {
-        match fs::read(path.clone()) {
-            Ok(bytes) => Self {
-                bytes: Some(bytes),
-                path,
-                error: None,
-            },
-            Err(err) => Self {
-                bytes: None,
-                path,
-                error: Some(err.to_string()),
-            },
-        }
-    }
-
-    pub fn serialize(&self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
-}
+pub mod auth;
+pub mod download;
+pub mod error;
+pub mod messages;
+pub mod mirror;
+pub mod sync;
+#[doc(hidden)]
+pub mod testing;
+pub mod tls;
 
 ///Master packet, when asking for the file tree
-#[derive(serde::Serialize, serde::Deserialize)]
 pub struct ServerList {
     pub list: Vec<PathItem>,
 }
@@ -57,23 +32,32 @@ impl ServerList {
     pub fn new(file_list: Vec<PathItem>) -> Self {
         Self { list: file_list }
     }
+}
 
-    pub fn serialize(&self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
+///A page of the immediate children of a folder
+#[derive(Debug)]
+pub struct DirectoryListing {
+    ///The folder which has been listed
+    pub path: PathBuf,
+    pub entries: Vec<PathItem>,
+    ///The index of the first entry in the folder
+    pub offset: u64,
+    ///The amount of entries in the whole folder
+    pub total: u64,
 }
 
+///The amount of entries clients ask for in a single ListDirectory request
+pub const DIRECTORY_PAGE_SIZE: u64 = 500;
+
 ///This is what the server replies with
-#[derive(serde::Serialize, serde::Deserialize, Debug)]
+#[derive(Debug)]
 pub enum ServerReply {
     List(ServerList),
-    File(ServerFile),
-}
-
-impl ServerReply {
-    pub fn serialize(&self) -> String {
-        serde_json::to_string(&self).unwrap()
-    }
+    Directory(DirectoryListing),
+    ///A file has been uploaded into a shared folder
+    Uploaded(FileStruct),
+    ///The metadata of the file a `Stat` request has asked for, with its digest
+    Stat(FileStruct),
 }
 
 ///Used for tree structure of the sent files
@@ -86,42 +70,160 @@ pub enum PathItem {
 ///This struct contains the data which is being sent to the client containing the path and the mtadata
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 pub struct FileStruct {
-    path: PathBuf,
-    metadata: Option<FileMetadata>,
+    pub path: PathBuf,
+    pub metadata: Option<FileMetadata>,
 }
 
 ///This is a newtype for fs::Metadata, which allows us to serialize and deserialize
 #[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
 pub struct FileMetadata {
-    file_size: u64,
-    file_modified: SystemTime,
-    file_accessed: SystemTime,
-    file_created: SystemTime,
+    pub file_size: u64,
+    pub file_modified: SystemTime,
+    ///The modification time on filesystems which do not record when files are read
+    pub file_accessed: SystemTime,
+    ///The modification time on filesystems which do not record when files are created, like tmpfs and many Linux setups
+    pub file_created: SystemTime,
+    ///The hex SHA-256 digest of the file, None if the server has not computed it yet
+    #[serde(default)]
+    pub sha256: Option<String>,
 }
 
 impl FileMetadata {
     pub fn from_fs_metadata(metadata: fs::Metadata) -> anyhow::Result<Self> {
+        let modified = metadata.modified()?;
+
         Ok(Self {
             file_size: metadata.len(),
-            file_modified: metadata.modified()?,
-            file_accessed: metadata.accessed()?,
-            file_created: metadata.created()?,
+            file_modified: modified,
+            file_accessed: metadata.accessed().unwrap_or(modified),
+            file_created: metadata.created().unwrap_or(modified),
+            sha256: None,
         })
     }
 }
 
+///Computes the hex SHA-256 digest of the file
+pub fn hash_file(path: &std::path::Path) -> std::io::Result<String> {
+    use sha2::{Digest, Sha256};
+
+    let mut hasher = Sha256::new();
+
+    std::io::copy(&mut fs::File::open(path)?, &mut hasher)?;
+
+    Ok(to_hex(&hasher.finalize()))
+}
+
+///Writes a file only its owner can read, like a private key, it fails if the file already exists
+pub fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    use std::io::Write;
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    options.open(path)?.write_all(contents)
+}
+
+///Formats a digest the way it is sent to clients
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
 ///This is what the server gets when the client is asking something (MASTER PACKET)
-#[derive(serde::Deserialize, serde::Serialize, Clone, Debug)]
+#[derive(Clone, Debug)]
 pub enum ClientRequest {
-    ///Client asked for a list
+    ///Client asked for the shared folders, only their immediate children are listed
     ListRequest,
-    ///Client asked for a file
-    FileRequest(PathBuf),
+    ///Client asked for the immediate children of a folder
+    ListDirectory {
+        path: PathBuf,
+        ///The index of the first entry to send
+        offset: u64,
+        ///The maximum amount of entries to send, 0 means no limit
+        limit: u64,
+    },
+    ///Client asked for a file or a range of it, the file itself is sent back in chunks by `StreamFile`
+    FileRequest {
+        path: PathBuf,
+        ///The index of the first byte to send
+        offset: u64,
+        ///The maximum amount of bytes to send, 0 means until the end of the file
+        length: u64,
+        ///If set, the request fails unless the file still has this version
+        expected_version: Option<FileVersion>,
+    },
+    ///Client asked for the metadata and the digest of a file, so it can tell if it has to download it
+    Stat { path: PathBuf },
 }
 
 impl ClientRequest {
-    pub fn serialize(&self) -> String {
-        serde_json::to_string(&self).unwrap()
+    ///Asks for the whole file
+    pub fn file(path: PathBuf) -> Self {
+        Self::FileRequest {
+            path,
+            offset: 0,
+            length: 0,
+            expected_version: None,
+        }
+    }
+}
+
+///Identifies a version of a file, a download can only be resumed if it has not changed
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct FileVersion {
+    pub file_size: u64,
+    pub file_modified: SystemTime,
+}
+
+impl FileVersion {
+    pub fn from_fs_metadata(metadata: &fs::Metadata) -> std::io::Result<Self> {
+        Ok(Self {
+            file_size: metadata.len(),
+            file_modified: metadata.modified()?,
+        })
+    }
+}
+
+///The formats files and folders can be downloaded in, when more than a single file is asked for
+#[derive(serde::Deserialize, serde::Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    #[default]
+    Tar,
+    ///A tar archive compressed with zstd
+    TarZstd,
+    Zip,
+}
+
+impl ArchiveFormat {
+    pub const ALL: [ArchiveFormat; 3] = [Self::Tar, Self::TarZstd, Self::Zip];
+
+    ///The extension of the archive's file name, without the leading dot
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Tar => "tar",
+            Self::TarZstd => "tar.zst",
+            Self::Zip => "zip",
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.extension())
+    }
+}
+
+impl std::str::FromStr for ArchiveFormat {
+    type Err = anyhow::Error;
+
+    ///Parses the extension of the archive
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::ALL
+            .into_iter()
+            .find(|format| format.extension() == s)
+            .ok_or_else(|| anyhow::anyhow!("Unknown archive format {}, use tar, tar.zst or zip", s))
     }
 }
 
@@ -143,33 +245,195 @@ pub struct FolderItem {
     pub opened: bool,
     ///The folder's entries
     pub entries: Vec<PathItem>,
+    ///Have the entries been listed yet, folders are only listed when they are opened
+    #[serde(default)]
+    pub loaded: bool,
+    ///Can clients upload files into the folder
+    #[serde(default)]
+    pub writable: bool,
 }
 
 impl FolderItem {
+    ///Creates a folder whose entries have not been listed yet
     pub fn new(path: PathBuf) -> Self {
         Self {
-            path: path.clone(),
+            path,
             opened: false,
-            entries: iter_folder(&path),
+            entries: Vec::new(),
+            loaded: false,
+            writable: false,
         }
     }
+
+    ///Lists the immediate children of the folder from the disk
+    pub fn load(&mut self) -> std::io::Result<()> {
+        self.entries = iter_folder(&self.path, |_| false)?;
+        self.loaded = true;
+
+        Ok(())
+    }
 }
 
-//It returns which file button it has been clicked on
-pub fn render_path(folder_list: &mut Vec<PathItem>, ui: &mut egui::Ui) -> Option<PathBuf> {
+///Finds a folder anywhere in the tree
+pub fn find_folder_mut<'a>(
+    folder_list: &'a mut [PathItem],
+    path: &std::path::Path,
+) -> Option<&'a mut FolderItem> {
+    for entry in folder_list {
+        if let PathItem::Folder(folder) = entry {
+            if folder.path == path {
+                return Some(folder);
+            }
+
+            //Only descend into the folder which contains the path
+            if path.starts_with(&folder.path) {
+                return find_folder_mut(&mut folder.entries, path);
+            }
+        }
+    }
+
+    None
+}
+
+///A change of the filesystem inside of a shared folder
+#[derive(Debug, Clone)]
+pub enum TreeChange {
+    Created(PathItem),
+    ///A file's content or metadata has changed
+    Modified(PathItem),
+    ///A file or folder has been deleted or moved out of the shared folders
+    Removed(PathBuf),
+    Renamed {
+        from: PathBuf,
+        to: PathItem,
+    },
+}
+
+impl TreeChange {
+    ///The path the change is about, for renames the old path
+    pub fn path(&self) -> PathBuf {
+        match self {
+            TreeChange::Created(item) | TreeChange::Modified(item) => item.get_path(),
+            TreeChange::Removed(path) | TreeChange::Renamed { from: path, .. } => path.clone(),
+        }
+    }
+}
+
+impl PathItem {
+    ///Reads the item from the disk, None if it does not exist anymore or is a symlink
+    ///
+    ///Symlinks are left out like in `iter_folder`, a change does not know who can see the link's target
+    pub fn from_disk(path: PathBuf) -> Option<Self> {
+        let file_type = fs::symlink_metadata(&path).ok()?.file_type();
+
+        if file_type.is_file() {
+            let metadata = fs::metadata(&path)
+                .ok()
+                .and_then(|metadata| FileMetadata::from_fs_metadata(metadata).ok());
+
+            Some(Self::File(FileStruct { path, metadata }))
+        } else if file_type.is_dir() {
+            Some(Self::Folder(FolderItem::new(path)))
+        } else {
+            None
+        }
+    }
+}
+
+///Applies the change to the tree, folders which have not been listed yet are left alone as they are read from the disk when they are opened
+pub fn apply_change(folder_list: &mut [PathItem], change: TreeChange) {
+    match change {
+        TreeChange::Created(item) | TreeChange::Modified(item) => insert_item(folder_list, item),
+        TreeChange::Removed(path) => remove_item(folder_list, &path),
+        TreeChange::Renamed { from, to } => {
+            remove_item(folder_list, &from);
+            insert_item(folder_list, to);
+        }
+    }
+}
+
+///Returns the folder the path is in, if its entries have been listed
+fn loaded_parent<'a>(
+    folder_list: &'a mut [PathItem],
+    path: &std::path::Path,
+) -> Option<&'a mut FolderItem> {
+    find_folder_mut(folder_list, path.parent()?).filter(|folder| folder.loaded)
+}
+
+fn insert_item(folder_list: &mut [PathItem], item: PathItem) {
+    let path = item.get_path();
+
+    let Some(folder) = loaded_parent(folder_list, &path) else {
+        return;
+    };
+
+    match folder
+        .entries
+        .iter_mut()
+        .find(|entry| entry.get_path() == path)
+    {
+        //A folder which already exists keeps its entries and whether it is opened
+        Some(PathItem::Folder(_)) if matches!(item, PathItem::Folder(_)) => {}
+        Some(existing) => *existing = item,
+        None => {
+            folder.entries.push(item);
+
+            sort_entries(&mut folder.entries);
+        }
+    }
+}
+
+fn remove_item(folder_list: &mut [PathItem], path: &std::path::Path) {
+    if let Some(folder) = loaded_parent(folder_list, path) {
+        folder.entries.retain(|entry| entry.get_path() != path);
+    }
+}
+
+///What the user has done in the file tree
+#[derive(Debug, Clone)]
+pub enum PathAction {
+    ///A file has been clicked on
+    FileClicked(PathBuf),
+    ///A folder whose entries have not been listed yet has been opened
+    FolderOpened(PathBuf),
+    ///The upload button of a writable folder has been clicked
+    UploadClicked(PathBuf),
+    ///Files have been dropped onto a writable folder
+    FilesDropped {
+        folder: PathBuf,
+        files: Vec<PathBuf>,
+    },
+    ///The download button of a folder has been clicked
+    DownloadFolder(PathBuf),
+    ///The mirror button of a folder has been clicked
+    MirrorFolder(PathBuf),
+}
+
+//It returns which file button it has been clicked on, or which folder needs to be listed, the highlighted items are drawn in a different color
+//If there is a selection, every item gets a checkbox and folders can be downloaded
+pub fn render_path(
+    folder_list: &mut Vec<PathItem>,
+    highlighted: &HashSet<PathBuf>,
+    mut selection: Option<&mut HashSet<PathBuf>>,
+    ui: &mut egui::Ui,
+) -> Option<PathAction> {
     //check if folder is empty
     if folder_list.is_empty() {
         ui.label("Empty");
         return None;
     }
 
-    let mut clicked_button: Option<PathBuf> = None;
+    let mut clicked_button: Option<PathAction> = None;
 
     //Iter over entries of the directory
     for entry in folder_list {
         match entry {
             PathItem::Folder(folder) => {
                 ui.horizontal(|ui| {
+                    if let Some(selection) = selection.as_deref_mut() {
+                        select_checkbox(selection, &folder.path, ui);
+                    }
+
                     //dir button
                     ui.allocate_ui(vec2(30., 30.), |ui| {
                         if ui
@@ -179,29 +443,69 @@ pub fn render_path(folder_list: &mut Vec<PathItem>, ui: &mut egui::Ui) -> Option
                             .clicked()
                         {
                             folder.opened = !folder.opened;
+
+                            if folder.opened && !folder.loaded {
+                                clicked_button =
+                                    Some(PathAction::FolderOpened(folder.path.clone()));
+                            }
                         }
                     });
 
                     //Display name
-                    ui.label(format!(
-                        "{}",
-                        folder
-                            .path
-                            .file_stem()
-                            .unwrap()
-                            .to_string_lossy()
+                    ui.label(highlight(
+                        folder.path.file_stem().unwrap().to_string_lossy(),
+                        highlighted.contains(&folder.path),
                     ));
+
+                    if folder.writable && ui.small_button("Upload").clicked() {
+                        clicked_button = Some(PathAction::UploadClicked(folder.path.clone()));
+                    }
+
+                    if selection.is_some() {
+                        if ui.small_button("Download folder").clicked() {
+                            clicked_button = Some(PathAction::DownloadFolder(folder.path.clone()));
+                        }
+
+                        if ui.small_button("Mirror").clicked() {
+                            clicked_button = Some(PathAction::MirrorFolder(folder.path.clone()));
+                        }
+                    }
                 });
 
                 if folder.opened {
                     //Indent
-                    ui.group(|ui| {
-                        clicked_button = render_path(&mut folder.entries, ui);
+                    let group = ui.group(|ui| {
+                        if !folder.loaded {
+                            ui.label("Loading...");
+                        } else if let Some(action) = render_path(
+                            &mut folder.entries,
+                            highlighted,
+                            selection.as_deref_mut(),
+                            ui,
+                        ) {
+                            clicked_button = Some(action);
+                        }
                     });
+
+                    //A drop onto a subfolder has already been handled by it
+                    if folder.writable
+                        && !matches!(clicked_button, Some(PathAction::FilesDropped { .. }))
+                    {
+                        if let Some(files) = dropped_files(ui, group.response.rect) {
+                            clicked_button = Some(PathAction::FilesDropped {
+                                folder: folder.path.clone(),
+                                files,
+                            });
+                        }
+                    }
                 }
             }
             PathItem::File(file) => {
                 ui.horizontal(|ui| {
+                    if let Some(selection) = selection.as_deref_mut() {
+                        select_checkbox(selection, &file.path, ui);
+                    }
+
                     //file button
                     ui.allocate_ui(vec2(30., 30.), |ui| {
                         if ui
@@ -210,15 +514,23 @@ pub fn render_path(folder_list: &mut Vec<PathItem>, ui: &mut egui::Ui) -> Option
                             )))
                             .clicked()
                         {
-                            clicked_button = Some(file.clone().path);
+                            clicked_button = Some(PathAction::FileClicked(file.clone().path));
                         }
                     });
 
-                    ui.label(format!(
-                        "{}",
-                        file.path.file_name().unwrap().to_string_lossy()
+                    let name = ui.label(highlight(
+                        file.path.file_name().unwrap().to_string_lossy(),
+                        highlighted.contains(&file.path),
                     ));
 
+                    if let Some(sha256) = file
+                        .metadata
+                        .as_ref()
+                        .and_then(|metadata| metadata.sha256.as_ref())
+                    {
+                        name.on_hover_text(format!("SHA-256: {}", sha256));
+                    }
+
                     //Separator
                     ui.separator();
 
@@ -243,13 +555,71 @@ pub fn render_path(folder_list: &mut Vec<PathItem>, ui: &mut egui::Ui) -> Option
     clicked_button
 }
 
-pub fn iter_folder(group: &PathBuf) -> Vec<PathItem> {
+///Adds or removes the path from the selection when its checkbox is clicked
+fn select_checkbox(selection: &mut HashSet<PathBuf>, path: &PathBuf, ui: &mut egui::Ui) {
+    let mut selected = selection.contains(path);
+
+    if ui.checkbox(&mut selected, "").changed() {
+        if selected {
+            selection.insert(path.clone());
+        } else {
+            selection.remove(path);
+        }
+    }
+}
+
+fn highlight(name: impl Into<String>, highlighted: bool) -> egui::RichText {
+    let text = egui::RichText::new(name);
+
+    if highlighted {
+        text.color(egui::Color32::LIGHT_GREEN).strong()
+    } else {
+        text
+    }
+}
+
+///Returns the files which have been dropped this frame, if the pointer is inside of the rect
+pub fn dropped_files(ui: &egui::Ui, rect: egui::Rect) -> Option<Vec<PathBuf>> {
+    ui.ctx().input(|input| {
+        let files: Vec<PathBuf> = input
+            .raw
+            .dropped_files
+            .iter()
+            .filter_map(|file| file.path.clone())
+            .collect();
+
+        let inside = input
+            .pointer
+            .latest_pos()
+            .is_some_and(|pos| rect.contains(pos));
+
+        (!files.is_empty() && inside).then_some(files)
+    })
+}
+
+///Lists the immediate children of the folder, folders come first and entries are sorted by name
+///
+///Symlinks are only listed if they point to a file which `follow_link` allows, it is given the canonical target
+pub fn iter_folder(
+    group: &PathBuf,
+    follow_link: impl Fn(&Path) -> bool,
+) -> std::io::Result<Vec<PathItem>> {
     let mut paths: Vec<PathItem> = Vec::new();
-    for dir_entry in fs::read_dir(group).unwrap() {
-        let dir_entry = dir_entry.unwrap();
+    for dir_entry in fs::read_dir(group)? {
+        let dir_entry = dir_entry?;
         let path = dir_entry.path();
+        //Unlike the checks of the path, the type of the entry itself does not follow symlinks
+        let file_type = dir_entry.file_type()?;
+
+        //Links to folders are never followed, they could point outside of the shared folders or form a cycle
+        let is_file = if file_type.is_symlink() {
+            path.canonicalize()
+                .is_ok_and(|target| target.is_file() && follow_link(&target))
+        } else {
+            file_type.is_file()
+        };
 
-        if path.is_file() {
+        if is_file {
             paths.push(PathItem::File(FileStruct {
                 path: path.clone(),
                 metadata: {
@@ -259,9 +629,22 @@ pub fn iter_folder(group: &PathBuf) -> Vec<PathItem> {
                     }
                 },
             }));
-        } else if path.is_dir() {
+        } else if file_type.is_dir() {
             paths.push(PathItem::Folder(FolderItem::new(path)));
         }
     }
-    paths
+
+    //The order has to be stable for paging through the entries
+    sort_entries(&mut paths);
+
+    Ok(paths)
+}
+
+///Folders come first, then the entries are sorted by name
+pub fn sort_entries(entries: &mut [PathItem]) {
+    entries.sort_by(|a, b| {
+        matches!(b, PathItem::Folder(_))
+            .cmp(&matches!(a, PathItem::Folder(_)))
+            .then_with(|| a.get_path().cmp(&b.get_path()))
+    });
 }
@@ -0,0 +1,326 @@
+//! The protobuf messages sent between the server and the client, and the conversions between them and the types used by the apps
+
+use std::{path::PathBuf, time::SystemTime};
+
+use anyhow::Context;
+
+tonic::include_proto!("file_hosting");
+
+impl HostRequest {
+    pub fn new(request: crate::ClientRequest) -> Self {
+        Self {
+            request: Some(request.into()),
+        }
+    }
+}
+
+impl HostReply {
+    pub fn reply(reply: crate::ServerReply) -> Self {
+        Self {
+            reply: Some(reply.into()),
+        }
+    }
+
+    ///The typed reply, failures are not sent in it but as statuses
+    pub fn into_reply(self) -> anyhow::Result<crate::ServerReply> {
+        crate::ServerReply::try_from(self.reply.context("Empty reply")?)
+    }
+}
+
+impl From<crate::ClientRequest> for ClientRequest {
+    fn from(value: crate::ClientRequest) -> Self {
+        let request = match value {
+            crate::ClientRequest::ListRequest => {
+                client_request::Request::ListRequest(ListRequest {})
+            }
+            crate::ClientRequest::FileRequest {
+                path,
+                offset,
+                length,
+                expected_version,
+            } => client_request::Request::FileRequest(FileRequest {
+                path: path_to_string(&path),
+                offset,
+                length,
+                expected_version: expected_version.map(FileVersion::from),
+            }),
+            crate::ClientRequest::ListDirectory {
+                path,
+                offset,
+                limit,
+            } => client_request::Request::ListDirectory(ListDirectoryRequest {
+                path: path_to_string(&path),
+                offset,
+                limit,
+            }),
+            crate::ClientRequest::Stat { path } => client_request::Request::Stat(StatRequest {
+                path: path_to_string(&path),
+            }),
+        };
+
+        Self {
+            request: Some(request),
+        }
+    }
+}
+
+impl TryFrom<ClientRequest> for crate::ClientRequest {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ClientRequest) -> Result<Self, Self::Error> {
+        Ok(match value.request.context("Empty request")? {
+            client_request::Request::ListRequest(_) => Self::ListRequest,
+            client_request::Request::FileRequest(file) => Self::FileRequest {
+                path: PathBuf::from(file.path),
+                offset: file.offset,
+                length: file.length,
+                expected_version: file
+                    .expected_version
+                    .map(crate::FileVersion::try_from)
+                    .transpose()?,
+            },
+            client_request::Request::ListDirectory(list) => Self::ListDirectory {
+                path: PathBuf::from(list.path),
+                offset: list.offset,
+                limit: list.limit,
+            },
+            client_request::Request::Stat(stat) => Self::Stat {
+                path: PathBuf::from(stat.path),
+            },
+        })
+    }
+}
+
+impl From<crate::ServerReply> for ServerReply {
+    fn from(value: crate::ServerReply) -> Self {
+        let reply = match value {
+            crate::ServerReply::List(list) => server_reply::Reply::List(ServerList {
+                list: list.list.into_iter().map(PathItem::from).collect(),
+            }),
+            crate::ServerReply::Directory(directory) => {
+                server_reply::Reply::Directory(DirectoryListing {
+                    path: path_to_string(&directory.path),
+                    entries: directory.entries.into_iter().map(PathItem::from).collect(),
+                    offset: directory.offset,
+                    total: directory.total,
+                })
+            }
+            crate::ServerReply::Uploaded(file) => server_reply::Reply::Uploaded(file.into()),
+            crate::ServerReply::Stat(file) => server_reply::Reply::Stat(file.into()),
+        };
+
+        Self { reply: Some(reply) }
+    }
+}
+
+impl TryFrom<ServerReply> for crate::ServerReply {
+    type Error = anyhow::Error;
+
+    fn try_from(value: ServerReply) -> Result<Self, Self::Error> {
+        Ok(match value.reply.context("Empty reply")? {
+            server_reply::Reply::List(list) => Self::List(crate::ServerList::new(
+                list.list
+                    .into_iter()
+                    .map(crate::PathItem::try_from)
+                    .collect::<anyhow::Result<_>>()?,
+            )),
+            server_reply::Reply::Directory(directory) => Self::Directory(crate::DirectoryListing {
+                path: PathBuf::from(directory.path),
+                entries: directory
+                    .entries
+                    .into_iter()
+                    .map(crate::PathItem::try_from)
+                    .collect::<anyhow::Result<_>>()?,
+                offset: directory.offset,
+                total: directory.total,
+            }),
+            server_reply::Reply::Uploaded(file) => Self::Uploaded(file.try_into()?),
+            server_reply::Reply::Stat(file) => Self::Stat(file.try_into()?),
+        })
+    }
+}
+
+impl From<crate::PathItem> for PathItem {
+    fn from(value: crate::PathItem) -> Self {
+        let item = match value {
+            crate::PathItem::Folder(folder) => path_item::Item::Folder(FolderItem {
+                path: path_to_string(&folder.path),
+                entries: folder.entries.into_iter().map(PathItem::from).collect(),
+                loaded: folder.loaded,
+                writable: folder.writable,
+            }),
+            crate::PathItem::File(file) => path_item::Item::File(file.into()),
+        };
+
+        Self { item: Some(item) }
+    }
+}
+
+impl TryFrom<PathItem> for crate::PathItem {
+    type Error = anyhow::Error;
+
+    fn try_from(value: PathItem) -> Result<Self, Self::Error> {
+        Ok(match value.item.context("Empty path item")? {
+            path_item::Item::Folder(folder) => Self::Folder(crate::FolderItem {
+                path: PathBuf::from(folder.path),
+                opened: false,
+                entries: folder
+                    .entries
+                    .into_iter()
+                    .map(crate::PathItem::try_from)
+                    .collect::<anyhow::Result<_>>()?,
+                loaded: folder.loaded,
+                writable: folder.writable,
+            }),
+            path_item::Item::File(file) => Self::File(file.try_into()?),
+        })
+    }
+}
+
+impl From<crate::FileStruct> for FileStruct {
+    fn from(value: crate::FileStruct) -> Self {
+        Self {
+            path: path_to_string(&value.path),
+            metadata: value.metadata.map(FileMetadata::from),
+        }
+    }
+}
+
+impl TryFrom<FileStruct> for crate::FileStruct {
+    type Error = anyhow::Error;
+
+    fn try_from(value: FileStruct) -> Result<Self, Self::Error> {
+        Ok(Self {
+            path: PathBuf::from(value.path),
+            metadata: value
+                .metadata
+                .map(crate::FileMetadata::try_from)
+                .transpose()?,
+        })
+    }
+}
+
+impl From<crate::FileMetadata> for FileMetadata {
+    fn from(value: crate::FileMetadata) -> Self {
+        Self {
+            file_size: value.file_size,
+            file_modified: Some(value.file_modified.into()),
+            file_accessed: Some(value.file_accessed.into()),
+            file_created: Some(value.file_created.into()),
+            sha256: value.sha256.unwrap_or_default(),
+        }
+    }
+}
+
+impl TryFrom<FileMetadata> for crate::FileMetadata {
+    type Error = anyhow::Error;
+
+    fn try_from(value: FileMetadata) -> Result<Self, Self::Error> {
+        Ok(Self {
+            file_size: value.file_size,
+            file_modified: to_system_time(value.file_modified)?,
+            file_accessed: to_system_time(value.file_accessed)?,
+            file_created: to_system_time(value.file_created)?,
+            sha256: (!value.sha256.is_empty()).then_some(value.sha256),
+        })
+    }
+}
+
+impl From<crate::FileVersion> for FileVersion {
+    fn from(value: crate::FileVersion) -> Self {
+        Self {
+            file_size: value.file_size,
+            file_modified: Some(value.file_modified.into()),
+        }
+    }
+}
+
+impl TryFrom<FileVersion> for crate::FileVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: FileVersion) -> Result<Self, Self::Error> {
+        Ok(Self {
+            file_size: value.file_size,
+            file_modified: to_system_time(value.file_modified)?,
+        })
+    }
+}
+
+impl ArchiveRequest {
+    pub fn new(paths: &[PathBuf], format: crate::ArchiveFormat) -> Self {
+        Self {
+            paths: paths.iter().map(|path| path_to_string(path)).collect(),
+            format: ArchiveFormat::from(format) as i32,
+        }
+    }
+}
+
+impl From<crate::ArchiveFormat> for ArchiveFormat {
+    fn from(value: crate::ArchiveFormat) -> Self {
+        match value {
+            crate::ArchiveFormat::Tar => Self::Tar,
+            crate::ArchiveFormat::TarZstd => Self::TarZstd,
+            crate::ArchiveFormat::Zip => Self::Zip,
+        }
+    }
+}
+
+impl From<ArchiveFormat> for crate::ArchiveFormat {
+    fn from(value: ArchiveFormat) -> Self {
+        match value {
+            ArchiveFormat::Tar => Self::Tar,
+            ArchiveFormat::TarZstd => Self::TarZstd,
+            ArchiveFormat::Zip => Self::Zip,
+        }
+    }
+}
+
+impl From<crate::TreeChange> for TreeEvent {
+    fn from(value: crate::TreeChange) -> Self {
+        let path = path_to_string(&value.path());
+
+        let (kind, item) = match value {
+            crate::TreeChange::Created(item) => (TreeEventKind::Created, Some(item)),
+            crate::TreeChange::Modified(item) => (TreeEventKind::Modified, Some(item)),
+            crate::TreeChange::Removed(_) => (TreeEventKind::Deleted, None),
+            crate::TreeChange::Renamed { to, .. } => (TreeEventKind::Renamed, Some(to)),
+        };
+
+        Self {
+            kind: kind as i32,
+            path,
+            item: item.map(PathItem::from),
+        }
+    }
+}
+
+impl TryFrom<TreeEvent> for crate::TreeChange {
+    type Error = anyhow::Error;
+
+    fn try_from(value: TreeEvent) -> Result<Self, Self::Error> {
+        let kind = value.kind();
+        let item = value.item.map(crate::PathItem::try_from).transpose()?;
+
+        Ok(match (kind, item) {
+            (TreeEventKind::Created, Some(item)) => Self::Created(item),
+            (TreeEventKind::Modified, Some(item)) => Self::Modified(item),
+            (TreeEventKind::Deleted, _) => Self::Removed(PathBuf::from(value.path)),
+            (TreeEventKind::Renamed, Some(to)) => Self::Renamed {
+                from: PathBuf::from(value.path),
+                to,
+            },
+            (kind, _) => anyhow::bail!("Invalid tree event: {:?}", kind),
+        })
+    }
+}
+
+fn to_system_time(timestamp: Option<prost_types::Timestamp>) -> anyhow::Result<SystemTime> {
+    Ok(SystemTime::try_from(
+        timestamp.context("Missing timestamp")?,
+    )?)
+}
+
+///Paths are sent as strings so clients on other platforms can read them too
+fn path_to_string(path: &std::path::Path) -> String {
+    path.to_string_lossy().into_owned()
+}
@@ -0,0 +1,162 @@
+//! Mirrors a remote folder into a local folder file by file, files which are already identical locally are skipped
+
+use std::{
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use crate::{
+    auth::SessionClient,
+    download::{download_file, remote_version},
+    error::ServiceError,
+    hash_file,
+    messages::HostRequest,
+    ClientRequest, FileStruct, FolderItem, PathItem, ServerReply, DIRECTORY_PAGE_SIZE,
+};
+
+///What a mirror has done
+#[derive(Debug, Default)]
+pub struct MirrorReport {
+    ///The amount of files which have been downloaded
+    pub downloaded: u64,
+    ///The amount of files which were already identical locally
+    pub skipped: u64,
+    ///The amount of bytes which have been downloaded
+    pub bytes: u64,
+    ///The remote files which could not be mirrored, and why
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+///Recreates the remote folder with all of its subfolders inside of the destination, and downloads every file into it
+///
+///The folders which have already been listed are taken from the tree, the rest are listed now. A file which cannot be mirrored does not stop the others
+pub async fn mirror_folder(
+    client: &mut SessionClient,
+    folder: &FolderItem,
+    destination: &Path,
+) -> anyhow::Result<MirrorReport> {
+    let mut report = MirrorReport::default();
+
+    //Walk the tree without recursion, as async fns cannot call themselves directly
+    let mut pending = vec![(folder.clone(), destination.to_path_buf())];
+
+    while let Some((folder, local)) = pending.pop() {
+        tokio::fs::create_dir_all(&local).await?;
+
+        let entries = if folder.loaded {
+            folder.entries
+        } else {
+            list_directory(client, &folder.path).await?
+        };
+
+        for entry in entries {
+            //Only the name is used, so the server cannot make us write outside of the destination
+            let Some(name) = entry.get_path().file_name().map(PathBuf::from) else {
+                continue;
+            };
+
+            let local = local.join(name);
+
+            match entry {
+                PathItem::Folder(folder) => pending.push((folder, local)),
+                PathItem::File(file) => match mirror_file(client, &file, &local).await {
+                    Ok(Some(bytes)) => {
+                        report.downloaded += 1;
+                        report.bytes += bytes;
+                    }
+                    Ok(None) => report.skipped += 1,
+                    Err(err) => report.failed.push((file.path, format!("{:#}", err))),
+                },
+            }
+        }
+    }
+
+    Ok(report)
+}
+
+///Downloads the file unless the local one has the same size and digest, returns the amount of bytes downloaded
+async fn mirror_file(
+    client: &mut SessionClient,
+    file: &FileStruct,
+    local: &Path,
+) -> anyhow::Result<Option<u64>> {
+    let (version, sha256) = remote_version(client, &file.path).await?;
+
+    let downloaded = if is_identical(local, version.file_size, &sha256).await? {
+        None
+    } else {
+        Some(download_file(client, &file.path, local).await?)
+    };
+
+    //Skipped files get the remote modification time too
+    set_modified(local, version.file_modified).await?;
+
+    Ok(downloaded)
+}
+
+///Only hashes the local file if it has the same size as the remote one
+pub(crate) async fn is_identical(
+    local: &Path,
+    file_size: u64,
+    sha256: &str,
+) -> anyhow::Result<bool> {
+    match tokio::fs::metadata(local).await {
+        Ok(metadata) if metadata.is_file() && metadata.len() == file_size => {
+            let local = local.to_path_buf();
+
+            let local_sha256 = tokio::task::spawn_blocking(move || hash_file(&local)).await??;
+
+            Ok(local_sha256 == sha256)
+        }
+        _ => Ok(false),
+    }
+}
+
+pub(crate) async fn set_modified(path: &Path, modified: SystemTime) -> anyhow::Result<()> {
+    let path = path.to_path_buf();
+
+    tokio::task::spawn_blocking(move || {
+        std::fs::File::options()
+            .write(true)
+            .open(path)?
+            .set_modified(modified)
+    })
+    .await??;
+
+    Ok(())
+}
+
+///Asks for every entry of a remote folder, one page at a time
+pub async fn list_directory(
+    client: &mut SessionClient,
+    path: &Path,
+) -> anyhow::Result<Vec<PathItem>> {
+    let mut entries = Vec::new();
+
+    loop {
+        let request = ClientRequest::ListDirectory {
+            path: path.to_path_buf(),
+            offset: entries.len() as u64,
+            limit: DIRECTORY_PAGE_SIZE,
+        };
+
+        let reply = client
+            .server_provide(HostRequest::new(request))
+            .await
+            .map_err(ServiceError::from)?
+            .into_inner();
+
+        let listing = match reply.into_reply()? {
+            ServerReply::Directory(listing) => listing,
+            reply => anyhow::bail!("Unexpected reply: {:?}", reply),
+        };
+
+        let page_was_empty = listing.entries.is_empty();
+
+        entries.extend(listing.entries);
+
+        if page_was_empty || entries.len() as u64 >= listing.total {
+            return Ok(entries);
+        }
+    }
+}
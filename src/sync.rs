@@ -0,0 +1,457 @@
+//! One-way sync of a remote folder into a local folder, only the files which are new or have changed on the server are downloaded
+
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+use crate::{
+    auth::SessionClient,
+    download::{download_file, remote_version},
+    mirror::{is_identical, list_directory, set_modified},
+    FileStruct, PathItem,
+};
+
+///What is synced, and where to
+#[derive(serde::Deserialize, serde::Serialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct SyncJob {
+    ///The folder on the server
+    pub remote: PathBuf,
+    ///The local folder the remote one is synced into
+    pub local: PathBuf,
+    ///Only the files matching one of these are synced, every file is if it is empty
+    pub include: Vec<String>,
+    ///The files and folders matching one of these are not synced
+    pub exclude: Vec<String>,
+    ///Delete the local files which have been deleted on the server
+    pub delete: bool,
+    ///Only report what would be done
+    pub dry_run: bool,
+}
+
+///A change made to the local folder
+#[derive(serde::Serialize, Clone, Debug)]
+pub enum SyncAction {
+    ///The file is new or has changed on the server
+    Download {
+        remote: PathBuf,
+        local: PathBuf,
+        size: u64,
+    },
+    ///The file has been deleted on the server
+    Delete(PathBuf),
+    ///The folder has been deleted on the server
+    DeleteFolder(PathBuf),
+}
+
+impl SyncAction {
+    ///The local file or folder which is changed
+    pub fn local_path(&self) -> &Path {
+        match self {
+            SyncAction::Download { local, .. } => local,
+            SyncAction::Delete(path) | SyncAction::DeleteFolder(path) => path,
+        }
+    }
+}
+
+impl std::fmt::Display for SyncAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SyncAction::Download {
+                remote,
+                local,
+                size,
+            } => write!(
+                f,
+                "download {} -> {} ({} bytes)",
+                remote.display(),
+                local.display(),
+                size
+            ),
+            SyncAction::Delete(path) => write!(f, "delete {}", path.display()),
+            SyncAction::DeleteFolder(path) => write!(f, "delete folder {}", path.display()),
+        }
+    }
+}
+
+///What a sync has done, or would have done in a dry run
+#[derive(serde::Serialize, Debug, Default)]
+pub struct SyncReport {
+    pub actions: Vec<SyncAction>,
+    ///The amount of files which were already up to date
+    pub unchanged: u64,
+    ///The amount of bytes which have been downloaded
+    pub bytes: u64,
+    ///The actions which have failed, and why
+    pub failed: Vec<(PathBuf, String)>,
+    pub dry_run: bool,
+}
+
+///Decides which files are synced, the globs are matched against paths relative to the synced folder
+struct SyncFilter {
+    include: Option<GlobSet>,
+    exclude: GlobSet,
+}
+
+impl SyncFilter {
+    fn new(include: &[String], exclude: &[String]) -> anyhow::Result<Self> {
+        Ok(Self {
+            include: (!include.is_empty())
+                .then(|| glob_set(include))
+                .transpose()?,
+            exclude: glob_set(exclude)?,
+        })
+    }
+
+    fn includes_file(&self, relative: &Path) -> bool {
+        self.include
+            .as_ref()
+            .is_none_or(|include| include.is_match(relative))
+            && !self.exclude.is_match(relative)
+    }
+
+    ///Nothing inside of an excluded folder is synced
+    fn includes_folder(&self, relative: &Path) -> bool {
+        !self.exclude.is_match(relative)
+    }
+}
+
+fn glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+
+    Ok(builder.build()?)
+}
+
+///Compares the remote folder with the local one, then downloads the new and changed files, and deletes the removed ones if the job asks for it
+///
+///A file is up to date if it has the same size and modification time as the remote one, or the same size and digest
+pub async fn run_sync(client: &mut SessionClient, job: &SyncJob) -> anyhow::Result<SyncReport> {
+    let filter = SyncFilter::new(&job.include, &job.exclude)?;
+
+    let mut report = SyncReport {
+        dry_run: job.dry_run,
+        ..Default::default()
+    };
+
+    //The relative paths of everything on the server, what is not in here is deleted locally
+    let mut remote_paths = HashSet::new();
+
+    //Walk the tree without recursion, as async fns cannot call themselves directly
+    let mut pending = vec![(job.remote.clone(), PathBuf::new())];
+
+    while let Some((remote, relative)) = pending.pop() {
+        for entry in list_directory(client, &remote).await? {
+            //Only the name is used, so the server cannot make us write outside of the local folder
+            let Some(name) = entry.get_path().file_name().map(PathBuf::from) else {
+                continue;
+            };
+
+            let relative = relative.join(name);
+
+            match entry {
+                PathItem::Folder(folder) if filter.includes_folder(&relative) => {
+                    remote_paths.insert(relative.clone());
+
+                    pending.push((folder.path, relative));
+                }
+                PathItem::File(file) if filter.includes_file(&relative) => {
+                    remote_paths.insert(relative.clone());
+
+                    let local = job.local.join(&relative);
+
+                    match sync_file(client, &file, &local, job.dry_run).await {
+                        Ok(Some(action)) => {
+                            if let (SyncAction::Download { size, .. }, false) =
+                                (&action, job.dry_run)
+                            {
+                                report.bytes += size;
+                            }
+
+                            report.actions.push(action);
+                        }
+                        Ok(None) => report.unchanged += 1,
+                        Err(err) => report.failed.push((file.path, format!("{:#}", err))),
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    if job.delete {
+        let local = job.local.clone();
+
+        let removed =
+            tokio::task::spawn_blocking(move || removed_locally(&local, &remote_paths, &filter))
+                .await??;
+
+        for action in removed {
+            if !job.dry_run {
+                let result = match &action {
+                    SyncAction::Delete(path) => tokio::fs::remove_file(path).await,
+                    SyncAction::DeleteFolder(path) => match tokio::fs::remove_dir(path).await {
+                        //The folder still has files which are not synced
+                        Err(err) if err.kind() == std::io::ErrorKind::DirectoryNotEmpty => continue,
+                        result => result,
+                    },
+                    SyncAction::Download { .. } => Ok(()),
+                };
+
+                if let Err(err) = result {
+                    report
+                        .failed
+                        .push((action.local_path().to_path_buf(), err.to_string()));
+
+                    continue;
+                }
+            }
+
+            report.actions.push(action);
+        }
+    }
+
+    Ok(report)
+}
+
+///Downloads the file if it is not up to date, returns what has been done
+async fn sync_file(
+    client: &mut SessionClient,
+    file: &FileStruct,
+    local: &Path,
+    dry_run: bool,
+) -> anyhow::Result<Option<SyncAction>> {
+    let metadata = file.metadata.as_ref();
+
+    let local_metadata = tokio::fs::metadata(local).await.ok();
+
+    //Synced files get the remote modification time, so most files are not hashed again
+    let unchanged = match (metadata, &local_metadata) {
+        (Some(metadata), Some(local_metadata)) => {
+            local_metadata.is_file()
+                && local_metadata.len() == metadata.file_size
+                && local_metadata.modified().ok() == Some(metadata.file_modified)
+        }
+        _ => false,
+    };
+
+    if unchanged {
+        return Ok(None);
+    }
+
+    let (version, sha256) = remote_version(client, &file.path).await?;
+
+    if is_identical(local, version.file_size, &sha256).await? {
+        if !dry_run {
+            set_modified(local, version.file_modified).await?;
+        }
+
+        return Ok(None);
+    }
+
+    let action = SyncAction::Download {
+        remote: file.path.clone(),
+        local: local.to_path_buf(),
+        size: version.file_size,
+    };
+
+    if dry_run {
+        return Ok(Some(action));
+    }
+
+    if let Some(parent) = local.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+
+    download_file(client, &file.path, local).await?;
+
+    set_modified(local, version.file_modified).await?;
+
+    Ok(Some(action))
+}
+
+///Finds the local files and folders which are synced but are not on the server anymore, folders come after their contents
+fn removed_locally(
+    local: &Path,
+    remote_paths: &HashSet<PathBuf>,
+    filter: &SyncFilter,
+) -> std::io::Result<Vec<SyncAction>> {
+    let mut files = Vec::new();
+    let mut folders = Vec::new();
+
+    let mut pending = vec![PathBuf::new()];
+
+    while let Some(relative) = pending.pop() {
+        let entries = match std::fs::read_dir(local.join(&relative)) {
+            Ok(entries) => entries,
+            //Nothing has been synced yet
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(err) => return Err(err),
+        };
+
+        for entry in entries {
+            let entry = entry?;
+            let relative = relative.join(entry.file_name());
+
+            if entry.file_type()?.is_dir() {
+                if !filter.includes_folder(&relative) {
+                    continue;
+                }
+
+                if !remote_paths.contains(&relative) {
+                    folders.push(local.join(&relative));
+                }
+
+                pending.push(relative);
+            } else if filter.includes_file(&relative)
+                && !remote_paths.contains(&relative)
+                && !is_partial_download(&relative, remote_paths)
+            {
+                files.push(SyncAction::Delete(local.join(&relative)));
+            }
+        }
+    }
+
+    //The deepest folders have to be deleted first
+    folders.sort_by(|a, b| b.cmp(a));
+
+    files.extend(folders.into_iter().map(SyncAction::DeleteFolder));
+
+    Ok(files)
+}
+
+///Interrupted downloads of files which are still on the server are kept, so they can be resumed
+fn is_partial_download(path: &Path, remote_paths: &HashSet<PathBuf>) -> bool {
+    let name = path.as_os_str().to_string_lossy();
+
+    [".part.version", ".part"].iter().any(|suffix| {
+        name.strip_suffix(suffix)
+            .is_some_and(|file| remote_paths.contains(Path::new(file)))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFolder;
+
+    ///What would be removed from the local folder, by its relative paths
+    fn removed(
+        local: &TempFolder,
+        remote: &[&str],
+        include: &[&str],
+        exclude: &[&str],
+    ) -> Vec<String> {
+        let strings = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| pattern.to_string())
+                .collect::<Vec<_>>()
+        };
+        let filter = SyncFilter::new(&strings(include), &strings(exclude)).unwrap();
+        let remote_paths = remote.iter().map(PathBuf::from).collect();
+
+        removed_locally(local.path(), &remote_paths, &filter)
+            .unwrap()
+            .iter()
+            .map(|action| match action {
+                SyncAction::Delete(path) => format!("file {}", local.relative(path)),
+                SyncAction::DeleteFolder(path) => format!("folder {}", local.relative(path)),
+                SyncAction::Download { .. } => unreachable!(),
+            })
+            .collect()
+    }
+
+    fn sorted(mut removed: Vec<String>) -> Vec<String> {
+        removed.sort();
+        removed
+    }
+
+    #[test]
+    fn removes_what_is_not_on_the_server_anymore() {
+        let local = TempFolder::with_files(
+            "sync-removed",
+            &["kept.txt", "gone.txt", "old/deep/stale.txt"],
+        );
+
+        let removed = removed(&local, &["kept.txt"], &[], &[]);
+
+        assert_eq!(
+            sorted(removed.clone()),
+            vec![
+                "file gone.txt",
+                "file old/deep/stale.txt",
+                "folder old",
+                "folder old/deep"
+            ]
+        );
+        //Files come first, then folders from the deepest one up
+        assert_eq!(&removed[2..], ["folder old/deep", "folder old"]);
+    }
+
+    #[test]
+    fn only_removes_included_files() {
+        let local = TempFolder::with_files(
+            "sync-include",
+            &["gone.txt", "notes.md", "sub/gone.txt", "sub/notes.md"],
+        );
+
+        let removed = removed(&local, &["sub"], &["*.txt"], &[]);
+
+        assert_eq!(sorted(removed), vec!["file gone.txt", "file sub/gone.txt"]);
+    }
+
+    #[test]
+    fn never_removes_excluded_files_or_folders() {
+        let local = TempFolder::with_files(
+            "sync-exclude",
+            &[
+                "gone.txt",
+                "build.tmp",
+                "cache/gone.txt",
+                "cache/deep/gone.txt",
+            ],
+        );
+
+        let removed = removed(&local, &[], &[], &["*.tmp", "cache"]);
+
+        assert_eq!(removed, vec!["file gone.txt"]);
+    }
+
+    #[test]
+    fn keeps_interrupted_downloads_of_files_which_are_still_on_the_server() {
+        let local = TempFolder::with_files(
+            "sync-partial",
+            &[
+                "big.bin",
+                "big.bin.part",
+                "big.bin.part.version",
+                "removed.bin.part",
+                "removed.bin.part.version",
+            ],
+        );
+
+        let removed = removed(&local, &["big.bin"], &[], &[]);
+
+        assert_eq!(
+            sorted(removed),
+            vec!["file removed.bin.part", "file removed.bin.part.version"]
+        );
+    }
+
+    #[test]
+    fn a_missing_local_folder_has_nothing_to_remove() {
+        let folder = TempFolder::new("sync-missing");
+        let filter = SyncFilter::new(&[], &[]).unwrap();
+
+        let removed = removed_locally(&folder.join("not synced yet"), &HashSet::new(), &filter);
+
+        assert!(removed.unwrap().is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+//! Helpers for the tests of the library and of the binaries, which cannot see each other's test modules
+
+use std::path::{Path, PathBuf};
+
+///A folder of its own in the temporary directory, which is removed again when it is dropped
+pub struct TempFolder(PathBuf);
+
+impl TempFolder {
+    ///The name has to be unique among the tests, as they run at the same time
+    pub fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("fh-{}-{}", name, std::process::id()));
+
+        let _ = std::fs::remove_dir_all(&path);
+        std::fs::create_dir_all(&path).expect("The temporary folder could not be created");
+
+        Self(path)
+    }
+
+    ///A folder with the files, each of them contains its own relative path
+    pub fn with_files(name: &str, files: &[&str]) -> Self {
+        let folder = Self::new(name);
+
+        for file in files {
+            folder.write(file, file.as_bytes());
+        }
+
+        folder
+    }
+
+    ///Writes the file, the folders it is in are created
+    pub fn write(&self, relative: &str, contents: &[u8]) -> PathBuf {
+        let path = self.0.join(relative);
+
+        std::fs::create_dir_all(path.parent().unwrap()).expect("The folder could not be created");
+        std::fs::write(&path, contents).expect("The file could not be written");
+
+        path
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, relative: impl AsRef<Path>) -> PathBuf {
+        self.0.join(relative)
+    }
+
+    ///The path relative to the folder, with forward slashes on every platform
+    pub fn relative(&self, path: &Path) -> String {
+        path.strip_prefix(&self.0)
+            .expect("The path is not inside of the folder")
+            .to_string_lossy()
+            .replace('\\', "/")
+    }
+}
+
+impl Drop for TempFolder {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.0);
+    }
+}
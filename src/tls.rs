@@ -0,0 +1,236 @@
+//! Certificate handling for the encrypted connection between the server and the client
+
+use std::{
+    fs,
+    io::BufReader,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use anyhow::Context;
+use sha2::{Digest, Sha256};
+use tokio::net::TcpStream;
+use tokio_rustls::{
+    rustls::{
+        client::{ServerCertVerified, ServerCertVerifier},
+        Certificate, ClientConfig, ServerName,
+    },
+    TlsConnector,
+};
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint, Identity, Uri};
+
+///The certificate and key the server identifies itself with
+#[derive(Clone)]
+pub struct ServerIdentity {
+    pub identity: Identity,
+    ///SHA-256 fingerprint of the certificate, clients use this to pin the server
+    pub fingerprint: String,
+}
+
+impl ServerIdentity {
+    ///Loads the certificate and the key from the disk, or generates a self-signed pair if they do not exist yet
+    pub fn load_or_generate(cert_path: &Path, key_path: &Path) -> anyhow::Result<Self> {
+        if !(cert_path.exists() && key_path.exists()) {
+            let certificate = rcgen::generate_simple_self_signed(vec!["localhost".to_string()])?;
+
+            if let Some(parent) = cert_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            if let Some(parent) = key_path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+
+            //A key without its certificate is replaced, the new one is created so only we can read it
+            if key_path.exists() {
+                fs::remove_file(key_path)?;
+            }
+
+            fs::write(cert_path, certificate.serialize_pem()?)?;
+            crate::write_private_file(key_path, certificate.serialize_private_key_pem().as_bytes())
+                .with_context(|| format!("Failed to write the private key {:?}", key_path))?;
+        }
+
+        let cert_pem = fs::read(cert_path)?;
+        let key_pem = fs::read(key_path)?;
+
+        //The fingerprint is calculated from the first certificate of the chain
+        let cert_der = rustls_pemfile::certs(&mut BufReader::new(cert_pem.as_slice()))?
+            .into_iter()
+            .next()
+            .with_context(|| format!("No certificate found in {:?}", cert_path))?;
+
+        Ok(Self {
+            identity: Identity::from_pem(cert_pem, key_pem),
+            fingerprint: fingerprint(&cert_der),
+        })
+    }
+}
+
+///Formats the SHA-256 hash of a DER encoded certificate like `AB:CD:...`
+pub fn fingerprint(cert_der: &[u8]) -> String {
+    Sha256::digest(cert_der)
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<String>>()
+        .join(":")
+}
+
+///How the client decides whether it trusts the server it is connecting to
+#[derive(Clone, Debug)]
+pub enum ServerVerification {
+    ///The server's certificate has to be signed by this (PEM encoded) certificate authority
+    CertificateAuthority(Vec<u8>),
+    ///The server's certificate has to have this fingerprint, any certificate is accepted if we have never seen the server before (trust on first use)
+    Fingerprint(Option<String>),
+}
+
+///Opens an encrypted channel to the server, returns the fingerprint of the server's certificate if it has been checked by pinning
+pub async fn connect_channel(
+    address: &str,
+    verification: ServerVerification,
+) -> anyhow::Result<(Channel, Option<String>)> {
+    match verification {
+        ServerVerification::CertificateAuthority(ca_pem) => {
+            let endpoint = Endpoint::from_shared(format!("https://{}", address))?;
+
+            let uri: Uri = endpoint.uri().clone();
+
+            let host = uri
+                .host()
+                .context("Missing host")?
+                .trim_matches(|c| c == '[' || c == ']')
+                .to_string();
+
+            let channel = endpoint
+                .tls_config(
+                    ClientTlsConfig::new()
+                        .ca_certificate(tonic::transport::Certificate::from_pem(ca_pem))
+                        .domain_name(host),
+                )?
+                .connect()
+                .await?;
+
+            Ok((channel, None))
+        }
+        ServerVerification::Fingerprint(expected) => {
+            let verifier = Arc::new(FingerprintVerifier {
+                expected: expected.clone(),
+                seen: Mutex::new(None),
+            });
+
+            let mut config = ClientConfig::builder()
+                .with_safe_defaults()
+                .with_custom_certificate_verifier(verifier.clone())
+                .with_no_client_auth();
+
+            config.alpn_protocols = vec![b"h2".to_vec()];
+
+            let connector = TlsConnector::from(Arc::new(config));
+
+            //tonic refuses https uris without its own tls config, the connection is still encrypted by our connector
+            let endpoint = Endpoint::from_shared(format!("http://{}", address))?;
+
+            let result = endpoint
+                .connect_with_connector(tower::service_fn(move |uri: Uri| {
+                    let connector = connector.clone();
+
+                    async move {
+                        let authority = uri.authority().context("Missing address")?.clone();
+
+                        let host = authority.host().trim_matches(|c| c == '[' || c == ']');
+
+                        //The certificate's name is not checked, but rustls needs one for the handshake
+                        let server_name = ServerName::try_from(host)
+                            .or_else(|_| ServerName::try_from("localhost"))?;
+
+                        let stream = TcpStream::connect(authority.as_str()).await?;
+
+                        anyhow::Ok(connector.connect(server_name, stream).await?)
+                    }
+                }))
+                .await;
+
+            let seen = verifier.seen.lock().unwrap().clone();
+
+            match result {
+                Ok(channel) => Ok((channel, seen)),
+                //Give a clear reason if the connection failed because of the pinned certificate
+                Err(_) if seen.is_some() && expected.is_some() && seen != expected => {
+                    anyhow::bail!(
+                        "The server's certificate has changed!\nExpected: {}\nGot: {}",
+                        expected.unwrap_or_default(),
+                        seen.unwrap_or_default()
+                    )
+                }
+                Err(err) => Err(err.into()),
+            }
+        }
+    }
+}
+
+///Accepts the server's certificate if its fingerprint matches the pinned one, the signatures of the handshake are still verified by rustls
+struct FingerprintVerifier {
+    expected: Option<String>,
+    ///The fingerprint of the certificate the server has presented
+    seen: Mutex<Option<String>>,
+}
+
+impl ServerCertVerifier for FingerprintVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        let fingerprint = fingerprint(&end_entity.0);
+
+        *self.seen.lock().unwrap() = Some(fingerprint.clone());
+
+        match &self.expected {
+            Some(expected) if *expected != fingerprint => {
+                Err(tokio_rustls::rustls::Error::General(
+                    "Certificate fingerprint mismatch".to_string(),
+                ))
+            }
+            _ => Ok(ServerCertVerified::assertion()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempFolder;
+
+    #[test]
+    fn generates_an_identity_once() {
+        let folder = TempFolder::new("tls-generate");
+        let (cert, key) = (folder.join("tls/cert.pem"), folder.join("tls/key.pem"));
+
+        let generated = ServerIdentity::load_or_generate(&cert, &key).unwrap();
+        let loaded = ServerIdentity::load_or_generate(&cert, &key).unwrap();
+
+        assert_eq!(generated.fingerprint, loaded.fingerprint);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn only_the_owner_can_read_the_generated_key() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let folder = TempFolder::new("tls-key-mode");
+        let (cert, key) = (folder.join("cert.pem"), folder.join("key.pem"));
+
+        //A key without its certificate is replaced
+        folder.write("key.pem", b"stale");
+
+        ServerIdentity::load_or_generate(&cert, &key).unwrap();
+
+        let mode = fs::metadata(&key).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+    }
+}